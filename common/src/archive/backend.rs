@@ -0,0 +1,253 @@
+use super::protocol;
+use crate::{rdp, util};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// A pattern containing a '/' is matched against the whole path,
+// otherwise against the file name
+fn matches(pattern: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        util::glob_match(pattern, path)
+    } else {
+        path.rsplit('/')
+            .next()
+            .is_some_and(|name| util::glob_match(pattern, name))
+    }
+}
+
+// Anything below an excluded directory is excluded as well
+fn is_excluded(filter: &protocol::Filter, path: &str) -> bool {
+    path.match_indices('/')
+        .map(|(i, _)| &path[..i])
+        .chain([path])
+        .any(|path| filter.exclude.iter().any(|pattern| matches(pattern, path)))
+}
+
+fn is_included(filter: &protocol::Filter, path: &str) -> bool {
+    filter.include.is_empty() || filter.include.iter().any(|pattern| matches(pattern, path))
+}
+
+// Returns the number of files added; unreadable entries are skipped
+fn append_dir<W>(
+    builder: &mut tar::Builder<W>,
+    filter: &protocol::Filter,
+    dir: &path::Path,
+    prefix: &str,
+) -> Result<u64, io::Error>
+where
+    W: io::Write,
+{
+    let entries = match fs::read_dir(dir) {
+        Err(e) => {
+            crate::warn!("skipping {}: {e}", dir.display());
+            return Ok(0);
+        }
+        Ok(entries) => entries,
+    };
+
+    let mut count = 0;
+
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let name = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+
+        if is_excluded(filter, &name) {
+            continue;
+        }
+
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        if file_type.is_dir() {
+            // with include patterns, only directories of included
+            // files are created upon extraction
+            if filter.include.is_empty() {
+                builder.append_path_with_name(entry.path(), &name)?;
+            }
+            count += append_dir(builder, filter, &entry.path(), &name)?;
+        } else if is_included(filter, &name) {
+            if file_type.is_file() {
+                match fs::File::open(entry.path()) {
+                    Err(e) => {
+                        crate::warn!("skipping {}: {e}", entry.path().display());
+                        continue;
+                    }
+                    Ok(mut file) => builder.append_file(&name, &mut file)?,
+                }
+            } else {
+                builder.append_path_with_name(entry.path(), &name)?;
+            }
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+// Paths inside the archive are relative to the directory; a single
+// file is archived under its name
+fn archive<W>(
+    writer: W,
+    path: &path::Path,
+    metadata: &fs::Metadata,
+    filter: &protocol::Filter,
+) -> Result<u64, io::Error>
+where
+    W: io::Write,
+{
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+
+    let count = if metadata.is_dir() {
+        append_dir(&mut builder, filter, path, "")?
+    } else {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        builder.append_file(name, &mut fs::File::open(path)?)?;
+        1
+    };
+
+    builder.into_inner()?.flush()?;
+
+    Ok(count)
+}
+
+fn get(
+    stream: &mut rdp::RdpStream<'_>,
+    path: &str,
+    gzip: bool,
+    filter: &protocol::Filter,
+) -> Result<(), io::Error> {
+    let path = path::Path::new(path);
+
+    let metadata = match fs::metadata(path) {
+        Err(e) => {
+            crate::warn!("failed to access {}: {e}", path.display());
+            return protocol::Response::Error(e.to_string()).send(stream);
+        }
+        Ok(metadata) => metadata,
+    };
+
+    protocol::Response::Ready.send(stream)?;
+
+    crate::info!("sending {}", path.display());
+
+    let count = if gzip {
+        let mut encoder = flate2::write::GzEncoder::new(stream, flate2::Compression::default());
+        let count = archive(&mut encoder, path, &metadata, filter)?;
+        encoder.finish()?.flush()?;
+        count
+    } else {
+        archive(stream, path, &metadata, filter)?
+    };
+
+    crate::info!("sent {count} file(s) of {}", path.display());
+
+    Ok(())
+}
+
+// Returns the number of files extracted; entries which would be
+// extracted outside of the directory are skipped
+fn unpack<R>(reader: R, dest: &path::Path, filter: &protocol::Filter) -> Result<u64, io::Error>
+where
+    R: io::Read,
+{
+    let mut archive = tar::Archive::new(reader);
+
+    let mut count = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        let path = entry.path()?.to_string_lossy().to_string();
+        let path = path.trim_start_matches("./").trim_end_matches('/');
+        let is_dir = entry.header().entry_type().is_dir();
+
+        if !path.is_empty()
+            && (is_excluded(filter, path)
+                || (is_dir && !filter.include.is_empty())
+                || (!is_dir && !is_included(filter, path)))
+        {
+            continue;
+        }
+
+        if !entry.unpack_in(dest)? {
+            crate::warn!("skipping {path:?} outside of {}", dest.display());
+        } else if !is_dir {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+// The archive is uncompressed on the fly when it starts as a gzip one
+fn extract<R>(mut reader: R, dest: &path::Path, filter: &protocol::Filter) -> Result<u64, io::Error>
+where
+    R: io::Read,
+{
+    let mut magic = [0u8; GZIP_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    let reader = io::Cursor::new(magic).chain(reader);
+
+    if magic == GZIP_MAGIC {
+        unpack(flate2::read::GzDecoder::new(reader), dest, filter)
+    } else {
+        unpack(reader, dest, filter)
+    }
+}
+
+fn put(
+    stream: &mut rdp::RdpStream<'_>,
+    path: &str,
+    filter: &protocol::Filter,
+) -> Result<(), io::Error> {
+    let dest = path::Path::new(path);
+
+    if let Err(e) = fs::create_dir_all(dest) {
+        crate::warn!("failed to create {}: {e}", dest.display());
+        return protocol::Response::Error(e.to_string()).send(stream);
+    }
+
+    protocol::Response::Ready.send(stream)?;
+
+    crate::info!("extracting to {}", dest.display());
+
+    // the end of the archive is answered without waiting for the end
+    // of the upload (e.g. padding)
+    match extract(&mut *stream, dest, filter) {
+        Err(e) => {
+            crate::warn!("failed to extract to {}: {e}", dest.display());
+            protocol::Response::Error(e.to_string()).send(stream)
+        }
+        Ok(count) => {
+            crate::info!("extracted {count} file(s) to {}", dest.display());
+            protocol::Response::Done(count).send(stream)
+        }
+    }
+}
+
+pub fn handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
+    crate::debug!("starting");
+
+    let command = protocol::Command::receive(&mut stream)?;
+
+    crate::debug!("received {command:?}");
+
+    match command {
+        protocol::Command::Get { path, gzip, filter } => get(&mut stream, &path, gzip, &filter),
+        protocol::Command::Put { path, filter } => put(&mut stream, &path, &filter),
+    }
+}
@@ -0,0 +1,152 @@
+use super::protocol;
+use crate::{api, channel, frontend, rdp, service};
+use std::{
+    io::{self, BufRead, Write},
+    net, thread,
+};
+
+const USAGE: &str = "usage: get [-z] [-i <glob>]... [-x <glob>]... <directory>\r\n       \
+                     put [-i <glob>]... [-x <glob>]... <directory>\r\n";
+
+// "get" streams a tar (gzip compressed with -z) of a remote directory,
+// "put" extracts the tar following the request line into a remote
+// directory; -i and -x give include and exclude glob patterns
+fn parse_request(line: &str) -> Option<protocol::Command> {
+    let (verb, mut args) = line.trim().split_once(' ')?;
+
+    let mut gzip = false;
+    let mut filter = protocol::Filter::default();
+
+    loop {
+        args = args.trim_start();
+        let (option, rest) = args.split_once(' ').unwrap_or((args, ""));
+        match option {
+            "-z" => gzip = true,
+            "-i" | "-x" => {
+                let rest = rest.trim_start();
+                let (pattern, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                if pattern.is_empty() {
+                    return None;
+                }
+                if option == "-i" {
+                    filter.include.push(pattern.to_string());
+                } else {
+                    filter.exclude.push(pattern.to_string());
+                }
+                args = rest;
+                continue;
+            }
+            "--" => {
+                args = rest.trim_start();
+                break;
+            }
+            _ => break,
+        }
+        args = rest;
+    }
+
+    if args.is_empty() {
+        return None;
+    }
+    let path = args.to_string();
+
+    match verb.to_lowercase().as_str() {
+        "get" => Some(protocol::Command::Get { path, gzip, filter }),
+        "put" if !gzip => Some(protocol::Command::Put { path, filter }),
+        _ => None,
+    }
+}
+
+// Sends the rest of the upload while waiting for the result of the
+// extraction, which may come first
+fn upload(
+    client: &mut net::TcpStream,
+    client_read: &mut io::BufReader<net::TcpStream>,
+    rdp: rdp::RdpStream<'_>,
+) -> Result<(), io::Error> {
+    let (mut rdp_read, mut rdp_write) = rdp.split();
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} upload",
+            service::Kind::Frontend,
+            super::SERVICE
+        ));
+        thread.spawn_scoped(scope, move || {
+            if let Err(e) = service::stream_copy(client_read, &mut rdp_write, true) {
+                crate::debug!("error: {e}");
+            }
+        })?;
+
+        let res = match protocol::Response::receive(&mut rdp_read) {
+            Ok(protocol::Response::Done(count)) => {
+                client.write_all(format!("ok: {count} file(s) extracted\r\n").as_bytes())
+            }
+            Ok(protocol::Response::Error(msg)) => {
+                client.write_all(format!("error: {msg}\r\n").as_bytes())
+            }
+            Ok(protocol::Response::Ready) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected response",
+            )),
+            Err(e) => Err(e),
+        };
+
+        // stops the upload
+        let _ = client.shutdown(net::Shutdown::Both);
+
+        res
+    })
+}
+
+pub fn tcp_handler<'a>(
+    _server: &frontend::FrontendTcpServer,
+    _scope: &'a thread::Scope<'a, '_>,
+    mut client: net::TcpStream,
+    channel: &'a channel::Channel,
+) -> Result<(), api::Error> {
+    // the upload starts right after the request line, maybe already
+    // in the buffer
+    let mut client_read = io::BufReader::new(client.try_clone()?);
+
+    let mut line = String::new();
+    client_read.read_line(&mut line)?;
+
+    let Some(command) = parse_request(&line) else {
+        client.write_all(USAGE.as_bytes())?;
+        return Ok(());
+    };
+
+    crate::debug!("{command:?}");
+
+    let is_upload = matches!(command, protocol::Command::Put { .. });
+
+    let mut rdp = channel.connect(&super::SERVICE)?;
+    command.send(&mut rdp)?;
+
+    match protocol::Response::receive(&mut rdp)? {
+        protocol::Response::Error(msg) => {
+            crate::warn!("archive error: {msg}");
+            client.write_all(format!("error: {msg}\r\n").as_bytes())?;
+        }
+        protocol::Response::Ready if is_upload => {
+            upload(&mut client, &mut client_read, rdp)?;
+        }
+        protocol::Response::Ready => {
+            let mut client = io::BufWriter::new(&client);
+            service::stream_copy(&mut rdp, &mut client, false)?;
+        }
+        protocol::Response::Done(_) => {
+            return Err(api::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected response",
+            )));
+        }
+    }
+
+    let _ = client.shutdown(net::Shutdown::Both);
+
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use crate::util;
+use std::io;
+
+const ID_COMMAND_GET: u8 = 0x00;
+const ID_COMMAND_PUT: u8 = 0x01;
+
+// Glob patterns applied to the paths inside the archive
+#[derive(Debug, Default)]
+pub struct Filter {
+    // only files matching one of them, when any
+    pub include: Vec<String>,
+    // neither files nor directories matching one of them
+    pub exclude: Vec<String>,
+}
+
+impl Filter {
+    #[cfg(feature = "frontend")]
+    fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        for patterns in [&self.include, &self.exclude] {
+            let count = u16::try_from(patterns.len())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+            stream.write_all(&count.to_le_bytes())?;
+            for pattern in patterns {
+                util::serialize_string(stream, pattern)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "backend")]
+    fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut patterns = || {
+            let mut count = [0u8; 2];
+            stream.read_exact(&mut count)?;
+            (0..u16::from_le_bytes(count))
+                .map(|_| util::deserialize_string(stream))
+                .collect::<Result<Vec<String>, io::Error>>()
+        };
+
+        let include = patterns()?;
+        let exclude = patterns()?;
+
+        Ok(Self { include, exclude })
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    // tar of a remote directory, gzip compressed if requested
+    Get {
+        path: String,
+        gzip: bool,
+        filter: Filter,
+    },
+    // extraction of a tar, compressed or not, into a remote directory
+    Put {
+        path: String,
+        filter: Filter,
+    },
+}
+
+impl Command {
+    #[cfg(feature = "frontend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::Get { path, gzip, filter } => {
+                stream.write_all(&[ID_COMMAND_GET])?;
+                util::serialize_string(stream, path)?;
+                stream.write_all(&[u8::from(*gzip)])?;
+                filter.send(stream)?;
+            }
+            Self::Put { path, filter } => {
+                stream.write_all(&[ID_COMMAND_PUT])?;
+                util::serialize_string(stream, path)?;
+                filter.send(stream)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "backend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            ID_COMMAND_GET => {
+                let path = util::deserialize_string(stream)?;
+                let mut gzip = [0u8; 1];
+                stream.read_exact(&mut gzip)?;
+                let filter = Filter::receive(stream)?;
+                Ok(Self::Get {
+                    path,
+                    gzip: gzip[0] != 0,
+                    filter,
+                })
+            }
+            ID_COMMAND_PUT => {
+                let path = util::deserialize_string(stream)?;
+                let filter = Filter::receive(stream)?;
+                Ok(Self::Put { path, filter })
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid command",
+            )),
+        }
+    }
+}
+
+const ID_RESPONSE_READY: u8 = 0x00;
+const ID_RESPONSE_DONE: u8 = 0x01;
+const ID_RESPONSE_ERROR: u8 = 0x02;
+
+#[derive(Debug)]
+pub enum Response {
+    // the archive can be streamed
+    Ready,
+    // number of files extracted
+    Done(u64),
+    Error(String),
+}
+
+impl Response {
+    #[cfg(feature = "backend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::Ready => stream.write_all(&[ID_RESPONSE_READY])?,
+            Self::Done(count) => {
+                stream.write_all(&[ID_RESPONSE_DONE])?;
+                stream.write_all(&count.to_le_bytes())?;
+            }
+            Self::Error(msg) => {
+                stream.write_all(&[ID_RESPONSE_ERROR])?;
+                util::serialize_string(stream, msg)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            ID_RESPONSE_READY => Ok(Self::Ready),
+            ID_RESPONSE_DONE => {
+                let mut count = [0u8; 8];
+                stream.read_exact(&mut count)?;
+                Ok(Self::Done(u64::from_le_bytes(count)))
+            }
+            ID_RESPONSE_ERROR => Ok(Self::Error(util::deserialize_string(stream)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid response",
+            )),
+        }
+    }
+}
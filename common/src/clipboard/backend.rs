@@ -1,65 +1,224 @@
-use super::protocol;
+use super::{changes, content::Content, image, protocol};
 use crate::rdp;
+#[cfg(feature = "log")]
+use crate::service;
 use copyrs::Clipboard;
-use std::{borrow, io};
+use std::{borrow, io, thread, time};
 
-pub fn handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
+// Watched clipboards are polled
+const POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// Whether the clipboard is watched, with the hash of the last content
+// seen
+#[derive(Default)]
+struct Watch {
+    // set while watched
+    changes: Option<changes::Changes>,
+    last: Option<u64>,
+}
+
+impl Watch {
+    const fn enabled(&self) -> bool {
+        self.changes.is_some()
+    }
+
+    // Written contents are not notified back, the hash being taken from
+    // the clipboard which may not give exactly what was written
+    fn rebase(&mut self) {
+        if self.enabled() {
+            self.last = Content::current().map(|content| content.digest());
+        }
+    }
+}
+
+// Notifies the content of the clipboard if it changed since the last
+// poll, an empty or unreadable clipboard being ignored
+fn poll<W>(stream: &mut W, watch: &mut Watch) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    if !watch
+        .changes
+        .as_mut()
+        .is_some_and(changes::Changes::changed)
+    {
+        return Ok(());
+    }
+    let Some(content) = Content::current() else {
+        return Ok(());
+    };
+    let digest = content.digest();
+    if watch.last.replace(digest) != Some(digest) {
+        match content {
+            Content::Text(text) => {
+                crate::debug!("text changed");
+                protocol::Response::TextChanged(text).send(stream)?;
+            }
+            Content::Image(png) => {
+                crate::debug!("image changed ({} bytes)", png.len());
+                protocol::Response::ImageChanged(u64::try_from(png.len()).unwrap_or(u64::MAX))
+                    .send(stream)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn handler(stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
     crate::debug!("starting");
 
-    loop {
-        let cmd = protocol::Command::receive(&mut stream)?;
+    #[cfg(feature = "log")]
+    let client_id = stream.client_id();
 
-        match cmd {
-            protocol::Command::Read => {
-                crate::debug!("read");
+    let (mut stream_read, stream_write) = stream.split();
 
-                match copyrs::clipboard() {
-                    Err(e) => {
-                        crate::error!("failed to get clipboard: {e}");
-                        protocol::Response::Failed.send(&mut stream)?;
+    // commands are received by a dedicated thread for the clipboard to
+    // be polled meanwhile when watched
+    let (commands_send, commands) = crossbeam_channel::bounded(1);
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} {client_id:x} commands",
+            service::Kind::Backend,
+            super::SERVICE,
+        ));
+        thread
+            .spawn_scoped(scope, move || {
+                loop {
+                    let command = protocol::Command::receive(&mut stream_read);
+                    let failed = command.is_err();
+                    if commands_send.send(command).is_err() || failed {
+                        break;
                     }
-                    Ok(clipboard) => match clipboard.get_content() {
-                        Err(e) => {
-                            crate::error!("failed to get clipboard content: {e}");
-                            protocol::Response::Failed.send(&mut stream)?;
-                        }
-                        Ok(content) => match content.kind {
-                            copyrs::ClipboardContentKind::Image => {
-                                crate::error!("clipboard contrent is an image, not text");
-                                protocol::Response::Failed.send(&mut stream)?;
-                            }
-                            copyrs::ClipboardContentKind::Text => {
-                                let text = String::from_utf8_lossy(&content.data).to_string();
-                                protocol::Response::Text(text).send(&mut stream)?;
-                            }
-                        },
-                    },
                 }
-            }
+            })
+            .unwrap();
 
-            protocol::Command::WriteText(value) => {
-                crate::debug!("write_text {value:?}");
+        // dropped on return, closing the stream for the thread above to stop
+        let mut stream_write = stream_write;
+
+        let mut watch = Watch::default();
+
+        loop {
+            let command = if watch.enabled() {
+                match commands.recv_timeout(POLL_INTERVAL) {
+                    Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                        poll(&mut stream_write, &mut watch)?;
+                        continue;
+                    }
+                    Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return Ok(()),
+                    Ok(command) => command,
+                }
+            } else {
+                let Ok(command) = commands.recv() else {
+                    return Ok(());
+                };
+                command
+            };
+
+            handle(command?, &mut stream_write, &mut watch)?;
+        }
+    })
+}
 
-                match copyrs::clipboard() {
+fn handle<W>(command: protocol::Command, stream: &mut W, watch: &mut Watch) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    match command {
+        protocol::Command::Read => {
+            crate::debug!("read");
+
+            match copyrs::clipboard() {
+                Err(e) => {
+                    crate::error!("failed to get clipboard: {e}");
+                    protocol::Response::Failed.send(stream)?;
+                }
+                Ok(clipboard) => match clipboard.get_content() {
                     Err(e) => {
-                        crate::error!("failed to get clipboard: {e}");
-                        protocol::Response::Failed.send(&mut stream)?;
+                        crate::error!("failed to get clipboard content: {e}");
+                        protocol::Response::Failed.send(stream)?;
                     }
-                    Ok(mut clipboard) => {
-                        let value = borrow::Cow::from(value.as_bytes());
-
-                        match clipboard.set_content(value, copyrs::ClipboardContentKind::Text) {
-                            Err(e) => {
-                                crate::error!("failed to set clipboard: {e}");
-                                protocol::Response::Failed.send(&mut stream)?;
-                            }
-                            Ok(()) => {
-                                protocol::Response::WriteDone.send(&mut stream)?;
-                            }
+                    Ok(content) => match content.kind {
+                        copyrs::ClipboardContentKind::Image => {
+                            crate::error!("clipboard contrent is an image, not text");
+                            protocol::Response::Failed.send(stream)?;
                         }
+                        copyrs::ClipboardContentKind::Text => {
+                            let text = String::from_utf8_lossy(&content.data).to_string();
+                            protocol::Response::Text(text).send(stream)?;
+                        }
+                    },
+                },
+            }
+        }
+
+        protocol::Command::WriteText(value) => {
+            crate::debug!("write_text {value:?}");
+
+            match copyrs::clipboard() {
+                Err(e) => {
+                    crate::error!("failed to get clipboard: {e}");
+                    protocol::Response::Failed.send(stream)?;
+                }
+                Ok(mut clipboard) => {
+                    let value = borrow::Cow::from(value.as_bytes());
+
+                    if let Err(e) = clipboard.set_content(value, copyrs::ClipboardContentKind::Text)
+                    {
+                        crate::error!("failed to set clipboard: {e}");
+                        protocol::Response::Failed.send(stream)?;
+                    } else {
+                        watch.rebase();
+                        protocol::Response::WriteDone.send(stream)?;
                     }
                 }
             }
         }
+
+        protocol::Command::ReadImage => {
+            crate::debug!("read_image");
+
+            match image::read() {
+                Err(e) => {
+                    crate::error!("failed to get clipboard image: {e}");
+                    protocol::Response::Failed.send(stream)?;
+                }
+                Ok(png) => {
+                    protocol::Response::Image(png).send(stream)?;
+                }
+            }
+        }
+
+        protocol::Command::WriteImage(png) => {
+            crate::debug!("write_image ({} bytes)", png.len());
+
+            if let Err(e) = image::write(&png) {
+                crate::error!("failed to set clipboard image: {e}");
+                protocol::Response::Failed.send(stream)?;
+            } else {
+                watch.rebase();
+                protocol::Response::WriteDone.send(stream)?;
+            }
+        }
+
+        protocol::Command::Watch => {
+            crate::debug!("watch");
+
+            // only the changes from now on are notified
+            watch.changes = Some(changes::Changes::new());
+            watch.rebase();
+        }
+
+        protocol::Command::Unwatch => {
+            crate::debug!("unwatch");
+
+            watch.changes = None;
+            protocol::Response::Unwatched.send(stream)?;
+        }
     }
+
+    Ok(())
 }
@@ -0,0 +1,20 @@
+use windows_sys::Win32::System::DataExchange;
+
+// Tells whether the clipboard changed since the last call, from its
+// sequence number, for the clipboard to only be read (and its bitmap
+// converted) then
+pub struct Changes(u32);
+
+impl Changes {
+    pub fn new() -> Self {
+        Self(unsafe { DataExchange::GetClipboardSequenceNumber() })
+    }
+
+    pub fn changed(&mut self) -> bool {
+        let sequence = unsafe { DataExchange::GetClipboardSequenceNumber() };
+        // 0 when the process has no access to the sequence number
+        let changed = sequence == 0 || sequence != self.0;
+        self.0 = sequence;
+        changed
+    }
+}
@@ -0,0 +1,67 @@
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        Event,
+        xfixes::{self, ConnectionExt as _},
+        xproto::ConnectionExt as _,
+    },
+    rust_connection::RustConnection,
+};
+
+// Asks the X server (XFixes extension) to be notified each time the
+// clipboard gets a new owner, which is what copying does
+fn connect() -> copyrs::Result<RustConnection> {
+    let (connection, screen) = RustConnection::connect(None)?;
+    let root = connection
+        .setup()
+        .roots
+        .get(screen)
+        .ok_or("invalid screen")?
+        .root;
+    connection.xfixes_query_version(5, 0)?.reply()?;
+    let clipboard = connection.intern_atom(false, b"CLIPBOARD")?.reply()?.atom;
+    connection
+        .xfixes_select_selection_input(
+            root,
+            clipboard,
+            xfixes::SelectionEventMask::SET_SELECTION_OWNER
+                | xfixes::SelectionEventMask::SELECTION_WINDOW_DESTROY
+                | xfixes::SelectionEventMask::SELECTION_CLIENT_CLOSE,
+        )?
+        .check()?;
+    Ok(connection)
+}
+
+// Tells whether the clipboard changed since the last call, for the
+// clipboard to only be read then; it is always considered changed if
+// the notifications are not available
+pub struct Changes(Option<RustConnection>);
+
+impl Changes {
+    pub fn new() -> Self {
+        Self(
+            connect()
+                .inspect_err(|e| crate::warn!("clipboard changes not notified: {e}"))
+                .ok(),
+        )
+    }
+
+    pub fn changed(&mut self) -> bool {
+        let Some(connection) = &self.0 else {
+            return true;
+        };
+        let mut changed = false;
+        loop {
+            match connection.poll_for_event() {
+                Ok(Some(Event::XfixesSelectionNotify(_))) => changed = true,
+                Ok(Some(_)) => {}
+                Ok(None) => return changed,
+                Err(e) => {
+                    crate::warn!("clipboard changes no longer notified: {e}");
+                    self.0 = None;
+                    return true;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use super::image;
+use copyrs::Clipboard;
+#[cfg(feature = "frontend")]
+use std::borrow;
+use std::hash::{self, Hash, Hasher};
+
+// Text or PNG image held by the local clipboard
+#[derive(Hash)]
+pub enum Content {
+    Text(String),
+    Image(Vec<u8>),
+}
+
+impl Content {
+    // None if the clipboard is empty or unreadable
+    pub fn current() -> Option<Self> {
+        match copyrs::clipboard().and_then(|clipboard| clipboard.get_content()) {
+            Ok(content) if content.kind == copyrs::ClipboardContentKind::Text => Some(Self::Text(
+                String::from_utf8_lossy(&content.data).to_string(),
+            )),
+            _ => image::read().ok().map(Self::Image),
+        }
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn write(&self) -> copyrs::Result<()> {
+        match self {
+            Self::Text(text) => copyrs::clipboard()?.set_content(
+                borrow::Cow::from(text.as_bytes()),
+                copyrs::ClipboardContentKind::Text,
+            ),
+            Self::Image(png) => image::write(png),
+        }
+    }
+
+    #[cfg(feature = "frontend")]
+    pub const fn size(&self) -> usize {
+        match self {
+            Self::Text(text) => text.len(),
+            Self::Image(png) => png.len(),
+        }
+    }
+
+    // Changes are detected on the hash of the content
+    pub fn digest(&self) -> u64 {
+        let mut hasher = hash::DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
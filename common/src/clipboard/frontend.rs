@@ -1,10 +1,13 @@
 use super::protocol;
-use crate::{api, channel, frontend, service};
+use crate::{api, channel, frontend, rdp, service, util};
 use std::{
+    fs,
     io::{self, BufRead, Write},
-    net, thread,
+    net, thread, time,
 };
 
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
 // https://patorjk.com/software/taag/#p=display&h=0&v=0&f=Ogre&t=clipboard%0A
 const LOGO: &str = r"
        _  _         _                              _
@@ -18,11 +21,131 @@ const HELP: &str = r#"
 Available commands:
 - "read" or "get" to get remote clipboard content;
 - "write XXX" or "put XXX" to set remote clipboard content to XXX;
+- "save FILE" to save the remote clipboard image to the local PNG FILE;
+- "load FILE" to set the remote clipboard image to the local PNG FILE;
+- "watch [FILE]" to print each change of the remote clipboard, appending
+  them to the local FILE if given, until enter is pressed;
 - "exit" or "quit" to exit this intrerface.
 "#;
 
 const PROMPT: &str = "clipboard> ";
 
+fn save<W>(
+    rdp_read: &mut rdp::RdpReader<'_>,
+    rdp_write: &mut rdp::RdpWriter<'_>,
+    client: &mut W,
+    path: &str,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    protocol::Command::ReadImage.send(rdp_write)?;
+    match protocol::Response::receive(rdp_read)? {
+        protocol::Response::Image(png) => match fs::write(path, &png) {
+            Ok(()) => writeln!(client, "ok {} bytes", png.len()),
+            Err(e) => writeln!(client, "KO {e}"),
+        },
+        protocol::Response::Failed => writeln!(client, "KO"),
+        _ => unreachable!(),
+    }
+}
+
+fn load<W>(
+    rdp_read: &mut rdp::RdpReader<'_>,
+    rdp_write: &mut rdp::RdpWriter<'_>,
+    client: &mut W,
+    path: &str,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    let png = match fs::read(path) {
+        Err(e) => return writeln!(client, "KO {e}"),
+        Ok(png) if !png.starts_with(PNG_SIGNATURE) => {
+            return writeln!(client, "KO not a PNG file");
+        }
+        Ok(png) => png,
+    };
+    protocol::Command::WriteImage(png).send(rdp_write)?;
+    match protocol::Response::receive(rdp_read)? {
+        protocol::Response::WriteDone => writeln!(client, "ok"),
+        protocol::Response::Failed => writeln!(client, "KO"),
+        _ => unreachable!(),
+    }
+}
+
+// Prefixes the entry with the current date and time (UTC)
+fn record(history: Option<&mut fs::File>, entry: &str) {
+    let Some(file) = history else {
+        return;
+    };
+    let timestamp = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let [year, month, day, hour, minute, second] =
+        util::civil_from_unix(i64::try_from(timestamp).unwrap_or(i64::MAX));
+    if let Err(e) = writeln!(
+        file,
+        "{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02} {entry}"
+    ) {
+        crate::warn!("failed to write history: {e}");
+    }
+}
+
+// Prints the changes of the remote clipboard until a line is received
+// from the client, the latter having disconnected if false is returned
+fn watch<R, W>(
+    rdp_read: &mut rdp::RdpReader<'_>,
+    rdp_write: &mut rdp::RdpWriter<'_>,
+    client_read: &mut R,
+    client_write: &mut W,
+    history: Option<&str>,
+) -> Result<bool, io::Error>
+where
+    R: io::BufRead + Send,
+    W: io::Write,
+{
+    let history = history.map(|path| fs::OpenOptions::new().create(true).append(true).open(path));
+    let mut history = match history.transpose() {
+        Ok(history) => history,
+        Err(e) => {
+            writeln!(client_write, "KO {e}")?;
+            return Ok(true);
+        }
+    };
+
+    protocol::Command::Watch.send(rdp_write)?;
+    writeln!(client_write, "watching, press enter to stop")?;
+    client_write.flush()?;
+
+    let connected = thread::scope(|scope| {
+        let stop = scope.spawn(move || -> Result<bool, io::Error> {
+            let mut line = String::new();
+            let connected = client_read.read_line(&mut line).is_ok_and(|len| 0 < len);
+            protocol::Command::Unwatch.send(rdp_write)?;
+            Ok(connected)
+        });
+
+        loop {
+            let entry = match protocol::Response::receive(rdp_read)? {
+                protocol::Response::TextChanged(text) => format!("text {text:?}"),
+                protocol::Response::ImageChanged(size) => format!("image {size} bytes"),
+                protocol::Response::Unwatched => break,
+                _ => unreachable!(),
+            };
+            writeln!(client_write, "{entry}")?;
+            client_write.flush()?;
+            record(history.as_mut(), &entry);
+        }
+
+        stop.join().unwrap()
+    })?;
+    if connected {
+        writeln!(client_write, "ok")?;
+    }
+    Ok(connected)
+}
+
 pub fn tcp_handler<'a>(
     _server: &frontend::FrontendTcpServer,
     _scope: &'a thread::Scope<'a, '_>,
@@ -37,7 +160,7 @@ pub fn tcp_handler<'a>(
     client_write.write_fmt(format_args!("{}\n{}\n{}\n", service::LOGO, LOGO, HELP))?;
     client_write.flush()?;
 
-    let mut rdp = channel.connect(&super::SERVICE)?;
+    let (mut rdp_read, mut rdp_write) = channel.connect(&super::SERVICE)?.split();
 
     let mut line = String::new();
 
@@ -67,30 +190,50 @@ pub fn tcp_handler<'a>(
         crate::trace!("COMMAND = {command:?}");
         crate::trace!("ARGS = {args:?}");
 
+        let path = args.trim();
+
         match command.as_str() {
             "" => (),
             "READ" | "GET" => {
-                protocol::Command::Read.send(&mut rdp)?;
-                match protocol::Response::receive(&mut rdp)? {
+                protocol::Command::Read.send(&mut rdp_write)?;
+                match protocol::Response::receive(&mut rdp_read)? {
                     protocol::Response::Text(value) => {
                         writeln!(client_write, "ok {value:?}")?;
                     }
                     protocol::Response::Failed => {
                         writeln!(client_write, "KO")?;
                     }
-                    protocol::Response::WriteDone => unreachable!(),
+                    _ => unreachable!(),
                 }
             }
             "WRITE" | "PUT" => {
-                protocol::Command::WriteText(args).send(&mut rdp)?;
-                match protocol::Response::receive(&mut rdp)? {
+                protocol::Command::WriteText(args.clone()).send(&mut rdp_write)?;
+                match protocol::Response::receive(&mut rdp_read)? {
                     protocol::Response::WriteDone => {
                         writeln!(client_write, "ok")?;
                     }
                     protocol::Response::Failed => {
                         writeln!(client_write, "KO")?;
                     }
-                    protocol::Response::Text(_) => unreachable!(),
+                    _ => unreachable!(),
+                }
+            }
+            "SAVE" if !path.is_empty() => {
+                save(&mut rdp_read, &mut rdp_write, &mut client_write, path)?;
+            }
+            "LOAD" if !path.is_empty() => {
+                load(&mut rdp_read, &mut rdp_write, &mut client_write, path)?;
+            }
+            "WATCH" => {
+                let history = Some(path).filter(|path| !path.is_empty());
+                if !watch(
+                    &mut rdp_read,
+                    &mut rdp_write,
+                    &mut client_read,
+                    &mut client_write,
+                    history,
+                )? {
+                    return Ok(());
                 }
             }
             "EXIT" | "QUIT" => {
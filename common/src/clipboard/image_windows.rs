@@ -0,0 +1,244 @@
+use super::png;
+use std::io;
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(bmp: &[u8], offset: usize) -> Result<u16, io::Error> {
+    bmp.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("truncated bitmap"))
+}
+
+fn u32_at(bmp: &[u8], offset: usize) -> Result<u32, io::Error> {
+    bmp.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("truncated bitmap"))
+}
+
+// Uncompressed bitmap files, as given by the clipboard, of 1 to 8 bits
+// per pixel with a palette, 24 or 32 bits per pixel otherwise (the
+// fourth byte not being an alpha channel)
+fn from_bmp(bmp: &[u8]) -> Result<png::Image, io::Error> {
+    let too_large = || invalid("bitmap too large");
+
+    let offset = usize::try_from(u32_at(bmp, 10)?).map_err(|_| invalid("invalid offset"))?;
+    let header_size = usize::try_from(u32_at(bmp, FILE_HEADER_SIZE)?)
+        .map_err(|_| invalid("invalid header size"))?;
+    let width = u32_at(bmp, FILE_HEADER_SIZE + 4)?.cast_signed();
+    let height = u32_at(bmp, FILE_HEADER_SIZE + 8)?.cast_signed();
+    let bits = usize::from(u16_at(bmp, FILE_HEADER_SIZE + 14)?);
+    let compression = u32_at(bmp, FILE_HEADER_SIZE + 16)?;
+
+    if compression != 0 {
+        return Err(invalid("compressed bitmaps are not supported"));
+    }
+    if !matches!(bits, 1 | 4 | 8 | 24 | 32) {
+        return Err(invalid("unsupported bits per pixel"));
+    }
+
+    // rows are stored from bottom to top unless the height is negative
+    let bottom_up = 0 < height;
+    let width = width.unsigned_abs();
+    let height = height.unsigned_abs();
+    if png::MAX_PIXELS < u64::from(width) * u64::from(height) {
+        return Err(too_large());
+    }
+    let width_usize = usize::try_from(width).map_err(|_| too_large())?;
+    let height_usize = usize::try_from(height).map_err(|_| too_large())?;
+
+    let palette = FILE_HEADER_SIZE
+        .checked_add(header_size)
+        .and_then(|start| bmp.get(start..offset))
+        .unwrap_or_default();
+    let stride = width_usize
+        .checked_mul(bits)
+        .and_then(|bits| bits.div_ceil(32).checked_mul(4))
+        .ok_or_else(too_large)?;
+    let end = stride
+        .checked_mul(height_usize)
+        .and_then(|size| size.checked_add(offset))
+        .ok_or_else(too_large)?;
+    let data = bmp
+        .get(offset..end)
+        .ok_or_else(|| invalid("truncated bitmap"))?;
+
+    let mut pixels = Vec::with_capacity(width_usize * height_usize * 4);
+    for y in 0..height_usize {
+        let y = if bottom_up { height_usize - 1 - y } else { y };
+        let row = &data[y * stride..(y + 1) * stride];
+        for x in 0..width_usize {
+            let bgr = if bits <= 8 {
+                let bit = x * bits;
+                let index =
+                    usize::from((row[bit / 8] >> (8 - bits - bit % 8)) & (u8::MAX >> (8 - bits)));
+                palette
+                    .get(index * 4..index * 4 + 3)
+                    .ok_or_else(|| invalid("invalid palette index"))?
+            } else {
+                let start = x * bits / 8;
+                &row[start..start + 3]
+            };
+            pixels.extend_from_slice(&[bgr[2], bgr[1], bgr[0], u8::MAX]);
+        }
+    }
+
+    Ok(png::Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// 32 bits per pixel bitmap file
+fn to_bmp(image: &png::Image) -> Result<Vec<u8>, io::Error> {
+    let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+    let size =
+        u32::try_from(offset + image.pixels.len()).map_err(|_| invalid("image too large"))?;
+    let width = i32::try_from(image.width).map_err(|_| invalid("image too large"))?;
+    let height = i32::try_from(image.height).map_err(|_| invalid("image too large"))?;
+    let image_size = u32::try_from(image.pixels.len()).map_err(|_| invalid("image too large"))?;
+
+    let mut bmp = Vec::with_capacity(offset + image.pixels.len());
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&size.to_le_bytes());
+    bmp.extend_from_slice(&[0; 4]);
+    bmp.extend_from_slice(&u32::try_from(offset).unwrap_or(0).to_le_bytes());
+
+    bmp.extend_from_slice(&u32::try_from(INFO_HEADER_SIZE).unwrap_or(0).to_le_bytes());
+    bmp.extend_from_slice(&width.to_le_bytes());
+    // from bottom to top
+    bmp.extend_from_slice(&height.to_le_bytes());
+    // planes, bits per pixel, no compression
+    bmp.extend_from_slice(&1u16.to_le_bytes());
+    bmp.extend_from_slice(&32u16.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&image_size.to_le_bytes());
+    // resolutions and palette
+    bmp.extend_from_slice(&[0; 16]);
+
+    let stride = usize::try_from(image.width).map_err(|_| invalid("image too large"))? * 4;
+    if 0 < stride {
+        for row in image.pixels.chunks_exact(stride).rev() {
+            for rgba in row.chunks_exact(4) {
+                bmp.extend_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+            }
+        }
+    }
+
+    Ok(bmp)
+}
+
+pub fn read() -> copyrs::Result<Vec<u8>> {
+    let bitmap = copyrs::WindowsClipboard::new()?.get_bitmap()?;
+    Ok(png::encode(&from_bmp(&bitmap.bytes)?)?)
+}
+
+pub fn write(data: &[u8]) -> copyrs::Result<()> {
+    let bytes = to_bmp(&png::decode(data)?)?;
+    copyrs::WindowsClipboard::new()?.set_bitmap(&copyrs::Image { bytes })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bitmap file with the given header fields, palette and pixel data
+    fn bmp(width: i32, height: i32, bits: u16, palette: &[u8], data: &[u8]) -> Vec<u8> {
+        let offset = u32::try_from(FILE_HEADER_SIZE + INFO_HEADER_SIZE + palette.len()).unwrap();
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&(offset + u32::try_from(data.len()).unwrap()).to_le_bytes());
+        bmp.extend_from_slice(&[0; 4]);
+        bmp.extend_from_slice(&offset.to_le_bytes());
+        bmp.extend_from_slice(&u32::try_from(INFO_HEADER_SIZE).unwrap().to_le_bytes());
+        bmp.extend_from_slice(&width.to_le_bytes());
+        bmp.extend_from_slice(&height.to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&bits.to_le_bytes());
+        bmp.extend_from_slice(&[0; 24]);
+        bmp.extend_from_slice(palette);
+        bmp.extend_from_slice(data);
+        bmp
+    }
+
+    #[test]
+    fn round_trip() {
+        // opaque, the fourth byte of bitmaps not being an alpha channel
+        let image = png::Image {
+            width: 3,
+            height: 2,
+            pixels: (0..24)
+                .map(|i| if i % 4 == 3 { u8::MAX } else { i * 10 })
+                .collect(),
+        };
+        let decoded = from_bmp(&to_bmp(&image).unwrap()).unwrap();
+        assert_eq!((decoded.width, decoded.height), (3, 2));
+        assert_eq!(decoded.pixels, image.pixels);
+    }
+
+    #[test]
+    fn palette() {
+        // 4 bits per pixel, rows padded to 4 bytes, from top to bottom
+        let palette = [3, 2, 1, 0, 6, 5, 4, 0];
+        let decoded = from_bmp(&bmp(
+            3,
+            -2,
+            4,
+            &palette,
+            &[0x01, 0x00, 0, 0, 0x10, 0x10, 0, 0],
+        ))
+        .unwrap();
+        assert_eq!(
+            decoded.pixels,
+            [
+                1, 2, 3, 255, 4, 5, 6, 255, 1, 2, 3, 255, //
+                4, 5, 6, 255, 1, 2, 3, 255, 4, 5, 6, 255,
+            ]
+        );
+        // 8 bits per pixel
+        let decoded = from_bmp(&bmp(2, 1, 8, &palette, &[1, 0, 0, 0])).unwrap();
+        assert_eq!(decoded.pixels, [4, 5, 6, 255, 1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn bottom_up() {
+        // 24 bits per pixel, rows padded to 4 bytes
+        let data = [1, 2, 3, 0, 4, 5, 6, 0];
+        let decoded = from_bmp(&bmp(1, 2, 24, &[], &data)).unwrap();
+        assert_eq!(decoded.pixels, [6, 5, 4, 255, 3, 2, 1, 255]);
+    }
+
+    #[test]
+    fn malformed() {
+        let valid = bmp(2, 2, 32, &[], &[0; 16]);
+
+        for len in 0..valid.len() {
+            assert!(from_bmp(&valid[..len]).is_err());
+        }
+        // compressed
+        let mut compressed = valid.clone();
+        compressed[FILE_HEADER_SIZE + 16] = 1;
+        assert!(from_bmp(&compressed).is_err());
+        // unsupported bits per pixel
+        assert!(from_bmp(&bmp(1, 1, 16, &[], &[0; 4])).is_err());
+        // palette index out of the palette
+        assert!(from_bmp(&bmp(1, 1, 8, &[0; 4], &[1, 0, 0, 0])).is_err());
+        // header size beyond the end of the file
+        let mut header = valid;
+        header[FILE_HEADER_SIZE..FILE_HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(from_bmp(&header).is_ok());
+    }
+
+    #[test]
+    fn dimensions() {
+        // refused before allocating anything, without overflow
+        assert!(from_bmp(&bmp(i32::MAX, i32::MAX, 32, &[], &[])).is_err());
+        assert!(from_bmp(&bmp(i32::MIN, 1, 32, &[], &[])).is_err());
+        assert!(from_bmp(&bmp(1 << 13, 1 << 14, 32, &[], &[])).is_err());
+    }
+}
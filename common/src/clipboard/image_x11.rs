@@ -0,0 +1,32 @@
+use std::{sync::Mutex, time};
+
+const TARGET: &str = "image/png";
+
+const TIMEOUT: time::Duration = time::Duration::from_secs(3);
+
+// The image is only served to other clients while the clipboard which
+// stored it lives
+static OWNER: Mutex<Option<copyrs::X11Clipboard>> = Mutex::new(None);
+
+pub fn read() -> copyrs::Result<Vec<u8>> {
+    let clipboard = copyrs::X11Clipboard::new()?;
+    let target = clipboard.getter.get_atom(TARGET)?;
+    let png = clipboard.load(
+        clipboard.getter.atoms.clipboard,
+        target,
+        clipboard.getter.atoms.property,
+        TIMEOUT,
+    )?;
+    if png.is_empty() {
+        return Err("no image in the clipboard".into());
+    }
+    Ok(png)
+}
+
+pub fn write(png: &[u8]) -> copyrs::Result<()> {
+    let clipboard = copyrs::X11Clipboard::new()?;
+    let target = clipboard.setter.get_atom(TARGET)?;
+    clipboard.store(clipboard.setter.atoms.clipboard, target, png)?;
+    *OWNER.lock().unwrap() = Some(clipboard);
+    Ok(())
+}
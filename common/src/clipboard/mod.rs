@@ -4,9 +4,48 @@ use crate::service;
 
 #[cfg(feature = "backend")]
 mod backend;
+#[cfg(all(any(feature = "backend", feature = "frontend"), target_os = "windows"))]
+mod changes_windows;
+#[cfg(all(
+    any(feature = "backend", feature = "frontend"),
+    not(target_os = "windows")
+))]
+mod changes_x11;
+#[cfg(any(feature = "backend", feature = "frontend"))]
+mod content;
 #[cfg(feature = "frontend")]
 mod frontend;
+#[cfg(all(any(feature = "backend", feature = "frontend"), target_os = "windows"))]
+mod image_windows;
+#[cfg(all(
+    any(feature = "backend", feature = "frontend"),
+    not(target_os = "windows")
+))]
+mod image_x11;
+// also tested on other targets
+#[cfg(all(
+    any(feature = "backend", feature = "frontend"),
+    any(target_os = "windows", test)
+))]
+mod png;
 mod protocol;
+#[cfg(feature = "frontend")]
+mod sync;
+
+#[cfg(all(any(feature = "backend", feature = "frontend"), target_os = "windows"))]
+use changes_windows as changes;
+#[cfg(all(
+    any(feature = "backend", feature = "frontend"),
+    not(target_os = "windows")
+))]
+use changes_x11 as changes;
+#[cfg(all(any(feature = "backend", feature = "frontend"), target_os = "windows"))]
+use image_windows as image;
+#[cfg(all(
+    any(feature = "backend", feature = "frontend"),
+    not(target_os = "windows")
+))]
+use image_x11 as image;
 
 pub static SERVICE: service::Service = service::Service {
     internal: false,
@@ -16,7 +55,9 @@ pub static SERVICE: service::Service = service::Service {
         tcp: Some(sfrontend::FrontendTcp {
             default_port: 3032,
             handler: frontend::tcp_handler,
+            background: Some(sync::background),
         }),
+        udp: None,
     }),
     #[cfg(feature = "backend")]
     backend: Some(service::Backend {
@@ -0,0 +1,382 @@
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use std::io::{self, Read, Write};
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+// Bigger images are refused rather than allocated
+pub const MAX_PIXELS: u64 = 1 << 26;
+
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+// 8 bits RGBA pixels, rows from top to bottom
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn chunk(png: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) -> Result<(), io::Error> {
+    let len = u32::try_from(data.len()).map_err(|_| invalid("chunk too large"))?;
+    png.extend_from_slice(&len.to_be_bytes());
+    png.extend_from_slice(&kind);
+    png.extend_from_slice(data);
+    let mut crc = flate2::Crc::new();
+    crc.update(&kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+    Ok(())
+}
+
+pub fn encode(image: &Image) -> Result<Vec<u8>, io::Error> {
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    // 8 bits per sample, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, COLOR_RGBA, 0, 0, 0]);
+    chunk(&mut png, *b"IHDR", &header)?;
+
+    let stride = usize::try_from(image.width).map_err(|_| invalid("image too large"))? * 4;
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    if 0 < stride {
+        for row in image.pixels.chunks_exact(stride) {
+            // no filter
+            encoder.write_all(&[0])?;
+            encoder.write_all(row)?;
+        }
+    }
+    chunk(&mut png, *b"IDAT", &encoder.finish()?)?;
+
+    chunk(&mut png, *b"IEND", &[])?;
+
+    Ok(png)
+}
+
+const fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Reverts the filter of a row given the previous (unfiltered) one
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), io::Error> {
+    for i in 0..row.len() {
+        let left = if bpp <= i { row[i - bpp] } else { 0 };
+        let up = previous[i];
+        let up_left = if bpp <= i { previous[i - bpp] } else { 0 };
+        row[i] = row[i].wrapping_add(match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => left.midpoint(up),
+            4 => paeth(left, up, up_left),
+            _ => return Err(invalid("invalid filter")),
+        });
+    }
+    Ok(())
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: u8,
+    color: u8,
+    channels: usize,
+}
+
+impl Header {
+    fn parse(header: &[u8]) -> Result<Self, io::Error> {
+        let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let (depth, color, interlace) = (header[8], header[9], header[12]);
+
+        if interlace != 0 {
+            return Err(invalid("interlaced PNG files are not supported"));
+        }
+        let channels = match (color, depth) {
+            (COLOR_GRAY, 1 | 2 | 4 | 8 | 16) | (COLOR_PALETTE, 1 | 2 | 4 | 8) => 1,
+            (COLOR_GRAY_ALPHA, 8 | 16) => 2,
+            (COLOR_RGB, 8 | 16) => 3,
+            (COLOR_RGBA, 8 | 16) => 4,
+            _ => return Err(invalid("invalid color type or bit depth")),
+        };
+        if width == 0 || height == 0 {
+            return Err(invalid("invalid dimensions"));
+        }
+        if MAX_PIXELS < u64::from(width) * u64::from(height) {
+            return Err(invalid("image too large"));
+        }
+
+        Ok(Self {
+            width: usize::try_from(width).map_err(|_| invalid("image too large"))?,
+            height: usize::try_from(height).map_err(|_| invalid("image too large"))?,
+            depth,
+            color,
+            channels,
+        })
+    }
+
+    // most significant byte or bits of a sample
+    fn sample(&self, row: &[u8], x: usize, channel: usize) -> u8 {
+        let index = x * self.channels + channel;
+        match self.depth {
+            8 => row[index],
+            16 => row[index * 2],
+            depth => {
+                let bit = index * usize::from(depth);
+                let shift = 8 - usize::from(depth) - bit % 8;
+                (row[bit / 8] >> shift) & ((1 << depth) - 1)
+            }
+        }
+    }
+
+    // scales gray levels of less than 8 bits
+    fn gray(&self, row: &[u8], x: usize) -> u8 {
+        let value = self.sample(row, x, 0);
+        if self.depth < 8 {
+            u8::try_from(u16::from(value) * 255 / ((1 << self.depth) - 1)).unwrap_or(u8::MAX)
+        } else {
+            value
+        }
+    }
+
+    fn rgba(
+        &self,
+        row: &[u8],
+        x: usize,
+        palette: &[u8],
+        transparency: &[u8],
+    ) -> Result<[u8; 4], io::Error> {
+        let sample = |channel| self.sample(row, x, channel);
+        Ok(match self.color {
+            COLOR_GRAY => {
+                let value = self.gray(row, x);
+                [value, value, value, u8::MAX]
+            }
+            COLOR_PALETTE => {
+                let index = usize::from(sample(0));
+                let rgb = palette
+                    .get(index * 3..index * 3 + 3)
+                    .ok_or_else(|| invalid("invalid palette index"))?;
+                let alpha = transparency.get(index).copied().unwrap_or(u8::MAX);
+                [rgb[0], rgb[1], rgb[2], alpha]
+            }
+            COLOR_GRAY_ALPHA => [sample(0), sample(0), sample(0), sample(1)],
+            COLOR_RGB => [sample(0), sample(1), sample(2), u8::MAX],
+            _ => [sample(0), sample(1), sample(2), sample(3)],
+        })
+    }
+}
+
+// Non interlaced images of any color type and bit depth
+pub fn decode(png: &[u8]) -> Result<Image, io::Error> {
+    let mut png = png
+        .strip_prefix(SIGNATURE)
+        .ok_or_else(|| invalid("not a PNG file"))?;
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = vec![];
+
+    while 8 <= png.len() {
+        let len = usize::try_from(u32::from_be_bytes([png[0], png[1], png[2], png[3]]))
+            .map_err(|_| invalid("chunk too large"))?;
+        let kind = &png[4..8];
+        let end = len
+            .checked_add(8)
+            .ok_or_else(|| invalid("chunk too large"))?;
+        let data = png.get(8..end).ok_or_else(|| invalid("truncated chunk"))?;
+        match kind {
+            b"IHDR" if data.len() == 13 => header = Some(Header::parse(data)?),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => (),
+        }
+        // skips the CRC
+        png = png.get(end + 4..).unwrap_or_default();
+    }
+
+    let header = header.ok_or_else(|| invalid("missing header"))?;
+
+    let bits = header.channels * usize::from(header.depth);
+    let stride = (header.width * bits).div_ceil(8);
+    let bpp = bits.div_ceil(8);
+    let size = (stride + 1) * header.height;
+
+    let mut data = Vec::with_capacity(size);
+    ZlibDecoder::new(compressed.as_slice())
+        .take(u64::try_from(size).unwrap_or(u64::MAX))
+        .read_to_end(&mut data)?;
+    if data.len() != size {
+        return Err(invalid("truncated image data"));
+    }
+
+    let mut pixels = Vec::with_capacity(header.width * header.height * 4);
+    let mut previous = vec![0u8; stride];
+
+    for row in data.chunks_exact_mut(stride + 1) {
+        let (filter, row) = row.split_at_mut(1);
+        unfilter(filter[0], row, &previous, bpp)?;
+        for x in 0..header.width {
+            pixels.extend_from_slice(&header.rgba(row, x, palette, transparency)?);
+        }
+        previous.copy_from_slice(row);
+    }
+
+    Ok(Image {
+        width: u32::try_from(header.width).unwrap_or(u32::MAX),
+        height: u32::try_from(header.height).unwrap_or(u32::MAX),
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32) -> Image {
+        let pixels = (0..width * height * 4)
+            .map(|i| u8::try_from(i * 37 % 251).unwrap())
+            .collect();
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // PNG file made of the given header fields and filtered rows
+    fn png(
+        width: u32,
+        height: u32,
+        depth: u8,
+        color: u8,
+        chunks: &[(&[u8; 4], &[u8])],
+        rows: &[u8],
+    ) -> Vec<u8> {
+        let mut png = SIGNATURE.to_vec();
+        let mut header = vec![];
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[depth, color, 0, 0, 0]);
+        chunk(&mut png, *b"IHDR", &header).unwrap();
+        for (kind, data) in chunks {
+            chunk(&mut png, **kind, data).unwrap();
+        }
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(rows).unwrap();
+        chunk(&mut png, *b"IDAT", &encoder.finish().unwrap()).unwrap();
+        chunk(&mut png, *b"IEND", &[]).unwrap();
+        png
+    }
+
+    #[test]
+    fn round_trip() {
+        for (width, height) in [(1, 1), (3, 2), (17, 5)] {
+            let image = image(width, height);
+            let decoded = decode(&encode(&image).unwrap()).unwrap();
+            assert_eq!((decoded.width, decoded.height), (width, height));
+            assert_eq!(decoded.pixels, image.pixels);
+        }
+    }
+
+    #[test]
+    fn palette() {
+        // 1 bit per pixel, the second entry being transparent
+        let png = png(
+            3,
+            1,
+            1,
+            COLOR_PALETTE,
+            &[(b"PLTE", &[1, 2, 3, 4, 5, 6]), (b"tRNS", &[255, 0])],
+            &[0, 0b0100_0000],
+        );
+        let decoded = decode(&png).unwrap();
+        assert_eq!(decoded.pixels, [1, 2, 3, 255, 4, 5, 6, 0, 1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn gray() {
+        // 2 bits per pixel scaled to 8 bits, 16 bits keeping the most
+        // significant byte
+        let decoded = decode(&png(2, 1, 2, COLOR_GRAY, &[], &[0, 0b0001_1100])).unwrap();
+        assert_eq!(decoded.pixels, [0, 0, 0, 255, 85, 85, 85, 255]);
+        let decoded = decode(&png(1, 1, 16, COLOR_GRAY, &[], &[0, 0x12, 0x34])).unwrap();
+        assert_eq!(decoded.pixels, [0x12, 0x12, 0x12, 255]);
+    }
+
+    #[test]
+    fn filters() {
+        // RGB rows filtered with sub, up, average and paeth
+        let rows = [
+            1, 10, 20, 30, 1, 2, 3, //
+            2, 1, 1, 1, 1, 1, 1, //
+            3, 5, 5, 5, 0, 0, 0, //
+            4, 0, 0, 0, 1, 1, 1,
+        ];
+        let decoded = decode(&png(2, 4, 8, COLOR_RGB, &[], &rows)).unwrap();
+        assert_eq!(
+            decoded.pixels,
+            [
+                10, 20, 30, 255, 11, 22, 33, 255, //
+                11, 21, 31, 255, 12, 23, 34, 255, //
+                10, 15, 20, 255, 11, 19, 27, 255, //
+                10, 15, 20, 255, 12, 20, 28, 255,
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let valid = encode(&image(2, 2)).unwrap();
+
+        assert!(decode(b"GIF89a").is_err());
+        assert!(decode(SIGNATURE).is_err());
+        // truncated anywhere before the end of the image data, CRCs
+        // not being checked
+        for len in 0..valid.len() - 16 {
+            assert!(decode(&valid[..len]).is_err());
+        }
+        // chunk length beyond the end of the file
+        let mut corrupted = valid;
+        corrupted[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decode(&corrupted).is_err());
+        // invalid filter
+        assert!(decode(&png(1, 1, 8, COLOR_GRAY, &[], &[5, 0])).is_err());
+        // invalid bit depth
+        assert!(decode(&png(1, 1, 3, COLOR_RGB, &[], &[0, 0])).is_err());
+        // palette index without palette
+        assert!(decode(&png(1, 1, 8, COLOR_PALETTE, &[], &[0, 0])).is_err());
+        // not enough data for the dimensions
+        assert!(decode(&png(4, 4, 8, COLOR_RGBA, &[], &[0; 4])).is_err());
+    }
+
+    #[test]
+    fn dimensions() {
+        assert!(decode(&png(0, 1, 8, COLOR_GRAY, &[], &[])).is_err());
+        assert!(decode(&png(1, 0, 8, COLOR_GRAY, &[], &[])).is_err());
+        // refused before allocating anything
+        assert!(decode(&png(u32::MAX, u32::MAX, 16, COLOR_RGBA, &[], &[])).is_err());
+        assert!(decode(&png(1 << 13, 1 << 14, 8, COLOR_GRAY, &[], &[])).is_err());
+    }
+}
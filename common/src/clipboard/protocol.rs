@@ -3,10 +3,21 @@ use std::io;
 
 const ID_READ: u8 = 0x0;
 const ID_WRITE_TEXT: u8 = 0x1;
+const ID_READ_IMAGE: u8 = 0x2;
+const ID_WRITE_IMAGE: u8 = 0x3;
+const ID_WATCH: u8 = 0x4;
+const ID_UNWATCH: u8 = 0x5;
 
+// Images are carried as PNG files. Once watching, the backend sends a
+// TextChanged or ImageChanged response on each change of the clipboard
+// until Unwatch is acknowledged by Unwatched
 pub enum Command {
     Read,
     WriteText(String),
+    ReadImage,
+    WriteImage(Vec<u8>),
+    Watch,
+    Unwatch,
 }
 
 impl Command {
@@ -26,6 +37,24 @@ impl Command {
 
                 util::serialize_string(stream, t)?;
             }
+            Self::ReadImage => {
+                let buf = [ID_READ_IMAGE; 1];
+                stream.write_all(&buf)?;
+            }
+            Self::WriteImage(png) => {
+                let buf = [ID_WRITE_IMAGE; 1];
+                stream.write_all(&buf)?;
+
+                util::serialize_bytes(stream, png)?;
+            }
+            Self::Watch => {
+                let buf = [ID_WATCH; 1];
+                stream.write_all(&buf)?;
+            }
+            Self::Unwatch => {
+                let buf = [ID_UNWATCH; 1];
+                stream.write_all(&buf)?;
+            }
         }
         stream.flush()
     }
@@ -44,6 +73,13 @@ impl Command {
                 let text = util::deserialize_string(stream)?;
                 Ok(Self::WriteText(text))
             }
+            ID_READ_IMAGE => Ok(Self::ReadImage),
+            ID_WRITE_IMAGE => {
+                let png = util::deserialize_bytes(stream)?;
+                Ok(Self::WriteImage(png))
+            }
+            ID_WATCH => Ok(Self::Watch),
+            ID_UNWATCH => Ok(Self::Unwatch),
             _ => {
                 #[cfg(not(feature = "log"))]
                 {
@@ -65,11 +101,20 @@ impl Command {
 const ID_TEXT: u8 = 0x0;
 const ID_FAILED: u8 = 0x1;
 const ID_WRITE_DONE: u8 = 0x2;
+const ID_IMAGE: u8 = 0x3;
+const ID_TEXT_CHANGED: u8 = 0x4;
+const ID_IMAGE_CHANGED: u8 = 0x5;
+const ID_UNWATCHED: u8 = 0x6;
 
 pub enum Response {
     Text(String),
     Failed,
     WriteDone,
+    Image(Vec<u8>),
+    TextChanged(String),
+    // size of the PNG file, the image itself is fetched with ReadImage
+    ImageChanged(u64),
+    Unwatched,
 }
 
 impl Response {
@@ -93,6 +138,28 @@ impl Response {
                 let buf = [ID_WRITE_DONE; 1];
                 stream.write_all(&buf)?;
             }
+            Self::Image(png) => {
+                let buf = [ID_IMAGE; 1];
+                stream.write_all(&buf)?;
+
+                util::serialize_bytes(stream, png)?;
+            }
+            Self::TextChanged(t) => {
+                let buf = [ID_TEXT_CHANGED; 1];
+                stream.write_all(&buf)?;
+
+                util::serialize_string(stream, t)?;
+            }
+            Self::ImageChanged(size) => {
+                let buf = [ID_IMAGE_CHANGED; 1];
+                stream.write_all(&buf)?;
+
+                stream.write_all(&size.to_le_bytes())?;
+            }
+            Self::Unwatched => {
+                let buf = [ID_UNWATCHED; 1];
+                stream.write_all(&buf)?;
+            }
         }
         stream.flush()
     }
@@ -112,6 +179,20 @@ impl Response {
             }
             ID_FAILED => Ok(Self::Failed),
             ID_WRITE_DONE => Ok(Self::WriteDone),
+            ID_IMAGE => {
+                let png = util::deserialize_bytes(stream)?;
+                Ok(Self::Image(png))
+            }
+            ID_TEXT_CHANGED => {
+                let t = util::deserialize_string(stream)?;
+                Ok(Self::TextChanged(t))
+            }
+            ID_IMAGE_CHANGED => {
+                let mut size = [0u8; 8];
+                stream.read_exact(&mut size)?;
+                Ok(Self::ImageChanged(u64::from_le_bytes(size)))
+            }
+            ID_UNWATCHED => Ok(Self::Unwatched),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "invalid response",
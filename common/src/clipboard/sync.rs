@@ -0,0 +1,229 @@
+use super::{changes, content::Content, protocol};
+#[cfg(feature = "log")]
+use crate::service;
+use crate::{api, channel, frontend, rdp};
+use std::{collections, io, thread, time};
+
+// The local clipboard is polled, the remote one being watched
+const POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+const RETRY_DELAY: time::Duration = time::Duration::from_secs(10);
+
+const DEFAULT_MAX_SIZE: usize = 1024 * 1024;
+
+struct Sync<'a> {
+    rdp_write: rdp::RdpWriter<'a>,
+    responses: crossbeam_channel::Receiver<Result<protocol::Response, io::Error>>,
+    // changes notified while waiting for the response to a command
+    changes: collections::VecDeque<protocol::Response>,
+    max_size: usize,
+    // tells when the local clipboard is worth reading
+    local_changes: changes::Changes,
+    // hash of the last local content seen or written
+    last: Option<u64>,
+}
+
+impl Sync<'_> {
+    fn receive(&self) -> Result<protocol::Response, io::Error> {
+        self.responses
+            .recv()
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?
+    }
+
+    fn request(&mut self, command: &protocol::Command) -> Result<protocol::Response, io::Error> {
+        command.send(&mut self.rdp_write)?;
+        loop {
+            match self.receive()? {
+                change @ (protocol::Response::TextChanged(_)
+                | protocol::Response::ImageChanged(_)) => self.changes.push_back(change),
+                response => return Ok(response),
+            }
+        }
+    }
+
+    // From the local clipboard to the remote one
+    fn push(&mut self) -> Result<(), io::Error> {
+        if !self.local_changes.changed() {
+            return Ok(());
+        }
+        let Some(content) = Content::current() else {
+            return Ok(());
+        };
+        let digest = content.digest();
+        if self.last.replace(digest) == Some(digest) {
+            return Ok(());
+        }
+
+        if self.max_size < content.size() {
+            crate::warn!(
+                "local clipboard not synchronized, too large ({} bytes)",
+                content.size()
+            );
+            return Ok(());
+        }
+
+        let command = match content {
+            Content::Text(text) => {
+                crate::debug!("local text changed");
+                protocol::Command::WriteText(text)
+            }
+            Content::Image(png) => {
+                crate::debug!("local image changed ({} bytes)", png.len());
+                protocol::Command::WriteImage(png)
+            }
+        };
+        match self.request(&command)? {
+            protocol::Response::WriteDone => Ok(()),
+            protocol::Response::Failed => {
+                crate::warn!("failed to set the remote clipboard");
+                Ok(())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected response",
+            )),
+        }
+    }
+
+    // From the remote clipboard to the local one
+    fn pull(&mut self, change: protocol::Response) -> Result<(), io::Error> {
+        let content = match change {
+            protocol::Response::TextChanged(text) if text.len() <= self.max_size => {
+                crate::debug!("remote text changed");
+                Content::Text(text)
+            }
+            protocol::Response::ImageChanged(size)
+                if usize::try_from(size).is_ok_and(|size| size <= self.max_size) =>
+            {
+                crate::debug!("remote image changed ({size} bytes)");
+                let protocol::Response::Image(png) = self.request(&protocol::Command::ReadImage)?
+                else {
+                    crate::warn!("failed to get the remote clipboard image");
+                    return Ok(());
+                };
+                Content::Image(png)
+            }
+            _ => {
+                crate::warn!("remote clipboard not synchronized, too large");
+                return Ok(());
+            }
+        };
+
+        if let Err(e) = content.write() {
+            crate::warn!("failed to set the local clipboard: {e}");
+            return Ok(());
+        }
+        // not to push it back, as read from the clipboard which may not
+        // give exactly what was written
+        self.last = Content::current().map(|content| content.digest());
+        Ok(())
+    }
+}
+
+fn run(
+    server: &frontend::FrontendTcpServer,
+    channel: &channel::Channel,
+    max_size: usize,
+) -> Result<(), io::Error> {
+    let rdp = channel.connect(&super::SERVICE)?;
+
+    #[cfg(feature = "log")]
+    let client_id = rdp.client_id();
+
+    let (mut rdp_read, rdp_write) = rdp.split();
+
+    // responses are received by a dedicated thread for the local
+    // clipboard to be polled meanwhile
+    let (responses_send, responses) = crossbeam_channel::unbounded();
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} {client_id:x} responses",
+            service::Kind::Frontend,
+            super::SERVICE,
+        ));
+        thread
+            .spawn_scoped(scope, move || {
+                loop {
+                    let response = protocol::Response::receive(&mut rdp_read);
+                    let failed = response.is_err();
+                    if responses_send.send(response).is_err() || failed {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+
+        // dropped on return, closing the stream for the thread above to stop
+        let mut sync = Sync {
+            rdp_write,
+            responses,
+            changes: collections::VecDeque::new(),
+            max_size,
+            local_changes: changes::Changes::new(),
+            // only the changes from now on are synchronized
+            last: Content::current().map(|content| content.digest()),
+        };
+
+        protocol::Command::Watch.send(&mut sync.rdp_write)?;
+
+        while !server.is_stopped() {
+            if let Some(change) = sync.changes.pop_front() {
+                sync.pull(change)?;
+                continue;
+            }
+
+            match sync.responses.recv_timeout(POLL_INTERVAL) {
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => sync.push()?,
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "disconnected"));
+                }
+                Ok(response) => sync.pull(response?)?,
+            }
+        }
+
+        Ok(())
+    })
+}
+
+// Mirrors the local clipboard to and from the remote one if the "sync"
+// setting is true, contents larger than the "sync_max_size" setting (in
+// bytes) being ignored
+#[allow(clippy::unnecessary_wraps)]
+pub fn background(
+    server: &frontend::FrontendTcpServer,
+    channel: &channel::Channel,
+) -> Result<(), api::Error> {
+    let settings = server.settings();
+
+    if !settings.get_bool("sync") {
+        return Ok(());
+    }
+
+    let max_size = settings
+        .get("sync_max_size")
+        .and_then(|size| {
+            size.parse()
+                .inspect_err(|_| crate::warn!("invalid sync_max_size {size:?}"))
+                .ok()
+        })
+        .unwrap_or(DEFAULT_MAX_SIZE);
+
+    crate::info!("synchronizing clipboards");
+
+    // until the frontend stops accepting clients
+    while !server.is_stopped() {
+        if let Err(e) = run(server, channel, max_size) {
+            crate::debug!("synchronization stopped: {e}");
+        }
+
+        let retry = time::Instant::now() + RETRY_DELAY;
+        while !server.is_stopped() && time::Instant::now() < retry {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    Ok(())
+}
@@ -1,24 +1,93 @@
-use crate::{rdp, service};
-use std::{io, process, thread};
+use super::{protocol, pty, session};
+use crate::{api, rdp, service, util};
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+use std::{
+    env,
+    io::{self, Write},
+    process, sync, thread, time,
+};
 
-pub fn backend_handler(rdp_stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
-    #[cfg(feature = "log")]
-    let client_id = rdp_stream.client_id();
-    #[cfg(target_os = "windows")]
-    let cmd = "cmd.exe";
-    #[cfg(target_os = "windows")]
-    let args: [String; 0] = [];
+// Exit code reported when the process is killed on timeout, as the
+// timeout utility does
+const TIMEOUT_EXIT_CODE: i32 = 124;
 
+const WAIT_PERIOD: time::Duration = time::Duration::from_millis(100);
+
+// Absolute path of the plain shell, for it to be listed in an allowlist
+fn plain_shell() -> String {
+    #[cfg(target_os = "windows")]
+    {
+        env::var("ComSpec").unwrap_or_else(|_| "cmd.exe".to_string())
+    }
     #[cfg(not(target_os = "windows"))]
-    let cmd = "sh";
-    #[cfg(not(target_os = "windows"))]
-    let args = ["-i"];
+    {
+        "/bin/sh".to_string()
+    }
+}
+
+// Without program in the launch profile, cmd.exe is started on Windows
+// and, on Unix, the shell of the user (or sh) in a pseudo-terminal, sh
+// otherwise
+#[cfg_attr(target_os = "windows", allow(unused_variables))]
+fn with_default_shell(mut program: protocol::Program, pty: bool) -> protocol::Program {
+    if program.path.is_empty() {
+        #[cfg(target_os = "windows")]
+        {
+            program.path = plain_shell();
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            program.path = env::var("SHELL")
+                .ok()
+                .filter(|_| pty)
+                .unwrap_or_else(plain_shell);
+            if program.args.is_empty() {
+                program.args = vec!["-i".to_string()];
+            }
+        }
+    }
+    program
+}
 
-    crate::debug!("starting {cmd:?}");
+// With an allowlist, the client cannot change what the allowed program
+// does: the environment variables it sends are ignored and setting the
+// working directory is refused; returns why the program is refused
+fn check(path: &str, env: &mut Vec<(String, String)>, cwd: Option<&String>) -> Result<(), String> {
+    if !util::is_command_allowed(path) {
+        return Err(format!("{path:?} is not allowed"));
+    }
+    if util::command_allowlist().is_some() {
+        if cwd.is_some() {
+            return Err("working directory is not allowed".to_string());
+        }
+        if !env.is_empty() {
+            crate::warn!("environment variables of {path:?} ignored");
+            env.clear();
+        }
+    }
+    Ok(())
+}
+
+// Tells the client why nothing was started
+fn refuse(mut rdp_stream: rdp::RdpStream<'_>, reason: &str) -> Result<(), io::Error> {
+    crate::warn!("{reason}");
+    write!(rdp_stream, "{reason}\r\n")?;
+    rdp_stream.flush()
+}
+
+fn pipes(rdp_stream: rdp::RdpStream<'_>, program: &protocol::Program) -> Result<(), io::Error> {
+    #[cfg(feature = "log")]
+    let client_id = rdp_stream.client_id();
+
+    crate::debug!("starting {:?} {:?}", program.path, program.args);
 
     thread::scope(|scope| {
-        let child = process::Command::new(cmd)
-            .args(args)
+        let child = process::Command::new(&program.path)
+            .args(&program.args)
+            .envs(program.env.iter().map(|(name, value)| (name, value)))
             .stdin(process::Stdio::piped())
             .stdout(process::Stdio::piped())
             .stderr(process::Stdio::piped())
@@ -80,3 +149,347 @@ pub fn backend_handler(rdp_stream: rdp::RdpStream<'_>) -> Result<(), io::Error>
         Ok(())
     })
 }
+
+fn pty(
+    rdp_stream: rdp::RdpStream<'_>,
+    terminal: &protocol::Terminal,
+    program: &protocol::Program,
+) -> Result<(), io::Error> {
+    #[cfg(feature = "log")]
+    let client_id = rdp_stream.client_id();
+
+    let (pty, mut output, mut input) = pty::Pty::spawn(program, &terminal.term, terminal.size)?;
+
+    let (mut rdp_stream_read, mut rdp_stream_write) = rdp_stream.split();
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} {client_id:x} output",
+            service::Kind::Backend,
+            super::SERVICE,
+        ));
+        thread.spawn_scoped(scope, move || {
+            if let Err(e) = service::stream_copy(&mut output, &mut rdp_stream_write, true) {
+                crate::debug!("error: {e}");
+            } else {
+                crate::debug!("stopped");
+            }
+        })?;
+
+        // until the frontend disconnects
+        while let Ok(message) = protocol::Input::receive(&mut rdp_stream_read) {
+            match message {
+                protocol::Input::Data(data) => {
+                    if let Err(e) = input.write_all(&data) {
+                        crate::debug!("error: {e}");
+                        break;
+                    }
+                }
+                protocol::Input::Resize(size) => {
+                    crate::debug!("resize to {size:?}");
+                    if let Err(e) = pty.resize(size) {
+                        crate::warn!("failed to resize: {e}");
+                    }
+                }
+            }
+        }
+
+        drop(rdp_stream_read);
+        pty.close();
+
+        Ok(())
+    })
+}
+
+// Attaches to the named session, started if it does not exist yet;
+// the session is detached, and not killed, when the stream ends
+fn attach(rdp_stream: rdp::RdpStream<'_>, request: protocol::Attach) -> Result<(), io::Error> {
+    let client_id = rdp_stream.client_id();
+
+    let session = if let Some(session) = session::find(&request.name) {
+        session
+    } else {
+        let mut program = with_default_shell(request.program, true);
+        if let Err(reason) = check(&program.path, &mut program.env, None) {
+            return refuse(rdp_stream, &reason);
+        }
+        session::start(&request.name, &request.terminal, &program, request.timeout)?
+    };
+
+    let output = session.attach(client_id, request.terminal.size);
+
+    let (mut rdp_stream_read, mut rdp_stream_write) = rdp_stream.split();
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} {client_id:x} output",
+            service::Kind::Backend,
+            super::SERVICE,
+        ));
+        // until the program exits or another client attaches
+        thread.spawn_scoped(scope, move || {
+            for data in output {
+                if let Err(e) = rdp_stream_write
+                    .write_all(&data)
+                    .and_then(|()| rdp_stream_write.flush())
+                {
+                    crate::debug!("error: {e}");
+                    break;
+                }
+            }
+            crate::debug!("stopped");
+        })?;
+
+        // until the frontend disconnects
+        while let Ok(message) = protocol::Input::receive(&mut rdp_stream_read) {
+            match message {
+                protocol::Input::Data(data) => {
+                    if let Err(e) = session.write(&data) {
+                        crate::debug!("error: {e}");
+                        break;
+                    }
+                }
+                protocol::Input::Resize(size) => {
+                    crate::debug!("resize to {size:?}");
+                    session.resize(size);
+                }
+            }
+        }
+
+        drop(rdp_stream_read);
+        session.detach(client_id);
+
+        Ok(())
+    })
+}
+
+// Kills the process and, on Unix, the other processes of its group
+fn kill(child: &mut process::Child) -> Result<(), io::Error> {
+    #[cfg(not(target_os = "windows"))]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        unsafe { libc::kill(-pid, libc::SIGKILL) };
+    }
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+// Waits for the process to exit, killing it once the timeout (in
+// seconds) expires; returns None in that case
+fn wait(
+    child: &mut process::Child,
+    timeout: Option<u32>,
+) -> Result<Option<process::ExitStatus>, io::Error> {
+    let Some(timeout) = timeout else {
+        return child.wait().map(Some);
+    };
+
+    let deadline = time::Instant::now() + time::Duration::from_secs(u64::from(timeout));
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if deadline <= time::Instant::now() {
+            crate::info!("timeout expired, killing the process");
+            kill(child)?;
+            return Ok(None);
+        }
+        thread::sleep(WAIT_PERIOD);
+    }
+}
+
+// Processes killed by a signal are given the shell convention code
+fn exit_code(status: process::ExitStatus) -> i32 {
+    #[cfg(not(target_os = "windows"))]
+    if let Some(signal) = status.signal() {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(-1)
+}
+
+fn forward_output<R>(
+    mut output: R,
+    rdp_stream_write: &sync::Mutex<rdp::RdpWriter<'_>>,
+    message: fn(Vec<u8>) -> protocol::Output,
+) -> Result<(), io::Error>
+where
+    R: io::Read,
+{
+    let mut buf = vec![0u8; api::Chunk::max_payload_length()];
+    loop {
+        let read = output.read(&mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        let mut rdp_stream_write = rdp_stream_write.lock().unwrap();
+        message(buf[..read].to_vec()).send(&mut *rdp_stream_write)?;
+    }
+}
+
+// Without program, the arguments form a command line run by the
+// plain shell, which must then be allowed itself; returns the path of
+// the program with the command
+fn exec_command(program: &protocol::Program) -> (String, process::Command) {
+    if !program.path.is_empty() {
+        let mut command = process::Command::new(&program.path);
+        command.args(&program.args);
+        return (program.path.clone(), command);
+    }
+
+    let line = program.args.join(" ");
+    let shell = plain_shell();
+
+    let mut command = process::Command::new(&shell);
+    #[cfg(target_os = "windows")]
+    {
+        // cmd.exe has its own quoting rules, the line is passed as is
+        command.arg("/c").raw_arg(line);
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        command.arg("-c").arg(line);
+    }
+    (shell, command)
+}
+
+fn exec(mut rdp_stream: rdp::RdpStream<'_>, mut exec: protocol::Exec) -> Result<(), io::Error> {
+    #[cfg(feature = "log")]
+    let client_id = rdp_stream.client_id();
+
+    crate::debug!("executing {:?} {:?}", exec.program.path, exec.program.args);
+
+    let (path, mut command) = exec_command(&exec.program);
+
+    if let Err(reason) = check(&path, &mut exec.program.env, exec.cwd.as_ref()) {
+        crate::warn!("{reason}");
+        return protocol::Output::Error(reason).send(&mut rdp_stream);
+    }
+
+    command
+        .envs(exec.program.env.iter().map(|(name, value)| (name, value)))
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped());
+    if let Some(cwd) = &exec.cwd {
+        command.current_dir(cwd);
+    }
+    // so that the whole group can be killed on timeout
+    #[cfg(not(target_os = "windows"))]
+    command.process_group(0);
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            crate::debug!("failed to execute {path:?}: {e}");
+            return protocol::Output::Error(e.to_string()).send(&mut rdp_stream);
+        }
+    };
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no stdin"))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no stdout"))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no stderr"))?;
+
+    let (mut rdp_stream_read, rdp_stream_write) = rdp_stream.split();
+    let rdp_stream_write = sync::Mutex::new(rdp_stream_write);
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} {client_id:x} stdin",
+            service::Kind::Backend,
+            super::SERVICE,
+        ));
+        // until the frontend closes the standard input
+        thread.spawn_scoped(scope, move || {
+            if let Err(e) = service::stream_copy(&mut rdp_stream_read, &mut stdin, true) {
+                crate::debug!("error: {e}");
+            } else {
+                crate::debug!("stopped");
+            }
+        })?;
+
+        let rdp_stream_write_out = &rdp_stream_write;
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} {client_id:x} stdout",
+            service::Kind::Backend,
+            super::SERVICE,
+        ));
+        let stdout = thread.spawn_scoped(scope, move || {
+            if let Err(e) = forward_output(stdout, rdp_stream_write_out, protocol::Output::Stdout) {
+                crate::debug!("error: {e}");
+            } else {
+                crate::debug!("stopped");
+            }
+        })?;
+
+        let rdp_stream_write_err = &rdp_stream_write;
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} {client_id:x} stderr",
+            service::Kind::Backend,
+            super::SERVICE,
+        ));
+        let stderr = thread.spawn_scoped(scope, move || {
+            if let Err(e) = forward_output(stderr, rdp_stream_write_err, protocol::Output::Stderr) {
+                crate::debug!("error: {e}");
+            } else {
+                crate::debug!("stopped");
+            }
+        })?;
+
+        let status = wait(&mut child, exec.timeout)?;
+
+        // all the output is sent before the exit code
+        let _ = stdout.join();
+        let _ = stderr.join();
+
+        let code = status.map_or(TIMEOUT_EXIT_CODE, exit_code);
+        crate::debug!("exited with code {code}");
+
+        let mut rdp_stream_write = rdp_stream_write.lock().unwrap();
+        protocol::Output::Exit(code).send(&mut *rdp_stream_write)
+    })
+}
+
+pub fn backend_handler(mut rdp_stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
+    match protocol::Start::receive(&mut rdp_stream)? {
+        protocol::Start::Pipes(program) => {
+            let mut program = with_default_shell(program, false);
+            if let Err(reason) = check(&program.path, &mut program.env, None) {
+                return refuse(rdp_stream, &reason);
+            }
+            pipes(rdp_stream, &program)
+        }
+        protocol::Start::Pty(terminal, program) => {
+            let mut program = with_default_shell(program, true);
+            if let Err(reason) = check(&program.path, &mut program.env, None) {
+                return refuse(rdp_stream, &reason);
+            }
+            pty(rdp_stream, &terminal, &program)
+        }
+        protocol::Start::Exec(command) => exec(rdp_stream, command),
+        protocol::Start::Attach(request) => attach(rdp_stream, request),
+        protocol::Start::List => protocol::Sessions(session::list()).send(&mut rdp_stream),
+        protocol::Start::Kill(name) => {
+            protocol::Sessions(session::kill(&name).into_iter().collect()).send(&mut rdp_stream)
+        }
+    }
+}
@@ -1,18 +1,459 @@
-use crate::{api, channel, frontend, service};
-use std::{net, thread};
+use super::{protocol, record, telnet};
+use crate::{api, channel, frontend, rdp, service};
+use std::{
+    io::{self, BufRead, Read, Write},
+    net, thread, time,
+};
 
+#[cfg(feature = "log")]
+const SERVICE_KIND: service::Kind = service::Kind::Frontend;
+
+// Time given to the telnet client to send its terminal type and window
+// size before starting the shell
+const NEGOTIATION_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+const DEFAULT_TERM: &str = "vt100";
+
+const INPUT_BUFFER_SIZE: usize = 4096;
+
+// Negotiates telnet options, returns the terminal and the input
+// already typed
+fn negotiate(
+    client: &mut net::TcpStream,
+    parser: &mut telnet::Parser,
+) -> Result<(protocol::Terminal, Vec<u8>), io::Error> {
+    client.write_all(telnet::NEGOTIATION)?;
+    client.set_read_timeout(Some(NEGOTIATION_TIMEOUT))?;
+
+    let (mut term, mut size) = (None, None);
+    let (mut term_done, mut size_done) = (false, false);
+    let mut typed = vec![];
+
+    let mut buf = [0u8; INPUT_BUFFER_SIZE];
+
+    while !(term_done && size_done) {
+        let read = match client.read(&mut buf) {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break;
+            }
+            Err(e) => return Err(e),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(read) => read,
+        };
+
+        for event in parser.parse(&buf[..read]) {
+            match event {
+                telnet::Event::Data(data) => typed.extend(data),
+                telnet::Event::Will(telnet::OPTION_TTYPE) => {
+                    client.write_all(telnet::TTYPE_REQUEST)?;
+                }
+                telnet::Event::Wont(telnet::OPTION_TTYPE) => term_done = true,
+                telnet::Event::Wont(telnet::OPTION_NAWS) => size_done = true,
+                telnet::Event::Will(_) | telnet::Event::Wont(_) => (),
+                telnet::Event::Term(value) => {
+                    term = Some(value);
+                    term_done = true;
+                }
+                telnet::Event::Size(value) => {
+                    size = Some(value);
+                    size_done = true;
+                }
+            }
+        }
+    }
+
+    client.set_read_timeout(None)?;
+
+    let terminal = protocol::Terminal {
+        term: term.unwrap_or_else(|| DEFAULT_TERM.to_string()),
+        size: size.unwrap_or_default(),
+    };
+
+    Ok((terminal, typed))
+}
+
+fn pty<F>(
+    mut client: net::TcpStream,
+    mut rdp: rdp::RdpStream<'_>,
+    settings: &frontend::FrontendSettings,
+    start: F,
+) -> Result<(), io::Error>
+where
+    F: FnOnce(protocol::Terminal) -> protocol::Start,
+{
+    let mut parser = telnet::Parser::default();
+
+    let (terminal, typed) = negotiate(&mut client, &mut parser)?;
+
+    crate::debug!("{terminal:?}");
+
+    let recorder =
+        record::Recorder::new(settings, &super::SERVICE, rdp.client_id(), Some(&terminal));
+
+    start(terminal).send(&mut rdp)?;
+    if !typed.is_empty() {
+        recorder.record(record::Stream::Input, &typed);
+        protocol::Input::Data(typed).send(&mut rdp)?;
+    }
+
+    let (mut rdp_read, mut rdp_write) = rdp.split();
+    let mut client_write = client.try_clone()?;
+
+    let recorder = &recorder;
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!("{SERVICE_KIND} {} output", super::SERVICE));
+        thread.spawn_scoped(scope, move || {
+            let mut buf = vec![0u8; api::Chunk::max_payload_length()];
+            loop {
+                match rdp_read.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => {
+                        recorder.record(record::Stream::Output, &buf[..read]);
+                        if client_write
+                            .write_all(&telnet::escape(&buf[..read]))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+            // the shell exited
+            let _ = client_write.shutdown(net::Shutdown::Both);
+        })?;
+
+        let mut buf = [0u8; INPUT_BUFFER_SIZE];
+        loop {
+            // also fails once the output thread shut the connection down
+            let read = match client.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            for event in parser.parse(&buf[..read]) {
+                match event {
+                    telnet::Event::Data(data) => {
+                        recorder.record(record::Stream::Input, &data);
+                        protocol::Input::Data(data).send(&mut rdp_write)?;
+                    }
+                    telnet::Event::Size(size) => {
+                        recorder.resize(size);
+                        protocol::Input::Resize(size).send(&mut rdp_write)?;
+                    }
+                    telnet::Event::Will(_) | telnet::Event::Wont(_) | telnet::Event::Term(_) => (),
+                }
+            }
+        }
+
+        drop(rdp_write);
+
+        Ok(())
+    })
+}
+
+// Applies a "<key> <value>" line of an exec request
+fn parse_exec_line(exec: &mut protocol::Exec, line: &str) -> Result<(), String> {
+    let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+    match key {
+        "program" => value.clone_into(&mut exec.program.path),
+        "arg" => exec.program.args.push(value.to_string()),
+        "env" => {
+            let (name, value) = value
+                .split_once('=')
+                .ok_or_else(|| format!("invalid environment variable {value:?}"))?;
+            exec.program.env.push((name.to_string(), value.to_string()));
+        }
+        "cwd" => exec.cwd = Some(value.to_string()),
+        "timeout" => {
+            let timeout = value
+                .parse()
+                .map_err(|_| format!("invalid timeout {value:?}"))?;
+            exec.timeout = Some(timeout).filter(|timeout| *timeout != 0);
+        }
+        _ => return Err(format!("unknown key {key:?}")),
+    }
+    Ok(())
+}
+
+fn write_output(client: &mut net::TcpStream, name: &str, data: &[u8]) -> Result<(), io::Error> {
+    writeln!(client, "{name} {}", data.len())?;
+    client.write_all(data)
+}
+
+// The request is made of "<key> <value>" lines ended by an empty line,
+// it is followed by the standard input; the answer is made of
+// "stdout <length>" and "stderr <length>" lines each followed by the
+// data, and ends with an "exit <code>" or "error <message>" line
+fn exec(
+    client: net::TcpStream,
+    mut rdp: rdp::RdpStream<'_>,
+    settings: &frontend::FrontendSettings,
+) -> Result<(), io::Error> {
+    let mut client_read = io::BufReader::new(client.try_clone()?);
+    let mut client_write = client;
+
+    let mut request = protocol::Exec::default();
+    loop {
+        let mut line = String::new();
+        if client_read.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        if let Err(e) = parse_exec_line(&mut request, line) {
+            return writeln!(client_write, "error {e}");
+        }
+    }
+    if request.program.path.is_empty() {
+        return writeln!(client_write, "error missing program");
+    }
+
+    crate::debug!("{request:?}");
+
+    let recorder = record::Recorder::new(settings, &super::SERVICE, rdp.client_id(), None);
+
+    protocol::Start::Exec(request).send(&mut rdp)?;
+
+    let (mut rdp_read, mut rdp_write) = rdp.split();
+
+    let recorder = &recorder;
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!("{SERVICE_KIND} {} stdin", super::SERVICE));
+        thread.spawn_scoped(scope, move || {
+            // until the client closes its side of the connection
+            let mut client_read = recorder.reader(client_read, record::Stream::Input);
+            if let Err(e) = service::stream_copy(&mut client_read, &mut rdp_write, true) {
+                crate::debug!("error: {e}");
+            }
+        })?;
+
+        let result = loop {
+            let result = match protocol::Output::receive(&mut rdp_read) {
+                Ok(protocol::Output::Stdout(data)) => {
+                    recorder.record(record::Stream::Output, &data);
+                    write_output(&mut client_write, "stdout", &data)
+                }
+                Ok(protocol::Output::Stderr(data)) => {
+                    recorder.record(record::Stream::Output, &data);
+                    write_output(&mut client_write, "stderr", &data)
+                }
+                Ok(protocol::Output::Exit(code)) => {
+                    break writeln!(client_write, "exit {code}");
+                }
+                Ok(protocol::Output::Error(e)) => break writeln!(client_write, "error {e}"),
+                Err(e) => break Err(e),
+            };
+            if result.is_err() {
+                break result;
+            }
+        };
+
+        // also stops the standard input thread
+        let _ = client_write.shutdown(net::Shutdown::Both);
+
+        result
+    })
+}
+
+fn pipes(
+    client: net::TcpStream,
+    mut rdp: rdp::RdpStream<'_>,
+    settings: &frontend::FrontendSettings,
+) -> Result<(), io::Error> {
+    let recorder = record::Recorder::new(settings, &super::SERVICE, rdp.client_id(), None);
+
+    protocol::Start::Pipes(profile(settings)).send(&mut rdp)?;
+
+    let (rdp_read, mut rdp_write) = rdp.split();
+    let mut client_write = client.try_clone()?;
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!("{SERVICE_KIND} {} output", super::SERVICE));
+        thread.spawn_scoped(scope, || {
+            let mut rdp_read = recorder.reader(rdp_read, record::Stream::Output);
+            if let Err(e) = service::stream_copy(&mut rdp_read, &mut client_write, true) {
+                crate::debug!("error: {e}");
+            }
+            // the shell exited
+            let _ = client_write.shutdown(net::Shutdown::Both);
+        })?;
+
+        let mut client_read = recorder.reader(client, record::Stream::Input);
+        if let Err(e) = service::stream_copy(&mut client_read, &mut rdp_write, true) {
+            crate::debug!("error: {e}");
+        }
+
+        drop(rdp_write);
+
+        Ok(())
+    })
+}
+
+const SESSIONS_HELP: &str = r#"Available commands:
+- "list" or "ls" to list the sessions;
+- "attach NAME" to attach to a session, started if it does not exist;
+- "kill NAME" to kill a session;
+- "exit" or "quit" to exit this interface.
+Closing the connection detaches from a session without killing it.
+"#;
+
+const SESSIONS_PROMPT: &str = "session> ";
+
+fn write_sessions(
+    client: &mut net::TcpStream,
+    sessions: &[protocol::SessionInfo],
+) -> Result<(), io::Error> {
+    for session in sessions {
+        writeln!(
+            client,
+            "{}\t{}\t{:02}:{:02}:{:02}\t{}",
+            session.name,
+            if session.attached {
+                "attached"
+            } else {
+                "detached"
+            },
+            session.age / 3600,
+            (session.age % 3600) / 60,
+            session.age % 60,
+            session.program
+        )?;
+    }
+    Ok(())
+}
+
+// Lists, kills or attaches to the named sessions kept by the backend;
+// the "session_timeout" setting is the time (in seconds) after which a
+// detached session is killed
+fn sessions(
+    client: net::TcpStream,
+    channel: &channel::Channel,
+    settings: &frontend::FrontendSettings,
+) -> Result<(), io::Error> {
+    let mut client_read = io::BufReader::new(client.try_clone()?);
+    let mut client_write = client.try_clone()?;
+
+    client_write.write_all(SESSIONS_HELP.as_bytes())?;
+
+    let mut line = String::new();
+
+    loop {
+        client_write.write_all(SESSIONS_PROMPT.as_bytes())?;
+
+        line.clear();
+        if client_read.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let line = line.trim();
+        let (command, name) = line
+            .split_once(' ')
+            .map_or((line, ""), |(command, name)| (command, name.trim()));
+
+        crate::debug!("{line:?}");
+
+        match command.to_lowercase().as_str() {
+            "" => (),
+            "list" | "ls" => {
+                let mut rdp = channel.connect(&super::SERVICE)?;
+                protocol::Start::List.send(&mut rdp)?;
+                let protocol::Sessions(sessions) = protocol::Sessions::receive(&mut rdp)?;
+                if sessions.is_empty() {
+                    writeln!(client_write, "no session")?;
+                }
+                write_sessions(&mut client_write, &sessions)?;
+            }
+            "attach" if !name.is_empty() => {
+                let timeout = settings.get("session_timeout").and_then(|timeout| {
+                    timeout
+                        .parse()
+                        .inspect_err(|_| crate::warn!("invalid session_timeout {timeout:?}"))
+                        .ok()
+                });
+                let rdp = channel.connect(&super::SERVICE)?;
+                return pty(client, rdp, settings, |terminal| {
+                    protocol::Start::Attach(protocol::Attach {
+                        name: name.to_string(),
+                        terminal,
+                        program: profile(settings),
+                        timeout,
+                    })
+                });
+            }
+            "kill" if !name.is_empty() => {
+                let mut rdp = channel.connect(&super::SERVICE)?;
+                protocol::Start::Kill(name.to_string()).send(&mut rdp)?;
+                let protocol::Sessions(sessions) = protocol::Sessions::receive(&mut rdp)?;
+                if sessions.is_empty() {
+                    writeln!(client_write, "no session {name:?}")?;
+                } else {
+                    writeln!(client_write, "killed")?;
+                }
+            }
+            "exit" | "quit" => {
+                let _ = client.shutdown(net::Shutdown::Both);
+                return Ok(());
+            }
+            _ => writeln!(client_write, "invalid command")?,
+        }
+    }
+}
+
+// Launch profile of the service, with the "program", "args" and "env"
+// settings; the backend starts its default shell if there is no program
+fn profile(settings: &frontend::FrontendSettings) -> protocol::Program {
+    protocol::Program {
+        path: settings.get("program").unwrap_or_default().to_string(),
+        args: settings.get_all("args").to_vec(),
+        env: settings
+            .keys()
+            .filter_map(|key| Some((key.strip_prefix("env.")?, settings.get(key)?)))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+// The shell is attached to pipes and the connection is a raw one,
+// unless the "pty" setting is true, then a pseudo-terminal is used; if
+// the "exec" setting is true, a single program is executed per
+// connection instead, and if the "sessions" setting is true, the shell
+// is a named session kept by the backend; sessions are recorded if the
+// "record_dir" setting is set
 pub fn tcp_frontend_handler(
-    _server: &frontend::FrontendTcpServer,
+    server: &frontend::FrontendTcpServer,
     _scope: &thread::Scope,
     client: net::TcpStream,
     channel: &channel::Channel,
 ) -> Result<(), api::Error> {
+    let settings = server.settings();
+    if settings.get_bool("sessions") {
+        return Ok(sessions(client, channel, settings)?);
+    }
+
     let client_rdp = channel.connect(&super::SERVICE)?;
-    Ok(service::double_stream_copy(
-        service::Kind::Frontend,
-        &super::SERVICE,
-        client_rdp,
-        client,
-        true,
-    )?)
+
+    if settings.get_bool("exec") {
+        return Ok(exec(client, client_rdp, settings)?);
+    }
+    if settings.get_bool("pty") {
+        return Ok(pty(client, client_rdp, settings, |terminal| {
+            protocol::Start::Pty(terminal, profile(settings))
+        })?);
+    }
+
+    Ok(pipes(client, client_rdp, settings)?)
 }
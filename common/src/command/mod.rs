@@ -6,6 +6,22 @@ use crate::service;
 mod backend;
 #[cfg(feature = "frontend")]
 mod frontend;
+pub mod protocol;
+#[cfg(all(feature = "backend", not(target_os = "windows")))]
+mod pty_unix;
+#[cfg(all(feature = "backend", target_os = "windows"))]
+mod pty_windows;
+#[cfg(feature = "frontend")]
+mod record;
+#[cfg(feature = "backend")]
+mod session;
+#[cfg(feature = "frontend")]
+mod telnet;
+
+#[cfg(all(feature = "backend", not(target_os = "windows")))]
+use pty_unix as pty;
+#[cfg(all(feature = "backend", target_os = "windows"))]
+use pty_windows as pty;
 
 pub static SERVICE: service::Service = service::Service {
     internal: false,
@@ -15,7 +31,9 @@ pub static SERVICE: service::Service = service::Service {
         tcp: Some(sfrontend::FrontendTcp {
             default_port: 3031,
             handler: frontend::tcp_frontend_handler,
+            background: None,
         }),
+        udp: None,
     }),
     #[cfg(feature = "backend")]
     backend: Some(service::Backend {
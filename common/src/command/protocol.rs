@@ -0,0 +1,486 @@
+use crate::util;
+use std::io;
+
+const ID_START_PIPES: u8 = 0x00;
+const ID_START_PTY: u8 = 0x01;
+const ID_START_EXEC: u8 = 0x02;
+const ID_START_ATTACH: u8 = 0x03;
+const ID_START_LIST: u8 = 0x04;
+const ID_START_KILL: u8 = 0x05;
+
+const ID_INPUT_DATA: u8 = 0x00;
+const ID_INPUT_RESIZE: u8 = 0x01;
+
+const ID_OUTPUT_STDOUT: u8 = 0x00;
+const ID_OUTPUT_STDERR: u8 = 0x01;
+const ID_OUTPUT_EXIT: u8 = 0x02;
+const ID_OUTPUT_ERROR: u8 = 0x03;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Size {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Default for Size {
+    fn default() -> Self {
+        Self { cols: 80, rows: 24 }
+    }
+}
+
+impl Size {
+    #[cfg(feature = "frontend")]
+    fn send<W>(self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        stream.write_all(&self.cols.to_le_bytes())?;
+        stream.write_all(&self.rows.to_le_bytes())
+    }
+
+    #[cfg(feature = "backend")]
+    fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf)?;
+        Ok(Self {
+            cols: u16::from_le_bytes([buf[0], buf[1]]),
+            rows: u16::from_le_bytes([buf[2], buf[3]]),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Terminal {
+    // value of the TERM environment variable
+    pub term: String,
+    pub size: Size,
+}
+
+impl Terminal {
+    #[cfg(feature = "frontend")]
+    fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        util::serialize_string(stream, &self.term)?;
+        self.size.send(stream)
+    }
+
+    #[cfg(feature = "backend")]
+    fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let term = util::deserialize_string(stream)?;
+        let size = Size::receive(stream)?;
+        Ok(Self { term, size })
+    }
+}
+
+// Program to start, with its arguments and additional environment
+// variables; an empty path stands for the default shell of the backend
+#[derive(Debug, Default)]
+pub struct Program {
+    pub path: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+}
+
+impl Program {
+    #[cfg(feature = "frontend")]
+    fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        util::serialize_string(stream, &self.path)?;
+        serialize_len(stream, self.args.len())?;
+        for arg in &self.args {
+            util::serialize_string(stream, arg)?;
+        }
+        serialize_len(stream, self.env.len())?;
+        for (name, value) in &self.env {
+            util::serialize_string(stream, name)?;
+            util::serialize_string(stream, value)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "backend")]
+    fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let path = util::deserialize_string(stream)?;
+        let args = (0..deserialize_len(stream)?)
+            .map(|_| util::deserialize_string(stream))
+            .collect::<Result<_, _>>()?;
+        let env = (0..deserialize_len(stream)?)
+            .map(|_| {
+                Ok::<_, io::Error>((
+                    util::deserialize_string(stream)?,
+                    util::deserialize_string(stream)?,
+                ))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { path, args, env })
+    }
+}
+
+// Without program path, the arguments form a command line run by the
+// default shell of the backend
+#[derive(Debug, Default)]
+pub struct Exec {
+    pub program: Program,
+    pub cwd: Option<String>,
+    // in seconds
+    pub timeout: Option<u32>,
+}
+
+impl Exec {
+    #[cfg(feature = "frontend")]
+    fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        self.program.send(stream)?;
+        util::serialize_string(stream, self.cwd.as_deref().unwrap_or_default())?;
+        stream.write_all(&self.timeout.unwrap_or(0).to_le_bytes())
+    }
+
+    #[cfg(feature = "backend")]
+    fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let program = Program::receive(stream)?;
+        let cwd = Some(util::deserialize_string(stream)?).filter(|cwd| !cwd.is_empty());
+        let mut timeout = [0u8; 4];
+        stream.read_exact(&mut timeout)?;
+        let timeout = Some(u32::from_le_bytes(timeout)).filter(|timeout| *timeout != 0);
+        Ok(Self {
+            program,
+            cwd,
+            timeout,
+        })
+    }
+}
+
+// Named session kept by the backend when the stream ends, created with
+// the program if it does not exist; it is killed once detached for
+// longer than the timeout (in seconds)
+#[derive(Debug)]
+pub struct Attach {
+    pub name: String,
+    pub terminal: Terminal,
+    pub program: Program,
+    pub timeout: Option<u32>,
+}
+
+impl Attach {
+    #[cfg(feature = "frontend")]
+    fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        util::serialize_string(stream, &self.name)?;
+        self.terminal.send(stream)?;
+        self.program.send(stream)?;
+        stream.write_all(&self.timeout.unwrap_or(0).to_le_bytes())
+    }
+
+    #[cfg(feature = "backend")]
+    fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let name = util::deserialize_string(stream)?;
+        let terminal = Terminal::receive(stream)?;
+        let program = Program::receive(stream)?;
+        let mut timeout = [0u8; 4];
+        stream.read_exact(&mut timeout)?;
+        let timeout = Some(u32::from_le_bytes(timeout)).filter(|timeout| *timeout != 0);
+        Ok(Self {
+            name,
+            terminal,
+            program,
+            timeout,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionInfo {
+    pub name: String,
+    pub program: String,
+    pub attached: bool,
+    // in seconds since the start of the session
+    pub age: u32,
+}
+
+// Answer of the backend to List, and to Kill with the killed session
+// if it existed
+#[derive(Debug)]
+pub struct Sessions(pub Vec<SessionInfo>);
+
+impl Sessions {
+    #[cfg(feature = "backend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let len = u16::try_from(self.0.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        stream.write_all(&len.to_le_bytes())?;
+        for session in &self.0 {
+            util::serialize_string(stream, &session.name)?;
+            util::serialize_string(stream, &session.program)?;
+            stream.write_all(&[u8::from(session.attached)])?;
+            stream.write_all(&session.age.to_le_bytes())?;
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len)?;
+        let sessions = (0..u16::from_le_bytes(len))
+            .map(|_| {
+                let name = util::deserialize_string(stream)?;
+                let program = util::deserialize_string(stream)?;
+                let mut attached = [0u8; 1];
+                stream.read_exact(&mut attached)?;
+                let mut age = [0u8; 4];
+                stream.read_exact(&mut age)?;
+                Ok(SessionInfo {
+                    name,
+                    program,
+                    attached: attached[0] != 0,
+                    age: u32::from_le_bytes(age),
+                })
+            })
+            .collect::<Result<_, io::Error>>()?;
+        Ok(Self(sessions))
+    }
+}
+
+#[cfg(feature = "frontend")]
+fn serialize_len<W>(stream: &mut W, len: usize) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    let len = u16::try_from(len)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    stream.write_all(&len.to_le_bytes())
+}
+
+#[cfg(feature = "backend")]
+fn deserialize_len<R>(stream: &mut R) -> Result<u16, io::Error>
+where
+    R: io::Read,
+{
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    Ok(u16::from_le_bytes(len))
+}
+
+// First message sent by the frontend; with a pseudo-terminal, the
+// input which follows is made of Input messages, otherwise it is the
+// raw standard input; in exec mode the backend answers with Output
+// messages, List and Kill are answered with Sessions
+#[derive(Debug)]
+pub enum Start {
+    Pipes(Program),
+    Pty(Terminal, Program),
+    Exec(Exec),
+    Attach(Attach),
+    List,
+    Kill(String),
+}
+
+impl Start {
+    #[cfg(feature = "frontend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::Pipes(program) => {
+                stream.write_all(&[ID_START_PIPES])?;
+                program.send(stream)?;
+            }
+            Self::Pty(terminal, program) => {
+                stream.write_all(&[ID_START_PTY])?;
+                terminal.send(stream)?;
+                program.send(stream)?;
+            }
+            Self::Exec(exec) => {
+                stream.write_all(&[ID_START_EXEC])?;
+                exec.send(stream)?;
+            }
+            Self::Attach(attach) => {
+                stream.write_all(&[ID_START_ATTACH])?;
+                attach.send(stream)?;
+            }
+            Self::List => stream.write_all(&[ID_START_LIST])?,
+            Self::Kill(name) => {
+                stream.write_all(&[ID_START_KILL])?;
+                util::serialize_string(stream, name)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "backend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            ID_START_PIPES => Ok(Self::Pipes(Program::receive(stream)?)),
+            ID_START_PTY => {
+                let terminal = Terminal::receive(stream)?;
+                let program = Program::receive(stream)?;
+                Ok(Self::Pty(terminal, program))
+            }
+            ID_START_EXEC => Ok(Self::Exec(Exec::receive(stream)?)),
+            ID_START_ATTACH => Ok(Self::Attach(Attach::receive(stream)?)),
+            ID_START_LIST => Ok(Self::List),
+            ID_START_KILL => Ok(Self::Kill(util::deserialize_string(stream)?)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid start")),
+        }
+    }
+}
+
+pub enum Input {
+    Data(Vec<u8>),
+    Resize(Size),
+}
+
+impl Input {
+    #[cfg(feature = "frontend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::Data(data) => {
+                let len = u16::try_from(data.len())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+                stream.write_all(&[ID_INPUT_DATA])?;
+                stream.write_all(&len.to_le_bytes())?;
+                stream.write_all(data)?;
+            }
+            Self::Resize(size) => {
+                stream.write_all(&[ID_INPUT_RESIZE])?;
+                size.send(stream)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "backend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            ID_INPUT_DATA => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len)?;
+                let mut data = vec![0u8; usize::from(u16::from_le_bytes(len))];
+                stream.read_exact(&mut data)?;
+                Ok(Self::Data(data))
+            }
+            ID_INPUT_RESIZE => Ok(Self::Resize(Size::receive(stream)?)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid input")),
+        }
+    }
+}
+
+// Messages sent by the backend in exec mode, the last one being Exit
+// or Error
+pub enum Output {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exit(i32),
+    Error(String),
+}
+
+impl Output {
+    #[cfg(feature = "backend")]
+    fn send_data<W>(stream: &mut W, id: u8, data: &[u8]) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let len = u32::try_from(data.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        stream.write_all(&[id])?;
+        stream.write_all(&len.to_le_bytes())?;
+        stream.write_all(data)
+    }
+
+    #[cfg(feature = "backend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::Stdout(data) => Self::send_data(stream, ID_OUTPUT_STDOUT, data)?,
+            Self::Stderr(data) => Self::send_data(stream, ID_OUTPUT_STDERR, data)?,
+            Self::Exit(code) => {
+                stream.write_all(&[ID_OUTPUT_EXIT])?;
+                stream.write_all(&code.to_le_bytes())?;
+            }
+            Self::Error(message) => {
+                stream.write_all(&[ID_OUTPUT_ERROR])?;
+                util::serialize_string(stream, message)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            id @ (ID_OUTPUT_STDOUT | ID_OUTPUT_STDERR) => {
+                let mut len = [0u8; 4];
+                stream.read_exact(&mut len)?;
+                let len = usize::try_from(u32::from_le_bytes(len))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                let mut data = vec![0u8; len];
+                stream.read_exact(&mut data)?;
+                if id == ID_OUTPUT_STDOUT {
+                    Ok(Self::Stdout(data))
+                } else {
+                    Ok(Self::Stderr(data))
+                }
+            }
+            ID_OUTPUT_EXIT => {
+                let mut code = [0u8; 4];
+                stream.read_exact(&mut code)?;
+                Ok(Self::Exit(i32::from_le_bytes(code)))
+            }
+            ID_OUTPUT_ERROR => Ok(Self::Error(util::deserialize_string(stream)?)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid output")),
+        }
+    }
+}
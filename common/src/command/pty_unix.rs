@@ -0,0 +1,130 @@
+use super::protocol;
+use std::{
+    fs, io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    process, ptr,
+};
+
+const fn winsize(size: protocol::Size) -> libc::winsize {
+    libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn set_cloexec(fd: &OwnedFd) -> Result<(), io::Error> {
+    // SAFETY: the file descriptor is valid as long as fd lives
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Reading the master side fails with EIO once the slave side is
+// closed by all processes, which is the end of the output
+pub struct Output(fs::File);
+
+impl io::Read for Output {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            res => res,
+        }
+    }
+}
+
+pub struct Pty {
+    master: OwnedFd,
+    child: process::Child,
+}
+
+impl Pty {
+    // Starts the program in a new session, with the pseudo-terminal as
+    // controlling terminal
+    pub fn spawn(
+        program: &protocol::Program,
+        term: &str,
+        size: protocol::Size,
+    ) -> Result<(Self, Output, fs::File), io::Error> {
+        let mut master = -1;
+        let mut slave = -1;
+        let mut winsize = winsize(size);
+
+        // SAFETY: pointers are valid for the duration of the call
+        if unsafe {
+            libc::openpty(
+                &raw mut master,
+                &raw mut slave,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &raw mut winsize,
+            )
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: both file descriptors were just opened and are owned
+        // from now on
+        let (master, slave) =
+            unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        set_cloexec(&master)?;
+        set_cloexec(&slave)?;
+
+        crate::debug!(
+            "starting {:?} {:?} with TERM={term:?} {size:?}",
+            program.path,
+            program.args
+        );
+
+        let mut command = process::Command::new(&program.path);
+        command
+            .args(&program.args)
+            .env("TERM", term)
+            .envs(program.env.iter().map(|(name, value)| (name, value)))
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave);
+
+        // SAFETY: only async-signal-safe functions are called
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+        // closes the copies of the slave side kept by the command
+        drop(command);
+
+        let output = Output(fs::File::from(master.try_clone()?));
+        let input = fs::File::from(master.try_clone()?);
+
+        Ok((Self { master, child }, output, input))
+    }
+
+    pub fn resize(&self, size: protocol::Size) -> Result<(), io::Error> {
+        let winsize = winsize(size);
+        // SAFETY: the file descriptor and the pointer are valid for the
+        // duration of the call
+        if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // The other processes of the session get SIGHUP from the kernel
+    // once the shell is gone
+    pub fn close(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,355 @@
+use super::protocol;
+#[cfg(feature = "log")]
+use crate::service;
+use std::{
+    collections, env, ffi, io, iter, mem,
+    os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    process, ptr,
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+};
+use windows_sys::Win32::{
+    Foundation,
+    System::{Console, LibraryLoader, Threading},
+};
+
+type CreatePseudoConsole = unsafe extern "system" fn(
+    Console::COORD,
+    Foundation::HANDLE,
+    Foundation::HANDLE,
+    u32,
+    *mut Console::HPCON,
+) -> windows_sys::core::HRESULT;
+type ResizePseudoConsole =
+    unsafe extern "system" fn(Console::HPCON, Console::COORD) -> windows_sys::core::HRESULT;
+type ClosePseudoConsole = unsafe extern "system" fn(Console::HPCON);
+
+// The ConPTY functions are resolved at runtime as they only exist since
+// Windows 10 1809, the programs being attached to pipes before
+struct ConPty {
+    create: CreatePseudoConsole,
+    resize: ResizePseudoConsole,
+    close: ClosePseudoConsole,
+}
+
+fn conpty() -> Option<&'static ConPty> {
+    static CONPTY: OnceLock<Option<ConPty>> = OnceLock::new();
+    CONPTY
+        .get_or_init(|| {
+            // SAFETY: kernel32.dll is always loaded, the names are null
+            // terminated and the signatures are those of the documentation
+            unsafe {
+                let kernel32 = LibraryLoader::GetModuleHandleW(windows_sys::core::w!("kernel32.dll"));
+                if kernel32.is_null() {
+                    return None;
+                }
+                let create = LibraryLoader::GetProcAddress(kernel32, c"CreatePseudoConsole".as_ptr().cast())?;
+                let resize = LibraryLoader::GetProcAddress(kernel32, c"ResizePseudoConsole".as_ptr().cast())?;
+                let close = LibraryLoader::GetProcAddress(kernel32, c"ClosePseudoConsole".as_ptr().cast())?;
+                Some(ConPty {
+                    create: mem::transmute::<unsafe extern "system" fn() -> isize, CreatePseudoConsole>(create),
+                    resize: mem::transmute::<unsafe extern "system" fn() -> isize, ResizePseudoConsole>(resize),
+                    close: mem::transmute::<unsafe extern "system" fn() -> isize, ClosePseudoConsole>(close),
+                })
+            }
+        })
+        .as_ref()
+}
+
+fn close_console(console: Console::HPCON) {
+    if let Some(conpty) = conpty() {
+        // SAFETY: the pseudo console is valid and not used anymore
+        unsafe { (conpty.close)(console) };
+    }
+}
+
+fn coord(size: protocol::Size) -> Console::COORD {
+    Console::COORD {
+        X: i16::try_from(size.cols).unwrap_or(i16::MAX),
+        Y: i16::try_from(size.rows).unwrap_or(i16::MAX),
+    }
+}
+
+fn hresult(res: windows_sys::core::HRESULT) -> Result<(), io::Error> {
+    if res < 0 {
+        return Err(io::Error::from_raw_os_error(res));
+    }
+    Ok(())
+}
+
+// Quotes an argument so that CommandLineToArgvW gives it back
+fn quote(arg: &str, command_line: &mut String) {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        command_line.push_str(arg);
+        return;
+    }
+    command_line.push('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                command_line.extend(iter::repeat_n('\\', backslashes * 2 + 1));
+                command_line.push(c);
+                backslashes = 0;
+            }
+            c => {
+                command_line.extend(iter::repeat_n('\\', backslashes));
+                command_line.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    command_line.extend(iter::repeat_n('\\', backslashes * 2));
+    command_line.push('"');
+}
+
+fn command_line(program: &protocol::Program) -> Vec<u16> {
+    let mut command_line = String::new();
+    quote(&program.path, &mut command_line);
+    for arg in &program.args {
+        command_line.push(' ');
+        quote(arg, &mut command_line);
+    }
+    command_line.encode_utf16().chain([0]).collect()
+}
+
+// Environment block of the backend with the variables of the program
+// added, None if there is none to add
+fn environment(program: &protocol::Program) -> Option<Vec<u16>> {
+    if program.env.is_empty() {
+        return None;
+    }
+    // names are case insensitive
+    let mut variables = env::vars_os()
+        .map(|(name, value)| {
+            let name = name.to_string_lossy().to_string();
+            (
+                name.to_uppercase(),
+                (name, value.to_string_lossy().to_string()),
+            )
+        })
+        .collect::<collections::BTreeMap<_, _>>();
+    for (name, value) in &program.env {
+        variables.insert(name.to_uppercase(), (name.clone(), value.clone()));
+    }
+    let mut block = variables
+        .into_values()
+        .flat_map(|(name, value)| {
+            format!("{name}={value}\0")
+                .encode_utf16()
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    block.push(0);
+    Some(block)
+}
+
+type SharedConsole = Arc<Mutex<Option<Console::HPCON>>>;
+
+pub struct Pty {
+    // closed as soon as the process exits, the output only ends then
+    console: SharedConsole,
+    process: Arc<OwnedHandle>,
+}
+
+impl Pty {
+    // Starts the program attached to a pseudo console (ConPTY), which
+    // translates to and from VT sequences; the terminal type is not
+    // needed
+    pub fn spawn(
+        program: &protocol::Program,
+        _term: &str,
+        size: protocol::Size,
+    ) -> Result<(Self, io::PipeReader, io::PipeWriter), io::Error> {
+        let Some(conpty) = conpty() else {
+            return Self::spawn_pipes(program);
+        };
+
+        let (input_read, input_write) = io::pipe()?;
+        let (output_read, output_write) = io::pipe()?;
+
+        let mut console = 0;
+        // SAFETY: handles are valid for the duration of the call, the
+        // pseudo console keeps its own duplicates
+        hresult(unsafe {
+            (conpty.create)(
+                coord(size),
+                input_read.as_raw_handle(),
+                output_write.as_raw_handle(),
+                0,
+                &raw mut console,
+            )
+        })?;
+        drop(input_read);
+        drop(output_write);
+
+        let res = Self::create_process(console, program);
+        if res.is_err() {
+            close_console(console);
+        }
+        let process = Arc::new(res?);
+
+        let console = Arc::new(Mutex::new(Some(console)));
+
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!("{} {} pty", service::Kind::Backend, super::SERVICE));
+        thread.spawn({
+            let console = console.clone();
+            let process = process.clone();
+            move || {
+                // SAFETY: the process handle is valid as long as process lives
+                unsafe {
+                    Threading::WaitForSingleObject(process.as_raw_handle(), Threading::INFINITE)
+                };
+                let console = console.lock().unwrap().take();
+                if let Some(console) = console {
+                    close_console(console);
+                }
+            }
+        })?;
+
+        Ok((Self { console, process }, output_read, input_write))
+    }
+
+    // Without pseudo console, the program is attached to pipes, its
+    // output being neither translated nor resized
+    fn spawn_pipes(
+        program: &protocol::Program,
+    ) -> Result<(Self, io::PipeReader, io::PipeWriter), io::Error> {
+        crate::debug!("no pseudo console, using pipes");
+
+        let (input_read, input_write) = io::pipe()?;
+        let (output_read, output_write) = io::pipe()?;
+
+        let child = process::Command::new(&program.path)
+            .args(&program.args)
+            .envs(program.env.iter().map(|(name, value)| (name, value)))
+            .stdin(input_read)
+            .stdout(output_write.try_clone()?)
+            .stderr(output_write)
+            .spawn()?;
+
+        crate::debug!("started {:?} {:?}", program.path, program.args);
+
+        Ok((
+            Self {
+                console: Arc::new(Mutex::new(None)),
+                process: Arc::new(OwnedHandle::from(child)),
+            },
+            output_read,
+            input_write,
+        ))
+    }
+
+    fn create_process(
+        console: Console::HPCON,
+        program: &protocol::Program,
+    ) -> Result<OwnedHandle, io::Error> {
+        let mut size = 0;
+        // SAFETY: only queries the size of the list, failing as expected
+        unsafe {
+            Threading::InitializeProcThreadAttributeList(ptr::null_mut(), 1, 0, &raw mut size)
+        };
+
+        // aligned storage for the attribute list
+        let mut attributes = vec![0u64; size.div_ceil(mem::size_of::<u64>())];
+        let list = attributes.as_mut_ptr().cast::<ffi::c_void>();
+
+        // SAFETY: the list is at least as large as requested
+        if unsafe { Threading::InitializeProcThreadAttributeList(list, 1, 0, &raw mut size) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut startup = Threading::STARTUPINFOEXW::default();
+        startup.StartupInfo.cb =
+            u32::try_from(mem::size_of::<Threading::STARTUPINFOEXW>()).unwrap_or_default();
+        // no inheritance of the standard handles of the backend
+        startup.StartupInfo.dwFlags = Threading::STARTF_USESTDHANDLES;
+        startup.lpAttributeList = list;
+
+        let mut command = command_line(program);
+        let environment = environment(program);
+        let mut information = Threading::PROCESS_INFORMATION::default();
+
+        // SAFETY: the attribute value is the pseudo console itself, all
+        // pointers are valid for the duration of the calls
+        let res = unsafe {
+            if Threading::UpdateProcThreadAttribute(
+                list,
+                0,
+                Threading::PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE as usize,
+                ptr::without_provenance(console.cast_unsigned()),
+                mem::size_of::<Console::HPCON>(),
+                ptr::null_mut(),
+                ptr::null(),
+            ) == 0
+                || Threading::CreateProcessW(
+                    ptr::null(),
+                    command.as_mut_ptr(),
+                    ptr::null(),
+                    ptr::null(),
+                    0,
+                    Threading::EXTENDED_STARTUPINFO_PRESENT | Threading::CREATE_UNICODE_ENVIRONMENT,
+                    environment
+                        .as_ref()
+                        .map_or(ptr::null(), |environment| environment.as_ptr().cast()),
+                    ptr::null(),
+                    &raw const startup.StartupInfo,
+                    &raw mut information,
+                ) == 0
+            {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+
+        // SAFETY: the list was initialized
+        unsafe { Threading::DeleteProcThreadAttributeList(list) };
+
+        res?;
+
+        crate::debug!("started {:?} {:?}", program.path, program.args);
+
+        // SAFETY: both handles were just returned and are owned from now on
+        let (process, _thread) = unsafe {
+            (
+                OwnedHandle::from_raw_handle(information.hProcess),
+                OwnedHandle::from_raw_handle(information.hThread),
+            )
+        };
+
+        Ok(process)
+    }
+
+    pub fn resize(&self, size: protocol::Size) -> Result<(), io::Error> {
+        // the lock is held so that the pseudo console is not closed meanwhile
+        let console = self.console.lock().unwrap();
+        match (*console, conpty()) {
+            (Some(console), Some(conpty)) => {
+                // SAFETY: the pseudo console is valid while it is in the mutex
+                hresult(unsafe { (conpty.resize)(console, coord(size)) })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn close(self) {
+        // SAFETY: the process handle is valid as long as self lives
+        unsafe {
+            Threading::TerminateProcess(self.process.as_raw_handle(), 1);
+            Threading::WaitForSingleObject(self.process.as_raw_handle(), Threading::INFINITE);
+        }
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        let console = self.console.lock().unwrap().take();
+        if let Some(console) = console {
+            close_console(console);
+        }
+    }
+}
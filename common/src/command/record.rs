@@ -0,0 +1,281 @@
+use super::protocol;
+use crate::{api, frontend, service, util};
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    path, str,
+    sync::Mutex,
+    time,
+};
+
+const EXTENSION: &str = "cast";
+
+const DAY: time::Duration = time::Duration::from_hours(24);
+
+// Recordings started in the same millisecond get a numbered suffix
+const MAX_SUFFIX: u32 = 100;
+
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Output,
+    Input,
+}
+
+impl Stream {
+    const fn code(self) -> &'static str {
+        match self {
+            Self::Output => "o",
+            Self::Input => "i",
+        }
+    }
+}
+
+struct State {
+    file: io::BufWriter<fs::File>,
+    // incomplete UTF-8 sequences at the end of the previous data
+    pending_output: Vec<u8>,
+    pending_input: Vec<u8>,
+}
+
+// Transcript of a session in the asciinema v2 format
+pub struct Recorder {
+    start: time::Instant,
+    state: Mutex<Option<State>>,
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(res, "\\u{:04x}", u32::from(c));
+            }
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+// Decodes the complete UTF-8 sequences, keeps an incomplete one for the
+// next data
+fn decode(pending: &mut Vec<u8>, data: &[u8]) -> String {
+    pending.extend_from_slice(data);
+    let complete = match str::from_utf8(pending) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => pending.len(),
+    };
+    let rest = pending.split_off(complete);
+    let res = String::from_utf8_lossy(pending).into_owned();
+    *pending = rest;
+    res
+}
+
+// Removes the recordings older than the given number of days
+fn prune(dir: &path::Path, days: u64) {
+    let Some(limit) =
+        time::SystemTime::now().checked_sub(DAY * u32::try_from(days).unwrap_or(u32::MAX))
+    else {
+        return;
+    };
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            crate::warn!("failed to read {dir:?}: {e}");
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_none_or(|extension| extension != EXTENSION)
+        {
+            continue;
+        }
+        let expired = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified < limit);
+        if expired {
+            match fs::remove_file(&path) {
+                Ok(()) => crate::debug!("removed expired recording {path:?}"),
+                Err(e) => crate::warn!("failed to remove {path:?}: {e}"),
+            }
+        }
+    }
+}
+
+impl Recorder {
+    // Records into the directory set with the "record_dir" setting, if
+    // any; recordings older than "record_retention" days are removed
+    pub fn new(
+        settings: &frontend::FrontendSettings,
+        service: &service::Service,
+        client_id: api::ClientId,
+        terminal: Option<&protocol::Terminal>,
+    ) -> Self {
+        let start = time::Instant::now();
+
+        let state = settings.get("record_dir").and_then(|dir| {
+            let dir = path::Path::new(dir);
+
+            if let Some(days) = settings.get("record_retention") {
+                match days.parse() {
+                    Ok(days) => prune(dir, days),
+                    Err(_) => crate::warn!("invalid record_retention {days:?}"),
+                }
+            }
+
+            let now = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap_or_default();
+            let timestamp = now.as_secs();
+            let [year, month, day, hour, minute, second] =
+                util::civil_from_unix(i64::try_from(timestamp).unwrap_or(i64::MAX));
+            let millis = now.subsec_millis();
+            let stem = format!(
+                "{year:04}{month:02}{day:02}-{hour:02}{minute:02}{second:02}.{millis:03}_{client_id}_{service}"
+            );
+
+            let mut suffix = 0;
+            loop {
+                let path = if suffix == 0 {
+                    dir.join(format!("{stem}.{EXTENSION}"))
+                } else {
+                    dir.join(format!("{stem}-{suffix}.{EXTENSION}"))
+                };
+
+                match Self::create(&path, timestamp, terminal) {
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists && suffix < MAX_SUFFIX => {
+                        suffix += 1;
+                    }
+                    Ok(file) => {
+                        crate::info!("recording to {path:?}");
+                        break Some(State {
+                            file,
+                            pending_output: vec![],
+                            pending_input: vec![],
+                        });
+                    }
+                    Err(e) => {
+                        crate::warn!("failed to create {path:?}: {e}");
+                        break None;
+                    }
+                }
+            }
+        });
+
+        Self {
+            start,
+            state: Mutex::new(state),
+        }
+    }
+
+    fn create(
+        path: &path::Path,
+        timestamp: u64,
+        terminal: Option<&protocol::Terminal>,
+    ) -> Result<io::BufWriter<fs::File>, io::Error> {
+        let mut file = io::BufWriter::new(
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)?,
+        );
+
+        let size = terminal.map(|terminal| terminal.size).unwrap_or_default();
+        write!(
+            file,
+            "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {timestamp}",
+            size.cols, size.rows
+        )?;
+        if let Some(terminal) = terminal {
+            write!(
+                file,
+                ", \"env\": {{\"TERM\": {}}}",
+                json_string(&terminal.term)
+            )?;
+        }
+        writeln!(file, "}}")?;
+        file.flush()?;
+
+        Ok(file)
+    }
+
+    fn event(&self, state: &mut Option<State>, code: &str, data: &str) {
+        let Some(recording) = state.as_mut() else {
+            return;
+        };
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let res = writeln!(
+            recording.file,
+            "[{elapsed:.6}, \"{code}\", {}]",
+            json_string(data)
+        )
+        .and_then(|()| recording.file.flush());
+
+        if let Err(e) = res {
+            // stops recording
+            crate::warn!("failed to record: {e}");
+            *state = None;
+        }
+    }
+
+    pub fn record(&self, stream: Stream, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let Some(recording) = state.as_mut() else {
+            return;
+        };
+
+        let pending = match stream {
+            Stream::Output => &mut recording.pending_output,
+            Stream::Input => &mut recording.pending_input,
+        };
+        let data = decode(pending, data);
+        if !data.is_empty() {
+            self.event(&mut state, stream.code(), &data);
+        }
+    }
+
+    pub fn resize(&self, size: protocol::Size) {
+        let mut state = self.state.lock().unwrap();
+        self.event(&mut state, "r", &format!("{}x{}", size.cols, size.rows));
+    }
+
+    // Records what is read from a stream
+    pub const fn reader<R>(&self, inner: R, stream: Stream) -> Reader<'_, R> {
+        Reader {
+            inner,
+            recorder: self,
+            stream,
+        }
+    }
+}
+
+pub struct Reader<'a, R> {
+    inner: R,
+    recorder: &'a Recorder,
+    stream: Stream,
+}
+
+impl<R> io::Read for Reader<'_, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.recorder.record(self.stream, &buf[..read]);
+        Ok(read)
+    }
+}
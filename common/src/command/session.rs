@@ -0,0 +1,274 @@
+use super::{protocol, pty};
+use crate::api;
+#[cfg(feature = "log")]
+use crate::service;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Write},
+    sync::{Arc, Condvar, Mutex},
+    thread, time,
+};
+
+// Output kept to be replayed when a client attaches
+const SCROLLBACK_SIZE: usize = 64 * 1024;
+
+static SESSIONS: Mutex<BTreeMap<String, Arc<Session>>> = Mutex::new(BTreeMap::new());
+
+struct Client {
+    id: api::ClientId,
+    output: crossbeam_channel::Sender<Vec<u8>>,
+}
+
+struct State {
+    scrollback: VecDeque<u8>,
+    client: Option<Client>,
+    // since when no client is attached
+    detached: Option<time::Instant>,
+    exited: bool,
+}
+
+// Program running in a pseudo-terminal which outlives the streams of
+// the clients attached to it
+pub struct Session {
+    name: String,
+    program: String,
+    started: time::Instant,
+    pty: Mutex<Option<pty::Pty>>,
+    input: Mutex<Box<dyn io::Write + Send>>,
+    state: Mutex<State>,
+    state_changed: Condvar,
+}
+
+pub fn find(name: &str) -> Option<Arc<Session>> {
+    SESSIONS.lock().unwrap().get(name).cloned()
+}
+
+// Returns the named session, started with the program if it does not
+// exist yet; it is killed once detached for longer than the timeout
+// (in seconds)
+pub fn start(
+    name: &str,
+    terminal: &protocol::Terminal,
+    program: &protocol::Program,
+    timeout: Option<u32>,
+) -> Result<Arc<Session>, io::Error> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    if let Some(session) = sessions.get(name) {
+        return Ok(session.clone());
+    }
+
+    let (pty, output, input) = pty::Pty::spawn(program, &terminal.term, terminal.size)?;
+
+    crate::info!("session {name:?} started");
+
+    let session = Arc::new(Session {
+        name: name.to_string(),
+        program: program.path.clone(),
+        started: time::Instant::now(),
+        pty: Mutex::new(Some(pty)),
+        input: Mutex::new(Box::new(input)),
+        state: Mutex::new(State {
+            scrollback: VecDeque::new(),
+            client: None,
+            detached: Some(time::Instant::now()),
+            exited: false,
+        }),
+        state_changed: Condvar::new(),
+    });
+
+    let thread = thread::Builder::new();
+    #[cfg(feature = "log")]
+    let thread = thread.name(format!(
+        "{} {} session {name} output",
+        service::Kind::Backend,
+        super::SERVICE,
+    ));
+    thread.spawn({
+        let session = session.clone();
+        move || session.forward(output)
+    })?;
+
+    if let Some(timeout) = timeout {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} session {name} timeout",
+            service::Kind::Backend,
+            super::SERVICE,
+        ));
+        thread.spawn({
+            let session = session.clone();
+            move || session.watch(time::Duration::from_secs(u64::from(timeout)))
+        })?;
+    }
+
+    sessions.insert(name.to_string(), session.clone());
+
+    Ok(session)
+}
+
+pub fn list() -> Vec<protocol::SessionInfo> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|session| session.info())
+        .collect()
+}
+
+pub fn kill(name: &str) -> Option<protocol::SessionInfo> {
+    let session = SESSIONS.lock().unwrap().remove(name)?;
+    crate::info!("killing session {name:?}");
+    let info = session.info();
+    session.close();
+    Some(info)
+}
+
+impl Session {
+    fn info(&self) -> protocol::SessionInfo {
+        protocol::SessionInfo {
+            name: self.name.clone(),
+            program: self.program.clone(),
+            attached: self.state.lock().unwrap().client.is_some(),
+            age: u32::try_from(self.started.elapsed().as_secs()).unwrap_or(u32::MAX),
+        }
+    }
+
+    fn close(&self) {
+        let pty = self.pty.lock().unwrap().take();
+        if let Some(pty) = pty {
+            pty.close();
+        }
+    }
+
+    // Copies the output to the scrollback and to the attached client
+    // until the program exits
+    fn forward<R>(self: &Arc<Self>, mut output: R)
+    where
+        R: io::Read,
+    {
+        let mut buf = vec![0u8; api::Chunk::max_payload_length()];
+        loop {
+            let read = match output.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            state.scrollback.extend(&buf[..read]);
+            let excess = state.scrollback.len().saturating_sub(SCROLLBACK_SIZE);
+            state.scrollback.drain(..excess);
+            if let Some(client) = &state.client {
+                let _ = client.output.send(buf[..read].to_vec());
+            }
+        }
+
+        crate::info!("session {:?} ended", self.name);
+
+        self.close();
+        self.unregister();
+
+        let mut state = self.state.lock().unwrap();
+        state.exited = true;
+        // ends the output of the attached client
+        state.client = None;
+        self.state_changed.notify_all();
+    }
+
+    // Removes the session from the list unless another one took its name
+    fn unregister(self: &Arc<Self>) {
+        let mut sessions = SESSIONS.lock().unwrap();
+        if sessions
+            .get(&self.name)
+            .is_some_and(|session| Arc::ptr_eq(session, self))
+        {
+            sessions.remove(&self.name);
+        }
+    }
+
+    fn watch(self: &Arc<Self>, timeout: time::Duration) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.exited {
+                return;
+            }
+            match state.detached {
+                None => state = self.state_changed.wait(state).unwrap(),
+                Some(since) => {
+                    let idle = since.elapsed();
+                    if timeout <= idle {
+                        break;
+                    }
+                    state = self
+                        .state_changed
+                        .wait_timeout(state, timeout.saturating_sub(idle))
+                        .unwrap()
+                        .0;
+                }
+            }
+        }
+        drop(state);
+
+        crate::info!("session {:?} detached for too long, killing it", self.name);
+        self.unregister();
+        self.close();
+    }
+
+    // Replaces the attached client, if any; the returned receiver gets
+    // the scrollback then the output, and is closed once the program
+    // exits or another client attaches
+    pub fn attach(
+        &self,
+        id: api::ClientId,
+        size: protocol::Size,
+    ) -> crossbeam_channel::Receiver<Vec<u8>> {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+
+        let mut state = self.state.lock().unwrap();
+        if !state.scrollback.is_empty() {
+            let _ = sender.send(state.scrollback.iter().copied().collect());
+        }
+        if state.exited {
+            return receiver;
+        }
+        if let Some(previous) = state.client.replace(Client { id, output: sender }) {
+            crate::info!(
+                "session {:?} taken over from client {:x}",
+                self.name,
+                previous.id
+            );
+        }
+        state.detached = None;
+        self.state_changed.notify_all();
+        drop(state);
+
+        crate::info!("client {id:x} attached to session {:?}", self.name);
+
+        // also makes full screen programs redraw
+        self.resize(size);
+
+        receiver
+    }
+
+    pub fn detach(&self, id: api::ClientId) {
+        let mut state = self.state.lock().unwrap();
+        if state.client.as_ref().is_some_and(|client| client.id == id) {
+            crate::info!("client {id:x} detached from session {:?}", self.name);
+            state.client = None;
+            state.detached = Some(time::Instant::now());
+            self.state_changed.notify_all();
+        }
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<(), io::Error> {
+        self.input.lock().unwrap().write_all(data)
+    }
+
+    pub fn resize(&self, size: protocol::Size) {
+        if let Some(pty) = self.pty.lock().unwrap().as_ref()
+            && let Err(e) = pty.resize(size)
+        {
+            crate::warn!("failed to resize: {e}");
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use super::protocol;
+
+pub const IAC: u8 = 0xff;
+const DONT: u8 = 0xfe;
+const DO: u8 = 0xfd;
+const WONT: u8 = 0xfc;
+const WILL: u8 = 0xfb;
+const SB: u8 = 0xfa;
+const SE: u8 = 0xf0;
+
+const OPTION_ECHO: u8 = 0x01;
+const OPTION_SGA: u8 = 0x03;
+pub const OPTION_TTYPE: u8 = 0x18;
+pub const OPTION_NAWS: u8 = 0x1f;
+
+const TTYPE_IS: u8 = 0x00;
+const TTYPE_SEND: u8 = 0x01;
+
+const CR: u8 = b'\r';
+
+// The server echoes and suppresses go-ahead so that the client sends
+// each key as typed (character mode), and asks for the terminal type
+// (RFC 1091) and the window size (RFC 1073)
+pub const NEGOTIATION: &[u8] = &[
+    IAC,
+    WILL,
+    OPTION_ECHO,
+    IAC,
+    WILL,
+    OPTION_SGA,
+    IAC,
+    DO,
+    OPTION_SGA,
+    IAC,
+    DO,
+    OPTION_TTYPE,
+    IAC,
+    DO,
+    OPTION_NAWS,
+];
+
+// Sent once the client agreed to send its terminal type
+pub const TTYPE_REQUEST: &[u8] = &[IAC, SB, OPTION_TTYPE, TTYPE_SEND, IAC, SE];
+
+pub enum Event {
+    Data(Vec<u8>),
+    Will(u8),
+    Wont(u8),
+    Term(String),
+    Size(protocol::Size),
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    Option(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+#[derive(Default)]
+pub struct Parser {
+    state: State,
+    subnegotiation: Vec<u8>,
+    // CR is followed by NUL or LF, which are dropped
+    after_cr: bool,
+}
+
+impl Parser {
+    fn subnegotiation(&mut self) -> Option<Event> {
+        let res = match self.subnegotiation.as_slice() {
+            [OPTION_TTYPE, TTYPE_IS, term @ ..] => {
+                Some(Event::Term(String::from_utf8_lossy(term).to_lowercase()))
+            }
+            [OPTION_NAWS, c0, c1, r0, r1] => Some(Event::Size(protocol::Size {
+                cols: u16::from_be_bytes([*c0, *c1]),
+                rows: u16::from_be_bytes([*r0, *r1]),
+            })),
+            _ => None,
+        };
+        self.subnegotiation.clear();
+        res
+    }
+
+    pub fn parse(&mut self, input: &[u8]) -> Vec<Event> {
+        let mut events = vec![];
+        let mut data = vec![];
+
+        for b in input.iter().copied() {
+            match self.state {
+                State::Data if b == IAC => self.state = State::Iac,
+                State::Data => {
+                    if !(self.after_cr && (b == 0 || b == b'\n')) {
+                        data.push(b);
+                    }
+                    self.after_cr = b == CR;
+                }
+                State::Iac => {
+                    self.state = match b {
+                        // escaped 0xff
+                        IAC => {
+                            data.push(IAC);
+                            State::Data
+                        }
+                        DO | DONT | WILL | WONT => State::Option(b),
+                        SB => State::Subnegotiation,
+                        _ => State::Data,
+                    };
+                }
+                State::Option(verb) => {
+                    match verb {
+                        WILL => events.push(Event::Will(b)),
+                        WONT => events.push(Event::Wont(b)),
+                        _ => (),
+                    }
+                    self.state = State::Data;
+                }
+                State::Subnegotiation if b == IAC => self.state = State::SubnegotiationIac,
+                State::Subnegotiation => self.subnegotiation.push(b),
+                State::SubnegotiationIac if b == SE => {
+                    if !data.is_empty() {
+                        events.push(Event::Data(data.split_off(0)));
+                    }
+                    events.extend(self.subnegotiation());
+                    self.state = State::Data;
+                }
+                State::SubnegotiationIac => {
+                    self.subnegotiation.push(b);
+                    self.state = State::Subnegotiation;
+                }
+            }
+        }
+
+        if !data.is_empty() {
+            events.push(Event::Data(data));
+        }
+
+        events
+    }
+}
+
+// Output bytes equal to IAC are doubled
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(data.len());
+    for b in data.iter().copied() {
+        res.push(b);
+        if b == IAC {
+            res.push(IAC);
+        }
+    }
+    res
+}
@@ -0,0 +1,257 @@
+use super::protocol;
+use crate::{outbound, rdp};
+#[cfg(not(target_os = "windows"))]
+use std::ffi;
+use std::{
+    io::{self, Read, Write},
+    net::{self, ToSocketAddrs},
+    time,
+};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Networking::WinSock;
+
+const HEADER_LEN: usize = 12;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+const RCODE_FORMERR: u8 = 1;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_NOTIMP: u8 = 4;
+
+const ANSWER_TTL: u32 = 60;
+
+const RELAY_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+struct Question {
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    // offset of the end of the question section in the query
+    end: usize,
+}
+
+fn read_u16(query: &[u8], offset: usize) -> Option<u16> {
+    query
+        .get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn parse_question(query: &[u8]) -> Option<Question> {
+    if read_u16(query, 4)? != 1 {
+        return None;
+    }
+
+    let mut labels = vec![];
+    let mut offset = HEADER_LEN;
+
+    loop {
+        let len = usize::from(*query.get(offset)?);
+        offset += 1;
+
+        if len == 0 {
+            break;
+        }
+        // compression pointers are not expected in the question of a query
+        if len & 0xC0 != 0 {
+            return None;
+        }
+
+        let label = query.get(offset..offset + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        offset += len;
+    }
+
+    let qtype = read_u16(query, offset)?;
+    let qclass = read_u16(query, offset + 2)?;
+
+    Some(Question {
+        name: labels.join("."),
+        qtype,
+        qclass,
+        end: offset + 4,
+    })
+}
+
+fn response(
+    query: &[u8],
+    question: Option<&Question>,
+    rcode: u8,
+    answers: &[net::IpAddr],
+) -> Vec<u8> {
+    let mut res = Vec::with_capacity(512);
+
+    res.extend_from_slice(&query[0..2]);
+    // QR, same opcode and RD as the query
+    res.push(0x80 | (query[2] & 0x79));
+    // RA
+    res.push(0x80 | rcode);
+    res.extend_from_slice(&u16::from(question.is_some()).to_be_bytes());
+    let ancount = u16::try_from(answers.len()).unwrap_or(u16::MAX);
+    res.extend_from_slice(&ancount.to_be_bytes());
+    res.extend_from_slice(&[0, 0, 0, 0]);
+
+    if let Some(question) = question {
+        res.extend_from_slice(&query[HEADER_LEN..question.end]);
+    }
+
+    for ip in answers.iter().take(usize::from(ancount)) {
+        // pointer to the name of the question
+        res.extend_from_slice(&[0xC0, 0x0C]);
+        match ip {
+            net::IpAddr::V4(ip) => {
+                res.extend_from_slice(&TYPE_A.to_be_bytes());
+                res.extend_from_slice(&CLASS_IN.to_be_bytes());
+                res.extend_from_slice(&ANSWER_TTL.to_be_bytes());
+                res.extend_from_slice(&4u16.to_be_bytes());
+                res.extend_from_slice(&ip.octets());
+            }
+            net::IpAddr::V6(ip) => {
+                res.extend_from_slice(&TYPE_AAAA.to_be_bytes());
+                res.extend_from_slice(&CLASS_IN.to_be_bytes());
+                res.extend_from_slice(&ANSWER_TTL.to_be_bytes());
+                res.extend_from_slice(&16u16.to_be_bytes());
+                res.extend_from_slice(&ip.octets());
+            }
+        }
+    }
+
+    res
+}
+
+// Only names which do not exist are answered with NXDOMAIN, which stub
+// resolvers cache; other failures (e.g. timeouts) are transient
+#[cfg(not(target_os = "windows"))]
+fn is_not_found(e: &io::Error) -> bool {
+    let codes = [
+        libc::EAI_NONAME,
+        #[cfg(target_os = "linux")]
+        libc::EAI_NODATA,
+    ];
+    // the error only holds the message of the getaddrinfo error code
+    let message = e.to_string();
+    codes.into_iter().any(|code| {
+        // SAFETY: gai_strerror returns a static NUL-terminated string
+        let detail = unsafe { ffi::CStr::from_ptr(libc::gai_strerror(code)) };
+        message.ends_with(detail.to_string_lossy().as_ref())
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn is_not_found(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(WinSock::WSAHOST_NOT_FOUND | WinSock::WSANO_DATA)
+    )
+}
+
+fn resolve(query: &[u8]) -> Option<Vec<u8>> {
+    if query.len() < HEADER_LEN {
+        return None;
+    }
+
+    let Some(question) = parse_question(query) else {
+        return Some(response(query, None, RCODE_FORMERR, &[]));
+    };
+
+    let opcode = (query[2] >> 3) & 0x0F;
+
+    if opcode != 0
+        || question.qclass != CLASS_IN
+        || !matches!(question.qtype, TYPE_A | TYPE_AAAA | TYPE_ANY)
+    {
+        crate::debug!(
+            "unsupported query (opcode = {opcode}, type = {}, class = {})",
+            question.qtype,
+            question.qclass
+        );
+        return Some(response(query, Some(&question), RCODE_NOTIMP, &[]));
+    }
+
+    crate::debug!("resolving {:?}", question.name);
+
+    match (question.name.as_str(), 0).to_socket_addrs() {
+        Err(e) => {
+            crate::debug!("failed to resolve {:?}: {e}", question.name);
+            let rcode = if is_not_found(&e) {
+                RCODE_NXDOMAIN
+            } else {
+                RCODE_SERVFAIL
+            };
+            Some(response(query, Some(&question), rcode, &[]))
+        }
+        Ok(addrs) => {
+            let mut answers: Vec<net::IpAddr> = vec![];
+            addrs
+                .map(|addr| addr.ip())
+                .filter(|ip| match question.qtype {
+                    TYPE_A => ip.is_ipv4(),
+                    TYPE_AAAA => ip.is_ipv6(),
+                    _ => true,
+                })
+                .for_each(|ip| {
+                    if !answers.contains(&ip) {
+                        answers.push(ip);
+                    }
+                });
+            Some(response(query, Some(&question), 0, &answers))
+        }
+    }
+}
+
+// The connection to the server is subject to the destination policy
+// and the upstream proxy, as those of the other services
+fn relay(server: &str, query: &[u8]) -> Result<Vec<u8>, io::Error> {
+    crate::debug!("relaying query to {server:?}");
+
+    let mut stream = outbound::connect(server)?;
+    stream.set_read_timeout(Some(RELAY_TIMEOUT))?;
+    stream.set_write_timeout(Some(RELAY_TIMEOUT))?;
+
+    let len = u16::try_from(query.len())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    let mut buf = Vec::with_capacity(2 + query.len());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(query);
+    stream.write_all(&buf)?;
+    stream.flush()?;
+
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut answer = vec![0u8; usize::from(u16::from_be_bytes(len))];
+    stream.read_exact(&mut answer)?;
+
+    Ok(answer)
+}
+
+pub fn handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
+    crate::debug!("starting");
+
+    loop {
+        let command = match protocol::Command::receive(&mut stream) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+            Ok(command) => command,
+        };
+
+        let answer = match command {
+            protocol::Command::Resolve(query) => resolve(&query),
+            protocol::Command::Relay(server, query) => match relay(&server, &query) {
+                Err(e) => {
+                    crate::warn!("failed to relay query to {server:?}: {e}");
+                    (query.len() >= HEADER_LEN).then(|| response(&query, None, RCODE_SERVFAIL, &[]))
+                }
+                Ok(answer) => Some(answer),
+            },
+        };
+
+        match answer {
+            None => protocol::Response::Failed.send(&mut stream)?,
+            Some(answer) => protocol::Response::Answer(answer).send(&mut stream)?,
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use super::protocol;
+use crate::{api, channel, frontend, rdp};
+use std::{
+    io::{self, Read, Write},
+    net, thread,
+};
+
+const HEADER_LEN: usize = 12;
+
+// RFC 1035 limit for clients which do not announce a larger size with EDNS
+const UDP_MAX_PAYLOAD: usize = 512;
+// RFC 6891 recommended value when the client sent an OPT record
+const UDP_MAX_PAYLOAD_EDNS: usize = 1232;
+
+fn query(
+    settings: &frontend::FrontendSettings,
+    rdp: &mut rdp::RdpStream<'_>,
+    message: &[u8],
+) -> Result<Option<Vec<u8>>, io::Error> {
+    let command = settings.get("upstream").map_or_else(
+        || protocol::Command::Resolve(message.to_vec()),
+        |upstream| protocol::Command::Relay(upstream.to_string(), message.to_vec()),
+    );
+
+    command.send(rdp)?;
+
+    match protocol::Response::receive(rdp)? {
+        protocol::Response::Answer(answer) => Ok(Some(answer)),
+        protocol::Response::Failed => Ok(None),
+    }
+}
+
+// Too large answers are replaced by their header, with the TC flag
+// set, so that the client retries over TCP.
+fn truncate(message: &[u8], mut answer: Vec<u8>) -> Vec<u8> {
+    let edns = message.get(10..12).is_some_and(|arcount| arcount != [0, 0]);
+    let max_len = if edns {
+        UDP_MAX_PAYLOAD_EDNS
+    } else {
+        UDP_MAX_PAYLOAD
+    };
+
+    if max_len < answer.len() && HEADER_LEN <= answer.len() {
+        answer.truncate(HEADER_LEN);
+        answer[2] |= 0x02;
+        answer[4..HEADER_LEN].fill(0);
+    }
+
+    answer
+}
+
+pub fn tcp_handler(
+    server: &frontend::FrontendTcpServer,
+    _scope: &thread::Scope,
+    stream: net::TcpStream,
+    channel: &channel::Channel,
+) -> Result<(), api::Error> {
+    let mut rdp = channel.connect(&super::SERVICE)?;
+
+    let mut client_read = io::BufReader::new(stream.try_clone()?);
+    let mut client_write = io::BufWriter::new(stream);
+
+    loop {
+        let mut len = [0u8; 2];
+        match client_read.read_exact(&mut len) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(api::Error::Io(e)),
+            Ok(()) => (),
+        }
+
+        let mut message = vec![0u8; usize::from(u16::from_be_bytes(len))];
+        client_read.read_exact(&mut message)?;
+
+        // only messages too short to be answered (even with SERVFAIL)
+        // are not, the client would wait for the answer otherwise
+        let Some(answer) = query(server.settings(), &mut rdp, &message)? else {
+            return Err(api::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "query not answered",
+            )));
+        };
+
+        let len = u16::try_from(answer.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        client_write.write_all(&len.to_be_bytes())?;
+        client_write.write_all(&answer)?;
+        client_write.flush()?;
+    }
+}
+
+pub fn udp_handler(
+    server: &frontend::FrontendUdpServer,
+    _scope: &thread::Scope,
+    (message, client_addr): (Vec<u8>, net::SocketAddr),
+    channel: &channel::Channel,
+) -> Result<(), api::Error> {
+    let mut rdp = channel.connect(&super::SERVICE)?;
+
+    if let Some(answer) = query(server.settings(), &mut rdp, &message)? {
+        let answer = truncate(&message, answer);
+        server.socket().send_to(&answer, client_addr)?;
+    }
+
+    Ok(())
+}
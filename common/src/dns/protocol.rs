@@ -0,0 +1,118 @@
+use crate::util;
+use std::io;
+
+const ID_CMD_RESOLVE: u8 = 0x01;
+const ID_CMD_RELAY: u8 = 0x02;
+
+pub enum Command {
+    Resolve(Vec<u8>),
+    Relay(String, Vec<u8>),
+}
+
+impl Command {
+    #[cfg(feature = "frontend")]
+    pub(crate) fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::Resolve(query) => {
+                let buf = [ID_CMD_RESOLVE; 1];
+                stream.write_all(&buf)?;
+                util::serialize_bytes(stream, query)?;
+            }
+            Self::Relay(server, query) => {
+                let buf = [ID_CMD_RELAY; 1];
+                stream.write_all(&buf)?;
+                util::serialize_string(stream, server)?;
+                util::serialize_bytes(stream, query)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "backend")]
+    pub(crate) fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            ID_CMD_RESOLVE => {
+                let query = util::deserialize_bytes(stream)?;
+                Ok(Self::Resolve(query))
+            }
+            ID_CMD_RELAY => {
+                let server = util::deserialize_string(stream)?;
+                let query = util::deserialize_bytes(stream)?;
+                Ok(Self::Relay(server, query))
+            }
+            _ => {
+                #[cfg(not(feature = "log"))]
+                {
+                    Err(io::Error::new(io::ErrorKind::InvalidData, ""))
+                }
+
+                #[cfg(feature = "log")]
+                {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid command",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+const ID_RESP_ANSWER: u8 = 0x01;
+const ID_RESP_FAILED: u8 = 0x02;
+
+pub enum Response {
+    Answer(Vec<u8>),
+    Failed,
+}
+
+impl Response {
+    #[cfg(feature = "backend")]
+    pub(crate) fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::Answer(answer) => {
+                let buf = [ID_RESP_ANSWER; 1];
+                stream.write_all(&buf)?;
+                util::serialize_bytes(stream, answer)?;
+            }
+            Self::Failed => {
+                let buf = [ID_RESP_FAILED; 1];
+                stream.write_all(&buf)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "frontend")]
+    pub(crate) fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            ID_RESP_ANSWER => {
+                let answer = util::deserialize_bytes(stream)?;
+                Ok(Self::Answer(answer))
+            }
+            ID_RESP_FAILED => Ok(Self::Failed),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid response",
+            )),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use super::protocol;
-use crate::{rdp, service};
-use std::{io, net};
+use crate::{outbound, rdp, service};
+use std::io;
 
 const SERVICE_KIND: service::Kind = service::Kind::Backend;
 
@@ -13,7 +13,7 @@ pub fn handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
         protocol::Command::Connect(dest) => {
             crate::info!("connecting to {dest:#?}");
 
-            match net::TcpStream::connect(&dest) {
+            match outbound::connect(&dest) {
                 Err(e) => {
                     crate::warn!("failed to connect to {dest:#?}: {e}");
                     protocol::Response::Error(e.to_string()).send(&mut stream)
@@ -6,7 +6,7 @@ use crate::service;
 mod backend;
 #[cfg(feature = "frontend")]
 mod frontend;
-mod protocol;
+pub(crate) mod protocol;
 
 pub static SERVICE: service::Service = service::Service {
     internal: true,
@@ -16,7 +16,9 @@ pub static SERVICE: service::Service = service::Service {
         tcp: Some(sfrontend::FrontendTcp {
             default_port: 0,
             handler: frontend::tcp_handler,
+            background: None,
         }),
+        udp: None,
     }),
     #[cfg(feature = "backend")]
     backend: Some(service::Backend {
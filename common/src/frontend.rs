@@ -1,12 +1,52 @@
 use crate::{api, channel, service};
 
-use std::{io, net, thread};
+use std::{
+    collections, io, net,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
+
+const UDP_MAX_DATAGRAM_SIZE: usize = 65535;
+
+// Datagrams handled at the same time, each with its own thread and RDP
+// stream, the next ones being dropped until some are done
+const UDP_MAX_PENDING: usize = 64;
+
+#[derive(Default)]
+pub struct FrontendSettings(collections::BTreeMap<String, Vec<String>>);
+
+impl FrontendSettings {
+    pub fn insert(&mut self, key: String, value: String) {
+        self.0.entry(key).or_default().push(value);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.first()).map(String::as_str)
+    }
+
+    pub fn get_all(&self, key: &str) -> &[String] {
+        self.0.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    pub fn get_bool(&self, key: &str) -> bool {
+        self.get(key)
+            .is_some_and(|v| matches!(v.to_lowercase().as_str(), "true" | "yes" | "on" | "1"))
+    }
+}
 
 pub struct FrontendTcpServer {
     service: &'static service::Service,
     server: net::TcpListener,
     custom_data: Option<String>,
+    settings: FrontendSettings,
     pub(crate) ip: net::IpAddr,
+    // set when clients are no longer accepted, for the background
+    // thread to return
+    stopped: AtomicBool,
 }
 
 impl FrontendTcpServer {
@@ -18,10 +58,19 @@ impl FrontendTcpServer {
         self.custom_data.as_ref()
     }
 
+    pub const fn settings(&self) -> &FrontendSettings {
+        &self.settings
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
     pub fn bind(
         service: &'static service::Service,
         tcp: net::SocketAddr,
         custom_data: Option<String>,
+        settings: FrontendSettings,
     ) -> Result<Self, io::Error> {
         let data = custom_data
             .as_ref()
@@ -36,7 +85,9 @@ impl FrontendTcpServer {
             service,
             server,
             custom_data,
+            settings,
             ip,
+            stopped: AtomicBool::new(false),
         })
     }
 
@@ -46,7 +97,21 @@ impl FrontendTcpServer {
             .and_then(Frontend::tcp)
             .map_or(Ok(()), |frontend_tcp| {
                 thread::scope(|scope| {
-                    loop {
+                    if let Some(background) = frontend_tcp.background {
+                        thread::Builder::new()
+                            .name(format!(
+                                "{} {} background",
+                                service::Kind::Frontend,
+                                self.service
+                            ))
+                            .spawn_scoped(scope, move || {
+                                if let Err(e) = background(self, channel) {
+                                    crate::error!("{} background error: {e}", self.service);
+                                }
+                            })?;
+                    }
+
+                    let accept = || loop {
                         let (client, client_addr) = self.server.accept()?;
 
                         crate::debug!("new client {client_addr}");
@@ -63,6 +128,93 @@ impl FrontendTcpServer {
                                     crate::debug!("error: {e}");
                                 }
                             })?;
+                    };
+
+                    let res = accept();
+                    // the scope waits for the background thread
+                    self.stopped.store(true, Ordering::Relaxed);
+                    res
+                })
+            })
+    }
+}
+
+pub struct FrontendUdpServer {
+    service: &'static service::Service,
+    socket: net::UdpSocket,
+    settings: FrontendSettings,
+}
+
+impl FrontendUdpServer {
+    pub const fn service(&self) -> &service::Service {
+        self.service
+    }
+
+    pub const fn settings(&self) -> &FrontendSettings {
+        &self.settings
+    }
+
+    pub(crate) const fn socket(&self) -> &net::UdpSocket {
+        &self.socket
+    }
+
+    pub fn bind(
+        service: &'static service::Service,
+        udp: net::SocketAddr,
+        settings: FrontendSettings,
+    ) -> Result<Self, io::Error> {
+        crate::info!("binding {service} clients on {udp}/udp");
+
+        let socket = net::UdpSocket::bind(udp)?;
+
+        Ok(Self {
+            service,
+            socket,
+            settings,
+        })
+    }
+
+    pub fn start<'a>(&'a self, channel: &'a channel::Channel) -> Result<(), io::Error> {
+        self.service
+            .frontend()
+            .and_then(Frontend::udp)
+            .map_or(Ok(()), |frontend_udp| {
+                let pending = AtomicUsize::new(0);
+
+                thread::scope(|scope| {
+                    let mut buf = vec![0u8; UDP_MAX_DATAGRAM_SIZE];
+
+                    loop {
+                        let (read, client_addr) = self.socket.recv_from(&mut buf)?;
+
+                        crate::trace!("new datagram from {client_addr}");
+
+                        if UDP_MAX_PENDING <= pending.load(Ordering::Relaxed) {
+                            crate::debug!("too many pending datagrams, dropping {client_addr}");
+                            continue;
+                        }
+                        pending.fetch_add(1, Ordering::Relaxed);
+
+                        let datagram = buf[..read].to_vec();
+                        let pending = &pending;
+
+                        thread::Builder::new()
+                            .name(format!(
+                                "{} {} {client_addr}/udp",
+                                service::Kind::Frontend,
+                                self.service
+                            ))
+                            .spawn_scoped(scope, move || {
+                                if let Err(e) = (frontend_udp.handler)(
+                                    self,
+                                    scope,
+                                    (datagram, client_addr),
+                                    channel,
+                                ) {
+                                    crate::debug!("error: {e}");
+                                }
+                                pending.fetch_sub(1, Ordering::Relaxed);
+                            })?;
                     }
                 })
             })
@@ -78,17 +230,34 @@ type FrontendHandler<S, C> = for<'a> fn(
 
 type FrontendTcpHandler = FrontendHandler<FrontendTcpServer, net::TcpStream>;
 
+type FrontendUdpHandler = FrontendHandler<FrontendUdpServer, (Vec<u8>, net::SocketAddr)>;
+
+type FrontendTcpBackground =
+    fn(server: &FrontendTcpServer, channel: &channel::Channel) -> Result<(), api::Error>;
+
 pub struct FrontendTcp {
     pub default_port: u16,
     pub(crate) handler: FrontendTcpHandler,
+    // Runs alongside the server, independently of its clients
+    pub(crate) background: Option<FrontendTcpBackground>,
+}
+
+pub struct FrontendUdp {
+    pub default_port: u16,
+    pub(crate) handler: FrontendUdpHandler,
 }
 
 pub struct Frontend {
     pub(crate) tcp: Option<FrontendTcp>,
+    pub(crate) udp: Option<FrontendUdp>,
 }
 
 impl Frontend {
     pub const fn tcp(&self) -> Option<&FrontendTcp> {
         self.tcp.as_ref()
     }
+
+    pub const fn udp(&self) -> Option<&FrontendUdp> {
+        self.udp.as_ref()
+    }
 }
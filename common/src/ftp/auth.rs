@@ -0,0 +1,96 @@
+use super::protocol;
+use crate::frontend;
+use ring::pbkdf2;
+use std::num;
+
+// Users are configured with "users.<name>.password", and optionally
+// "users.<name>.root", "users.<name>.read_only", "users.<name>.overwrite"
+// and "users.<name>.deny"
+const USERS: &str = "users.";
+
+// Password hashes are "pbkdf2-sha256$<iterations>$<salt>$<hash>",
+// with the salt and the hash in hexadecimal
+const SCHEME: &str = "pbkdf2-sha256";
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn verify(hash: &str, password: &str) -> bool {
+    let mut parts = hash.split('$');
+    let (Some(SCHEME), Some(iterations), Some(salt), Some(hash), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        crate::warn!("invalid password hash format");
+        return false;
+    };
+
+    let (Ok(iterations), Some(salt), Some(hash)) = (
+        iterations.parse::<num::NonZeroU32>(),
+        hex_decode(salt),
+        hex_decode(hash),
+    ) else {
+        crate::warn!("invalid password hash format");
+        return false;
+    };
+
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+// Configured with the "root", "read_only", "overwrite" and "deny"
+// settings, under the given prefix
+pub fn scope(settings: &frontend::FrontendSettings, prefix: &str) -> protocol::Scope {
+    protocol::Scope {
+        root: settings.get(&format!("{prefix}root")).map(str::to_string),
+        read_only: settings.get_bool(&format!("{prefix}read_only")),
+        overwrite: settings.get_bool(&format!("{prefix}overwrite")),
+        deny: settings.get_all(&format!("{prefix}deny")).to_vec(),
+    }
+}
+
+// Checks the credentials sent with USER and PASS and returns the
+// restrictions of the user: its own root replaces the global one,
+// read-only modes, overwrite permissions and denied patterns add up. Any credentials are
+// accepted when no user is configured.
+pub fn login(
+    settings: &frontend::FrontendSettings,
+    user: &str,
+    password: &str,
+) -> Option<protocol::Scope> {
+    let global = scope(settings, "");
+
+    if !settings.keys().any(|key| key.starts_with(USERS)) {
+        return Some(global);
+    }
+
+    let prefix = format!("{USERS}{user}.");
+    let hash = settings.get(&format!("{prefix}password"))?;
+    if !verify(hash, password) {
+        return None;
+    }
+
+    let mut user = scope(settings, &prefix);
+    user.root = user.root.or(global.root);
+    user.read_only |= global.read_only;
+    user.overwrite |= global.overwrite;
+    user.deny.extend(global.deny);
+
+    Some(user)
+}
@@ -1,52 +1,92 @@
-use super::protocol;
+use super::{listing, protocol, vfs};
 use crate::{rdp, service};
 use std::{
-    env, fs,
-    io::{self, Write},
+    fs,
+    io::{self, Read, Seek, Write},
     path,
+    sync::atomic::{self, AtomicBool},
+    thread,
 };
 
-#[allow(clippy::too_many_lines)]
-fn control_handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
-    crate::debug!("starting control");
+// Replies to a command which needs a real path accessible in the scope
+macro_rules! real_path {
+    ($scope:expr, $path:expr) => {
+        match $scope.to_real(&$path) {
+            None => return protocol::ControlResponse::Error(550),
+            Some(path) => path,
+        }
+    };
+}
 
-    let mut cwd = env::current_dir()?;
+struct Session {
+    scope: vfs::Scope,
+    cwd: vfs::VirtualPath,
+    // set by RNFR, only valid for the command which immediately follows
+    rename_from: Option<path::PathBuf>,
+    // set by REST, same as above
+    restart_offset: Option<u64>,
+}
 
-    let mut quit = false;
+impl Session {
+    fn path(&self, path: &str) -> vfs::VirtualPath {
+        if path.is_empty() {
+            self.cwd.clone()
+        } else {
+            self.cwd.join(path)
+        }
+    }
 
-    loop {
-        let command = protocol::ControlCommand::receive(&mut stream)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    #[allow(clippy::too_many_lines)]
+    fn command(&mut self, command: protocol::ControlCommand) -> protocol::ControlResponse {
+        let pending_rename = self.rename_from.take();
+        let restart_offset = self.restart_offset.take();
+        let offset = restart_offset.unwrap_or_default();
 
-        crate::trace!("received {command:?}");
+        if self.scope.is_read_only()
+            && matches!(
+                command,
+                protocol::ControlCommand::Appe(_)
+                    | protocol::ControlCommand::Dele(_)
+                    | protocol::ControlCommand::Mfmt(_)
+                    | protocol::ControlCommand::Mkd(_)
+                    | protocol::ControlCommand::Rmd(_)
+                    | protocol::ControlCommand::Rnfr(_)
+                    | protocol::ControlCommand::Rnto(_)
+                    | protocol::ControlCommand::Stor(_)
+            )
+        {
+            return protocol::ControlResponse::Error(550);
+        }
 
-        let resp = match command {
-            protocol::ControlCommand::Cdup => {
-                if cwd.pop() {
-                    protocol::ControlResponse::Ok(250, None)
-                } else {
+        match command {
+            protocol::ControlCommand::Appe(path) => {
+                let path = real_path!(self.scope, self.path(&path));
+                if path.is_dir() {
                     protocol::ControlResponse::Error(550)
+                } else {
+                    protocol::ControlResponse::Data(protocol::DataCommand::Appe(
+                        path.display().to_string(),
+                    ))
                 }
             }
+            protocol::ControlCommand::Cdup => match self.cwd.parent() {
+                None => protocol::ControlResponse::Error(550),
+                Some(parent) => {
+                    self.cwd = parent;
+                    protocol::ControlResponse::Ok(250, None)
+                }
+            },
             protocol::ControlCommand::Cwd(path) => {
-                let path = if path.starts_with('/') {
-                    path::PathBuf::from(path)
-                } else {
-                    cwd.join(path)
-                };
-                if path.exists() && path.is_dir() {
-                    cwd = path;
+                let path = self.path(&path);
+                if self.scope.is_dir(&path) {
+                    self.cwd = path;
                     protocol::ControlResponse::Ok(250, None)
                 } else {
                     protocol::ControlResponse::Error(550)
                 }
             }
             protocol::ControlCommand::Dele(path) => {
-                let path = if path.starts_with('/') {
-                    path::PathBuf::from(path)
-                } else {
-                    cwd.join(path)
-                };
+                let path = real_path!(self.scope, self.path(&path));
                 if let Err(e) = fs::remove_file(&path) {
                     crate::warn!("failed to delete {path:?}: {e}");
                     protocol::ControlResponse::Error(550)
@@ -56,77 +96,266 @@ fn control_handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
             }
             protocol::ControlCommand::Epsv => protocol::ControlResponse::Epsv,
             protocol::ControlCommand::Feat => protocol::ControlResponse::Feat,
-            protocol::ControlCommand::List => protocol::ControlResponse::Data(
-                protocol::DataCommand::List(cwd.display().to_string()),
-            ),
-            protocol::ControlCommand::Nlst => protocol::ControlResponse::Data(
-                protocol::DataCommand::Nlst(cwd.display().to_string()),
-            ),
-            protocol::ControlCommand::Opts => protocol::ControlResponse::Ok(200, None),
-            protocol::ControlCommand::Pass | protocol::ControlCommand::Type => {
-                protocol::ControlResponse::Ok(230, None)
+            protocol::ControlCommand::List => {
+                protocol::ControlResponse::Data(protocol::DataCommand::List(self.cwd.to_string()))
             }
-            protocol::ControlCommand::Pasv => protocol::ControlResponse::Pasv,
-            protocol::ControlCommand::Pwd => {
-                protocol::ControlResponse::Ok(257, Some(format!("{:?}", cwd.display())))
+            protocol::ControlCommand::Mdtm(path) => {
+                let path = real_path!(self.scope, self.path(&path));
+                match path.metadata() {
+                    Ok(metadata) if metadata.is_file() => protocol::ControlResponse::Ok(
+                        213,
+                        Some(listing::format_time_val(&metadata)),
+                    ),
+                    _ => protocol::ControlResponse::Error(550),
+                }
             }
-            protocol::ControlCommand::Quit => {
-                quit = true;
-                protocol::ControlResponse::Quit
+            protocol::ControlCommand::Mfmt(args) => {
+                let Some((time_val, time, path)) =
+                    args.split_once(' ').and_then(|(time_val, path)| {
+                        listing::parse_time_val(time_val).map(|time| (time_val, time, path))
+                    })
+                else {
+                    return protocol::ControlResponse::Error(501);
+                };
+                let full_path = real_path!(self.scope, self.path(path));
+                let res = fs::File::options()
+                    .write(true)
+                    .open(&full_path)
+                    .and_then(|file| file.set_modified(time));
+                if let Err(e) = res {
+                    crate::warn!("failed to set modification time of {full_path:?}: {e}");
+                    protocol::ControlResponse::Error(550)
+                } else {
+                    protocol::ControlResponse::Ok(213, Some(format!("Modify={time_val}; {path}")))
+                }
             }
-            protocol::ControlCommand::Retr(path) => {
-                let path = if path.starts_with('/') {
-                    path::PathBuf::from(path)
+            protocol::ControlCommand::Mkd(path) => {
+                let path = self.path(&path);
+                let real = real_path!(self.scope, path);
+                if let Err(e) = fs::create_dir(&real) {
+                    crate::warn!("failed to create {real:?}: {e}");
+                    protocol::ControlResponse::Error(550)
                 } else {
-                    cwd.join(path)
-                };
-                if path.exists() && path.is_file() {
-                    protocol::ControlResponse::Data(protocol::DataCommand::Retr(
-                        path.display().to_string(),
-                    ))
+                    protocol::ControlResponse::Ok(257, Some(format!("{} created", quote(&path))))
+                }
+            }
+            protocol::ControlCommand::Mlsd(path) => {
+                let path = self.path(&path);
+                if self.scope.is_dir(&path) {
+                    protocol::ControlResponse::Data(protocol::DataCommand::Mlsd(path.to_string()))
                 } else {
                     protocol::ControlResponse::Error(550)
                 }
             }
-            protocol::ControlCommand::Size(path) => {
-                let path = if path.starts_with('/') {
-                    path::PathBuf::from(path)
+            protocol::ControlCommand::Mlst(path) => {
+                let path = self.path(&path);
+                if self.scope.is_virtual_dir(&path) {
+                    return protocol::ControlResponse::Mlst(format!("type=dir;perm=el; {path}"));
+                }
+                let real = real_path!(self.scope, path);
+                real.metadata()
+                    .map_or(protocol::ControlResponse::Error(550), |metadata| {
+                        let facts = listing::facts(&real, &metadata, self.scope.is_read_only());
+                        protocol::ControlResponse::Mlst(format!("{facts} {path}"))
+                    })
+            }
+            protocol::ControlCommand::Nlst => {
+                protocol::ControlResponse::Data(protocol::DataCommand::Nlst(self.cwd.to_string()))
+            }
+            protocol::ControlCommand::Opts => protocol::ControlResponse::Ok(200, None),
+            protocol::ControlCommand::Type => protocol::ControlResponse::Ok(230, None),
+            protocol::ControlCommand::Pasv => protocol::ControlResponse::Pasv,
+            protocol::ControlCommand::Pwd => protocol::ControlResponse::Ok(
+                257,
+                Some(format!("{} is the current directory", quote(&self.cwd))),
+            ),
+            protocol::ControlCommand::Quit => protocol::ControlResponse::Quit,
+            protocol::ControlCommand::Rest(arg) => {
+                arg.trim()
+                    .parse::<u64>()
+                    .map_or(protocol::ControlResponse::Error(501), |offset| {
+                        self.restart_offset = Some(offset);
+                        protocol::ControlResponse::Ok(
+                            350,
+                            Some(format!("Restarting at {offset}, send STOR or RETR")),
+                        )
+                    })
+            }
+            protocol::ControlCommand::Retr(path) => {
+                let path = real_path!(self.scope, self.path(&path));
+                match path.metadata() {
+                    Ok(metadata) if metadata.is_file() => {
+                        if metadata.len() < offset {
+                            protocol::ControlResponse::Error(554)
+                        } else {
+                            protocol::ControlResponse::Data(protocol::DataCommand::Retr(
+                                path.display().to_string(),
+                                offset,
+                            ))
+                        }
+                    }
+                    _ => protocol::ControlResponse::Error(550),
+                }
+            }
+            protocol::ControlCommand::Rmd(path) => {
+                let path = real_path!(self.scope, self.path(&path));
+                if let Err(e) = fs::remove_dir(&path) {
+                    crate::warn!("failed to remove {path:?}: {e}");
+                    protocol::ControlResponse::Error(550)
                 } else {
-                    cwd.join(path)
+                    protocol::ControlResponse::Ok(250, None)
+                }
+            }
+            protocol::ControlCommand::Rnfr(path) => {
+                let path = real_path!(self.scope, self.path(&path));
+                if path.symlink_metadata().is_ok() {
+                    self.rename_from = Some(path);
+                    protocol::ControlResponse::Ok(350, Some("Ready for RNTO".to_string()))
+                } else {
+                    protocol::ControlResponse::Error(550)
+                }
+            }
+            protocol::ControlCommand::Rnto(path) => {
+                let Some(from) = pending_rename else {
+                    return protocol::ControlResponse::Error(503);
                 };
+                let to = real_path!(self.scope, self.path(&path));
+                if let Err(e) = fs::rename(&from, &to) {
+                    crate::warn!("failed to rename {from:?} to {to:?}: {e}");
+                    protocol::ControlResponse::Error(550)
+                } else {
+                    protocol::ControlResponse::Ok(250, None)
+                }
+            }
+            protocol::ControlCommand::Size(path) => {
+                let path = real_path!(self.scope, self.path(&path));
                 path.metadata()
-                    .map_or(protocol::ControlResponse::Error(540), |metadata| {
+                    .map_or(protocol::ControlResponse::Error(550), |metadata| {
                         let size = metadata.len();
                         protocol::ControlResponse::Ok(213, Some(format!("{size}")))
                     })
             }
             protocol::ControlCommand::Stor(path) => {
-                let path = if path.starts_with('/') {
-                    path::PathBuf::from(path)
-                } else {
-                    cwd.join(path)
-                };
-                if path.exists() {
-                    protocol::ControlResponse::Error(450)
-                } else {
-                    protocol::ControlResponse::Data(protocol::DataCommand::Stor(
+                let path = real_path!(self.scope, self.path(&path));
+                let len = path
+                    .metadata()
+                    .ok()
+                    .map(|metadata| (metadata.is_file(), metadata.len()));
+                match len {
+                    Some((false, _)) => protocol::ControlResponse::Error(550),
+                    // existing files are only replaced when resuming or
+                    // when the scope allows it
+                    Some((true, _)) if restart_offset.is_none() && !self.scope.can_overwrite() => {
+                        protocol::ControlResponse::Error(450)
+                    }
+                    // resuming requires the file to be at least as large as the offset
+                    Some((true, len)) if len < offset => protocol::ControlResponse::Error(554),
+                    None if 0 < offset => protocol::ControlResponse::Error(554),
+                    _ => protocol::ControlResponse::Data(protocol::DataCommand::Stor(
                         path.display().to_string(),
-                    ))
+                        offset,
+                    )),
                 }
             }
-            protocol::ControlCommand::User => protocol::ControlResponse::Ok(331, None),
-        };
+        }
+    }
+}
+
+// Quotes a path in a 257 reply (RFC 959)
+fn quote(path: &vfs::VirtualPath) -> String {
+    format!("\"{}\"", path.to_string().replace('"', "\"\""))
+}
+
+fn control_handler(mut stream: rdp::RdpStream<'_>, scope: vfs::Scope) -> Result<(), io::Error> {
+    crate::debug!("starting control");
+
+    let mut session = Session {
+        cwd: scope.home()?,
+        scope,
+        rename_from: None,
+        restart_offset: None,
+    };
+
+    loop {
+        let command = protocol::ControlCommand::receive(&mut stream)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        crate::trace!("received {command:?}");
+
+        let resp = session.command(command);
 
         resp.send(&mut stream)
             .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))?;
 
-        if quit {
+        if matches!(resp, protocol::ControlResponse::Quit) {
             return Ok(());
         }
     }
 }
 
-fn data_handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
+// Stops reading once the frontend aborted the transfer
+struct Abortable<'a, R> {
+    inner: R,
+    aborted: &'a AtomicBool,
+}
+
+impl<R> io::Read for Abortable<'_, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        if self.aborted.load(atomic::Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "transfer aborted",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+// The frontend ends the stream when the transfer is aborted (ABOR),
+// which is only noticed by reading from it
+fn retr<R>(stream: rdp::RdpStream<'_>, file: R) -> Result<(), io::Error>
+where
+    R: io::Read,
+{
+    #[cfg(feature = "log")]
+    let client_id = stream.client_id();
+
+    let (mut stream_read, mut stream_write) = stream.split();
+    let aborted = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} {client_id:x} abort",
+            service::Kind::Backend,
+            super::SERVICE,
+        ));
+        let aborted = &aborted;
+        thread.spawn_scoped(scope, move || {
+            let _ = stream_read.read(&mut [0u8; 1]);
+            aborted.store(true, atomic::Ordering::Relaxed);
+        })?;
+
+        let mut file = Abortable {
+            inner: file,
+            aborted,
+        };
+        if let Err(e) = service::stream_copy(&mut file, &mut stream_write, false) {
+            crate::debug!("error: {e}");
+        }
+        stream_write.flush()?;
+        // ends the stream on this side, the frontend then ends it too
+        drop(stream_write);
+
+        Ok(())
+    })
+}
+
+fn data_handler(mut stream: rdp::RdpStream<'_>, scope: &vfs::Scope) -> Result<(), io::Error> {
     crate::debug!("starting data");
 
     let cmd = protocol::DataCommand::receive(&mut stream)
@@ -134,73 +363,72 @@ fn data_handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
 
     crate::debug!("received {cmd:?}");
 
+    if scope.is_read_only()
+        && matches!(
+            cmd,
+            protocol::DataCommand::Stor(_, _) | protocol::DataCommand::Appe(_)
+        )
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "read-only session",
+        ));
+    }
+
     match cmd {
         protocol::DataCommand::List(path) => {
-            let path = path::PathBuf::from(path);
-            if let Ok(dir) = path.read_dir() {
-                dir.into_iter().try_for_each(|entry| {
-                    if let Ok(entry) = entry
-                        && let Ok(file_type) = entry.file_type()
-                    {
-                        if file_type.is_dir() {
-                            write!(stream, "d")?;
-                        } else if file_type.is_file() {
-                            write!(stream, "-")?;
-                        } else {
-                            write!(stream, "l")?;
-                        }
-                        let _ = write!(stream, "rwxrwxrwx ");
-
-                        if file_type.is_dir() {
-                            let _ = write!(stream, "2 ftp ftp ");
-                        } else {
-                            let _ = write!(stream, "1 ftp ftp ");
-                        }
-
-                        if let Ok(metadata) = entry.metadata() {
-                            write!(stream, "{} ", metadata.len())?;
-                        } else {
-                            write!(stream, "0 ")?;
-                        }
-
-                        write!(stream, "Jan 1 1970 ")?;
-
-                        write!(stream, "{}\r\n", entry.file_name().into_string().unwrap())?;
-                    }
-
-                    Ok::<(), io::Error>(())
-                })?;
+            for (name, path) in scope.read_dir(&vfs::VirtualPath::default().join(&path))? {
+                if let Ok(link_metadata) = path.symlink_metadata() {
+                    let metadata = path.metadata().unwrap_or_else(|_| link_metadata.clone());
+                    let line = listing::list_line(&name, link_metadata.file_type(), &metadata);
+                    write!(stream, "{line}\r\n")?;
+                }
             }
         }
 
         protocol::DataCommand::Nlst(path) => {
-            let path = path::PathBuf::from(path);
-            if let Ok(dir) = path.read_dir() {
-                dir.into_iter().try_for_each(|entry| {
-                    if let Ok(entry) = entry {
-                        write!(stream, "{}\r\n", entry.file_name().into_string().unwrap())?;
-                    }
-                    Ok::<(), io::Error>(())
-                })?;
+            for (name, _) in scope.read_dir(&vfs::VirtualPath::default().join(&path))? {
+                write!(stream, "{name}\r\n")?;
             }
         }
 
-        protocol::DataCommand::Retr(path) => {
-            let path = path::PathBuf::from(path);
-            let file = fs::File::options().read(true).write(false).open(path)?;
-            let mut file = io::BufReader::new(file);
-            if let Err(e) = service::stream_copy(&mut file, &mut stream, false) {
-                crate::debug!("error: {e}");
+        protocol::DataCommand::Mlsd(path) => {
+            for (name, path) in scope.read_dir(&vfs::VirtualPath::default().join(&path))? {
+                if let Ok(metadata) = path.metadata() {
+                    let facts = listing::facts(&path, &metadata, scope.is_read_only());
+                    write!(stream, "{facts} {name}\r\n")?;
+                }
             }
         }
 
-        protocol::DataCommand::Stor(path) => {
+        protocol::DataCommand::Retr(path, offset) => {
             let path = path::PathBuf::from(path);
-            let file = fs::File::options()
+            let mut file = fs::File::options().read(true).write(false).open(path)?;
+            file.seek(io::SeekFrom::Start(offset))?;
+            return retr(stream, io::BufReader::new(file));
+        }
+
+        protocol::DataCommand::Stor(path, offset) => {
+            let path = path::PathBuf::from(path);
+            let mut file = fs::File::options()
                 .create(true)
                 .truncate(false)
                 .write(true)
                 .open(path)?;
+            // drops what follows the offset (i.e. all the content
+            // without REST), as an interrupted upload may have left
+            // partial data
+            file.set_len(offset)?;
+            file.seek(io::SeekFrom::Start(offset))?;
+            let mut file = io::BufWriter::new(file);
+            if let Err(e) = service::stream_copy(&mut stream, &mut file, false) {
+                crate::debug!("error: {e}");
+            }
+        }
+
+        protocol::DataCommand::Appe(path) => {
+            let path = path::PathBuf::from(path);
+            let file = fs::File::options().create(true).append(true).open(path)?;
             let mut file = io::BufWriter::new(file);
             if let Err(e) = service::stream_copy(&mut stream, &mut file, false) {
                 crate::debug!("error: {e}");
@@ -217,8 +445,12 @@ pub fn handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
     let mode = protocol::BackendMode::receive(&mut stream)
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
 
+    let scope = protocol::Scope::receive(&mut stream)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let scope = vfs::Scope::new(scope)?;
+
     match mode {
-        protocol::BackendMode::Control => control_handler(stream),
-        protocol::BackendMode::Data => data_handler(stream),
+        protocol::BackendMode::Control => control_handler(stream, scope),
+        protocol::BackendMode::Data => data_handler(stream, &scope),
     }
 }
@@ -1,25 +1,159 @@
-use super::protocol;
-use crate::{api, channel, frontend, service};
+use super::{auth, protocol, tls};
+use crate::{api, channel, frontend, rdp, service};
 use std::{
     io::{self, Write},
-    net, thread,
+    net,
+    sync::{
+        Arc,
+        atomic::{self, AtomicU64},
+    },
+    thread, time,
 };
 
-fn parse_command<R>(r: &mut R) -> Result<Option<protocol::ControlCommand>, io::Error>
+const ACTIVE_CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+const TELNET_IAC: u8 = 0xff;
+
+const LOGIN_FAILURE_DELAY: time::Duration = time::Duration::from_secs(1);
+
+enum Command {
+    Control(protocol::ControlCommand),
+    // PORT and EPRT are handled by the frontend, None when the
+    // address is invalid
+    Port(Option<net::SocketAddr>),
+    Abor,
+    Stat,
+    // TLS related commands are also handled by the frontend (RFC 4217)
+    Auth(String),
+    Pbsz,
+    Prot(String),
+    // as well as authentication, before connecting to the backend
+    User(String),
+    Pass(String),
+}
+
+// Backend control connection, opened once logged in
+struct Session<'a> {
+    backend: rdp::RdpStream<'a>,
+    scope: protocol::Scope,
+}
+
+type Commands = crossbeam_channel::Receiver<Result<Option<Command>, io::Error>>;
+
+// Counts the bytes going through a transfer, for STAT
+struct Counter<'a, R> {
+    inner: R,
+    count: &'a AtomicU64,
+}
+
+impl<R> io::Read for Counter<'_, R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let read = self.inner.read(buf)?;
+        self.count.fetch_add(read as u64, atomic::Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+// Removes the Telnet commands (IAC IP, IAC DM) clients send before
+// ABOR (RFC 959)
+fn strip_telnet(line: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(line.len());
+    let mut bytes = line.iter().copied();
+    while let Some(b) = bytes.next() {
+        if b == TELNET_IAC {
+            bytes.next();
+        } else {
+            res.push(b);
+        }
+    }
+    res
+}
+
+// How data connections are established
+enum DataMode {
+    // the client connects to the listener of Data
+    Passive,
+    // the frontend connects to the client
+    Active(net::SocketAddr),
+}
+
+struct Data {
+    listener: net::TcpListener,
+    mode: DataMode,
+    // set by PROT P
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Data {
+    // Also drops the connections left by clients for a previous data
+    // command which was refused, which would be accepted instead of
+    // the next ones
+    fn passive(&mut self) -> Result<(), io::Error> {
+        self.mode = DataMode::Passive;
+
+        self.listener.set_nonblocking(true)?;
+        while self.listener.accept().is_ok() {}
+        self.listener.set_nonblocking(false)
+    }
+}
+
+// "h1,h2,h3,h4,p1,p2" (RFC 959)
+fn parse_port(args: &str) -> Option<net::SocketAddr> {
+    let bytes = args
+        .split(',')
+        .map(|b| b.trim().parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()?;
+    let [a, b, c, d, p1, p2] = bytes.as_slice() else {
+        return None;
+    };
+    let ip = net::Ipv4Addr::new(*a, *b, *c, *d);
+    Some(net::SocketAddr::new(
+        net::IpAddr::V4(ip),
+        u16::from_be_bytes([*p1, *p2]),
+    ))
+}
+
+// "<d>proto<d>address<d>port<d>" (RFC 2428)
+fn parse_eprt(args: &str) -> Option<net::SocketAddr> {
+    let delimiter = args.chars().next()?;
+    let mut parts = args.split(delimiter);
+    let (Some(""), Some(proto), Some(ip), Some(port), Some(""), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return None;
+    };
+    let ip: net::IpAddr = match proto {
+        "1" => net::IpAddr::V4(ip.parse().ok()?),
+        "2" => net::IpAddr::V6(ip.parse().ok()?),
+        _ => return None,
+    };
+    Some(net::SocketAddr::new(ip, port.parse().ok()?))
+}
+
+fn parse_command<R>(r: &mut R) -> Result<Option<Command>, io::Error>
 where
     R: io::BufRead,
 {
-    let mut line = String::new();
-    let read = r.read_line(&mut line)?;
+    let mut line = vec![];
+    let read = r.read_until(b'\n', &mut line)?;
     if read == 0 {
         return Err(io::Error::new(io::ErrorKind::BrokenPipe, "disconnected"));
     }
 
-    let Some(line) = line.strip_suffix("\r\n") else {
+    let Some(line) = line.strip_suffix(b"\r\n") else {
         return Ok(None);
     };
-
-    crate::debug!("{line:?}");
+    let line = String::from_utf8_lossy(&strip_telnet(line)).to_string();
+    let line = line.as_str();
 
     let (command, args) = line
         .split_once(' ')
@@ -27,55 +161,201 @@ where
         .unwrap_or((line, String::new()));
     let command = command.to_uppercase();
 
+    // passwords are not logged
+    if command == "PASS" {
+        crate::debug!("\"PASS ****\"");
+    } else {
+        crate::debug!("{line:?}");
+    }
+
     let command = match command.as_str() {
+        "ABOR" => return Ok(Some(Command::Abor)),
+        "AUTH" => return Ok(Some(Command::Auth(args))),
+        "EPRT" => return Ok(Some(Command::Port(parse_eprt(&args)))),
+        "PASS" => return Ok(Some(Command::Pass(args))),
+        "PBSZ" => return Ok(Some(Command::Pbsz)),
+        "PORT" => return Ok(Some(Command::Port(parse_port(&args)))),
+        "PROT" => return Ok(Some(Command::Prot(args))),
+        "STAT" => return Ok(Some(Command::Stat)),
+        "USER" => return Ok(Some(Command::User(args))),
+        "APPE" => protocol::ControlCommand::Appe(args),
         "CDUP" => protocol::ControlCommand::Cdup,
         "CWD" => protocol::ControlCommand::Cwd(args),
         "DELE" => protocol::ControlCommand::Dele(args),
         "EPSV" => protocol::ControlCommand::Epsv,
         "FEAT" => protocol::ControlCommand::Feat,
         "LIST" => protocol::ControlCommand::List,
+        "MDTM" => protocol::ControlCommand::Mdtm(args),
+        "MFMT" => protocol::ControlCommand::Mfmt(args),
+        "MKD" | "XMKD" => protocol::ControlCommand::Mkd(args),
+        "MLSD" => protocol::ControlCommand::Mlsd(args),
+        "MLST" => protocol::ControlCommand::Mlst(args),
         "NLST" => protocol::ControlCommand::Nlst,
         "OPTS" => protocol::ControlCommand::Opts,
-        "PASS" => protocol::ControlCommand::Pass,
         "PASV" => protocol::ControlCommand::Pasv,
         "PWD" => protocol::ControlCommand::Pwd,
         "QUIT" => protocol::ControlCommand::Quit,
+        "REST" => protocol::ControlCommand::Rest(args),
         "RETR" => protocol::ControlCommand::Retr(args),
+        "RMD" | "XRMD" => protocol::ControlCommand::Rmd(args),
+        "RNFR" => protocol::ControlCommand::Rnfr(args),
+        "RNTO" => protocol::ControlCommand::Rnto(args),
         "SIZE" => protocol::ControlCommand::Size(args),
         "STOR" => protocol::ControlCommand::Stor(args),
         "TYPE" => protocol::ControlCommand::Type,
-        "USER" => protocol::ControlCommand::User,
         cmd => {
             crate::warn!("command {cmd:?} not implemented");
             return Ok(None);
         }
     };
 
-    Ok(Some(command))
+    Ok(Some(Command::Control(command)))
+}
+
+// Copies data in a dedicated thread while replying to ABOR and STAT,
+// returns the result of the copy and whether it was aborted
+fn transfer(
+    client: &mut tls::Control,
+    commands: &Commands,
+    mut backend: rdp::RdpStream<'_>,
+    data_client: tls::DataStream,
+    data_control: &net::TcpStream,
+    is_upload: bool,
+    cmd: &protocol::DataCommand,
+) -> Result<(Result<(), io::Error>, bool), io::Error> {
+    let count = AtomicU64::new(0);
+    let start = time::Instant::now();
+
+    thread::scope(|scope| {
+        let (done_send, done) = crossbeam_channel::bounded(1);
+        let count = &count;
+
+        let thread = thread::Builder::new();
+        #[cfg(feature = "log")]
+        let thread = thread.name(format!(
+            "{} {} transfer",
+            service::Kind::Frontend,
+            super::SERVICE
+        ));
+        thread.spawn_scoped(scope, move || {
+            let res = if is_upload {
+                let mut data_client_read = Counter {
+                    inner: io::BufReader::new(data_client),
+                    count,
+                };
+                let res = service::stream_copy(&mut data_client_read, &mut backend, false);
+                let _ = backend.flush();
+                // some TLS clients wait for the closure of the connection to
+                // be acknowledged, others are already gone
+                let _ = data_client_read.inner.into_inner().close();
+                res
+            } else {
+                let mut backend_read = Counter {
+                    inner: &mut backend,
+                    count,
+                };
+                let mut data_client_write = io::BufWriter::new(data_client);
+                service::stream_copy(&mut backend_read, &mut data_client_write, false)
+                    .and_then(|()| {
+                        data_client_write
+                            .into_inner()
+                            .map_err(io::IntoInnerError::into_error)
+                    })
+                    .and_then(|mut data_client| data_client.close())
+            };
+            // dropping the stream ends the transfer on the backend side
+            drop(backend);
+            let _ = done_send.send(res);
+        })?;
+
+        let mut aborted = false;
+
+        loop {
+            crossbeam_channel::select! {
+                recv(done) -> res => {
+                    let res = res.unwrap_or_else(|_| Err(io::Error::other("transfer failed")));
+                    return Ok::<_, io::Error>((res, aborted));
+                }
+                recv(commands) -> command => match command {
+                    Ok(Ok(Some(Command::Abor))) => {
+                        crate::debug!("aborting data command {cmd:?}");
+                        aborted = true;
+                        let _ = data_control.shutdown(net::Shutdown::Both);
+                    }
+                    Ok(Ok(Some(Command::Stat))) => {
+                        let bytes = count.load(atomic::Ordering::Relaxed);
+                        let elapsed = start.elapsed();
+                        let rate = u128::from(bytes) * 1000 / elapsed.as_millis().max(1);
+                        client.write_all(
+                            format!(
+                                "213 Transferring: {bytes} bytes in {} s ({rate} bytes/s)\r\n",
+                                elapsed.as_secs()
+                            )
+                            .as_bytes(),
+                        )?;
+                    }
+                    Ok(Ok(Some(_))) => client.write_all(b"503 Transfer in progress\r\n")?,
+                    Ok(Ok(None)) => client.write_all(b"502 Command not implemented\r\n")?,
+                    Ok(Err(e)) => {
+                        let _ = data_control.shutdown(net::Shutdown::Both);
+                        let _ = done.recv();
+                        return Err(e);
+                    }
+                    Err(_) => {
+                        let _ = data_control.shutdown(net::Shutdown::Both);
+                        let _ = done.recv();
+                        return Err(io::Error::new(io::ErrorKind::BrokenPipe, "disconnected"));
+                    }
+                }
+            }
+            client.flush()?;
+        }
+    })
 }
 
 fn data_command(
     channel: &channel::Channel,
-    client: &mut net::TcpStream,
-    data_frontend: &net::TcpListener,
+    client: &mut tls::Control,
+    commands: &Commands,
+    data: &Data,
+    scope: &protocol::Scope,
     cmd: &protocol::DataCommand,
 ) -> Result<(), api::Error> {
+    let active_data_client = match &data.mode {
+        DataMode::Passive => None,
+        DataMode::Active(addr) => {
+            crate::debug!("connecting data to {addr}");
+            match net::TcpStream::connect_timeout(addr, ACTIVE_CONNECT_TIMEOUT) {
+                Err(e) => {
+                    crate::warn!("failed to connect data to {addr}: {e}");
+                    client.write_all(b"425 Can't open data connection\r\n")?;
+                    client.flush()?;
+                    return Ok(());
+                }
+                Ok(data_client) => Some(data_client),
+            }
+        }
+    };
+
     let mut backend = channel.connect(&super::SERVICE)?;
     protocol::BackendMode::Data.send(&mut backend)?;
+    scope.send(&mut backend)?;
 
     cmd.send(&mut backend)?;
 
     let is_upload = match cmd {
-        protocol::DataCommand::List(_) | protocol::DataCommand::Nlst(_) => {
+        protocol::DataCommand::List(_)
+        | protocol::DataCommand::Nlst(_)
+        | protocol::DataCommand::Mlsd(_) => {
             client.write_all("150 Here comes the directory listing\r\n".as_bytes())?;
             false
         }
-        protocol::DataCommand::Retr(_) => {
+        protocol::DataCommand::Retr(_, _) => {
             client
                 .write_all("125 Data connection already open; transfer starting\r\n".as_bytes())?;
             false
         }
-        protocol::DataCommand::Stor(_) => {
+        protocol::DataCommand::Stor(_, _) | protocol::DataCommand::Appe(_) => {
             client
                 .write_all("125 Data connection already open; transfer starting\r\n".as_bytes())?;
             true
@@ -84,23 +364,33 @@ fn data_command(
 
     client.flush()?;
 
-    crate::debug!("accepting data on {}", data_frontend.local_addr()?);
-    let (data_client, _) = data_frontend.accept()?;
-    crate::debug!("data accepted");
-
-    let res = if is_upload {
-        let mut data_client_read = io::BufReader::new(data_client);
-        let res = service::stream_copy(&mut data_client_read, &mut backend, false);
-        let _ = backend.flush();
-        res
+    let data_client = if let Some(data_client) = active_data_client {
+        data_client
     } else {
-        let mut data_client_write = io::BufWriter::new(data_client);
-        let res = service::stream_copy(&mut backend, &mut data_client_write, false);
-        let _ = data_client_write.flush();
-        res
+        crate::debug!("accepting data on {}", data.listener.local_addr()?);
+        let (data_client, _) = data.listener.accept()?;
+        crate::debug!("data accepted");
+        data_client
     };
 
+    let data_control = data_client.try_clone()?;
+    let data_client = tls::DataStream::new(data_client, data.tls.clone())?;
+
+    let (res, aborted) = transfer(
+        client,
+        commands,
+        backend,
+        data_client,
+        &data_control,
+        is_upload,
+        cmd,
+    )?;
+
     match res {
+        _ if aborted => {
+            client.write_all(b"426 Connection closed; transfer aborted\r\n")?;
+            client.write_all(b"226 Abort successful\r\n")?;
+        }
         Err(e) => {
             crate::warn!("data command {cmd:?} failed: {e}");
             client.write_all(b"426 Connection closed; transfer aborted\r\n")?;
@@ -115,30 +405,175 @@ fn data_command(
     Ok(())
 }
 
-pub fn tcp_handler<'a>(
+// Replies to AUTH, PBSZ and PROT, the TLS configuration is loaded
+// upon AUTH TLS
+fn security_command(
     server: &frontend::FrontendTcpServer,
-    _scope: &'a thread::Scope<'a, '_>,
-    mut client: net::TcpStream,
-    channel: &'a channel::Channel,
+    client: &mut tls::Control,
+    data: &mut Data,
+    tls_config: &mut Option<Arc<rustls::ServerConfig>>,
+    command: Command,
+) -> Result<(), io::Error> {
+    match command {
+        Command::Auth(_) if client.is_secure() => {
+            client.write_all(b"503 Already using TLS\r\n")?;
+        }
+        Command::Auth(mechanism) => {
+            if matches!(mechanism.to_uppercase().as_str(), "TLS" | "TLS-C" | "SSL") {
+                match tls::config(server.settings()) {
+                    Err(e) => {
+                        crate::warn!("failed to configure TLS: {e}");
+                        client.write_all(b"431 Unable to accept security mechanism\r\n")?;
+                    }
+                    Ok(config) => {
+                        client.write_all(b"234 Using TLS\r\n")?;
+                        client.flush()?;
+                        client.secure(config.clone())?;
+                        *tls_config = Some(config);
+                    }
+                }
+            } else {
+                client.write_all(b"504 Unsupported security mechanism\r\n")?;
+            }
+        }
+        Command::Pbsz | Command::Prot(_) if !client.is_secure() => {
+            client.write_all(b"503 Use AUTH TLS first\r\n")?;
+        }
+        Command::Pbsz => client.write_all(b"200 PBSZ=0\r\n")?,
+        Command::Prot(level) => match level.to_uppercase().as_str() {
+            "P" => {
+                data.tls.clone_from(tls_config);
+                client.write_all(b"200 Protection level set to Private\r\n")?;
+            }
+            "C" if server.settings().get_bool("require_tls") => {
+                client.write_all(b"534 Data connections must be protected\r\n")?;
+            }
+            "C" => {
+                data.tls = None;
+                client.write_all(b"200 Protection level set to Clear\r\n")?;
+            }
+            _ => client.write_all(b"504 Unsupported protection level\r\n")?,
+        },
+        _ => (),
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_lines)]
+fn control_loop(
+    server: &frontend::FrontendTcpServer,
+    channel: &channel::Channel,
+    client: &mut tls::Control,
+    commands: &Commands,
+    resume: &crossbeam_channel::Sender<()>,
 ) -> Result<(), api::Error> {
-    let data_frontend = net::TcpListener::bind((server.ip, 0))?;
-    let data_frontend_bind_port = data_frontend.local_addr().unwrap().port();
+    // no clear text commands other than FEAT and QUIT, nor data
+    let require_tls = server.settings().get_bool("require_tls");
 
-    let client_read = client.try_clone()?;
-    let mut client_read = io::BufReader::new(client_read);
+    let mut data = Data {
+        listener: net::TcpListener::bind((server.ip, 0))?,
+        mode: DataMode::Passive,
+        tls: None,
+    };
+    let data_frontend_bind_port = data.listener.local_addr()?.port();
 
-    let mut backend = channel.connect(&super::SERVICE)?;
-    protocol::BackendMode::Control.send(&mut backend)?;
+    let mut tls_config = None;
+
+    let mut user = None;
+    let mut session: Option<Session<'_>> = None;
 
     client.write_all(b"220 Welcome\r\n")?;
     client.flush()?;
 
     loop {
-        match parse_command(&mut client_read)? {
+        let command = commands
+            .recv()
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))??;
+        match command {
             None => client.write_all(b"502 Command not implemented\r\n")?,
-            Some(command) => {
-                command.send(&mut backend)?;
-                let resp = protocol::ControlResponse::receive(&mut backend)?;
+            Some(Command::Abor) => client.write_all(b"226 No transfer to abort\r\n")?,
+            Some(Command::Stat) => client.write_all(b"211 No transfer in progress\r\n")?,
+            Some(command @ Command::Auth(_)) => {
+                security_command(server, client, &mut data, &mut tls_config, command)?;
+                // the command reading thread waits to know whether TLS is used
+                let _ = resume.send(());
+            }
+            Some(command @ (Command::Pbsz | Command::Prot(_))) => {
+                security_command(server, client, &mut data, &mut tls_config, command)?;
+            }
+            Some(ref command)
+                if require_tls
+                    && !client.is_secure()
+                    && !matches!(
+                        command,
+                        Command::Control(
+                            protocol::ControlCommand::Feat | protocol::ControlCommand::Quit
+                        )
+                    ) =>
+            {
+                client.write_all(b"530 TLS required\r\n")?;
+            }
+            Some(ref command)
+                if session.is_none()
+                    && !matches!(
+                        command,
+                        Command::User(_)
+                            | Command::Pass(_)
+                            | Command::Control(
+                                protocol::ControlCommand::Feat | protocol::ControlCommand::Quit
+                            )
+                    ) =>
+            {
+                client.write_all(b"530 Please login with USER and PASS\r\n")?;
+            }
+            Some(Command::User(_) | Command::Pass(_)) if session.is_some() => {
+                client.write_all(b"503 Already logged in\r\n")?;
+            }
+            Some(Command::User(name)) => {
+                user = Some(name);
+                client.write_all(b"331 Password required\r\n")?;
+            }
+            Some(Command::Pass(password)) => {
+                let Some(name) = user.take() else {
+                    client.write_all(b"503 Login with USER first\r\n")?;
+                    client.flush()?;
+                    continue;
+                };
+                if let Some(scope) = auth::login(server.settings(), &name, &password) {
+                    crate::info!("{name:?} logged in");
+                    let mut backend = channel.connect(&super::SERVICE)?;
+                    protocol::BackendMode::Control.send(&mut backend)?;
+                    scope.send(&mut backend)?;
+                    session = Some(Session { backend, scope });
+                    client.write_all(b"230 Logged in\r\n")?;
+                } else {
+                    crate::warn!("login of {name:?} failed");
+                    // slows down password guessing
+                    thread::sleep(LOGIN_FAILURE_DELAY);
+                    client.write_all(b"530 Login incorrect\r\n")?;
+                }
+            }
+            Some(Command::Port(None)) => client.write_all(b"501 Invalid address\r\n")?,
+            Some(Command::Port(Some(addr))) => {
+                // prevents using the frontend to connect elsewhere (FTP bounce)
+                if addr.ip().to_canonical() == client.socket().peer_addr()?.ip().to_canonical() {
+                    data.mode = DataMode::Active(addr);
+                    client.write_all(b"200 Active mode enabled\r\n")?;
+                } else {
+                    client.write_all(b"500 Illegal PORT command\r\n")?;
+                }
+            }
+            Some(Command::Control(command)) => {
+                let resp = if let Some(session) = session.as_mut() {
+                    command.send(&mut session.backend)?;
+                    protocol::ControlResponse::receive(&mut session.backend)?
+                } else if matches!(command, protocol::ControlCommand::Quit) {
+                    // FEAT and QUIT are answered before login
+                    protocol::ControlResponse::Quit
+                } else {
+                    protocol::ControlResponse::Feat
+                };
                 crate::trace!("response {resp:?}");
                 match resp {
                     protocol::ControlResponse::Ok(c, msg) => {
@@ -151,40 +586,64 @@ pub fn tcp_handler<'a>(
                     protocol::ControlResponse::Error(c) => {
                         client.write_all(format!("{c} Error\r\n").as_bytes())?;
                     }
+                    protocol::ControlResponse::Data(_) if require_tls && data.tls.is_none() => {
+                        client.write_all(b"521 Data connections must be protected\r\n")?;
+                    }
                     protocol::ControlResponse::Data(cmd) => {
-                        data_command(channel, &mut client, &data_frontend, &cmd)?;
+                        if let Some(session) = &session {
+                            data_command(channel, client, commands, &data, &session.scope, &cmd)?;
+                        }
                     }
                     protocol::ControlResponse::Quit => {
+                        client.write_all(b"221 Goodbye\r\n")?;
+                        client.flush()?;
                         return Ok(());
                     }
                     protocol::ControlResponse::Feat => {
                         client.write_all(b"211-Features:\r\n")?;
+                        client.write_all(b" AUTH TLS\r\n")?;
                         client.write_all(b" EPRT\r\n")?;
                         client.write_all(b" EPSV\r\n")?;
+                        client.write_all(b" MDTM\r\n")?;
+                        client.write_all(b" MFMT\r\n")?;
+                        client.write_all(b" MLST type*;size*;modify*;perm*;unique*;\r\n")?;
                         client.write_all(b" PASV\r\n")?;
+                        client.write_all(b" PBSZ\r\n")?;
+                        client.write_all(b" PROT\r\n")?;
                         client.write_all(b" REST STREAM\r\n")?;
                         client.write_all(b" SIZE\r\n")?;
                         client.write_all(b" TVFS\r\n")?;
                         client.write_all(b" UTF8\r\n")?;
                         client.write_all(b"211 End\r\n")?;
                     }
-                    protocol::ControlResponse::Pasv => match server.ip {
-                        net::IpAddr::V4(ip) => {
-                            let ip = ip.to_bits().to_be_bytes();
-                            let port = data_frontend_bind_port.to_be_bytes();
-                            client.write_all(
-                                format!(
-                                    "227 Entering Passive Mode ({},{},{},{},{},{})\r\n",
-                                    ip[0], ip[1], ip[2], ip[3], port[0], port[1]
-                                )
-                                .as_bytes(),
-                            )?;
-                        }
-                        net::IpAddr::V6(_) => {
-                            client.write_all(b"425 Can't open data connection\r\n")?;
+                    // the address announced is the one the client reached,
+                    // even when the frontend listens on all interfaces
+                    protocol::ControlResponse::Pasv => {
+                        match client.socket().local_addr()?.ip().to_canonical() {
+                            net::IpAddr::V4(ip) => {
+                                data.passive()?;
+                                let ip = ip.to_bits().to_be_bytes();
+                                let port = data_frontend_bind_port.to_be_bytes();
+                                client.write_all(
+                                    format!(
+                                        "227 Entering Passive Mode ({},{},{},{},{},{})\r\n",
+                                        ip[0], ip[1], ip[2], ip[3], port[0], port[1]
+                                    )
+                                    .as_bytes(),
+                                )?;
+                            }
+                            net::IpAddr::V6(_) => {
+                                client.write_all(b"425 Use EPSV with IPv6\r\n")?;
+                            }
                         }
-                    },
+                    }
+                    protocol::ControlResponse::Mlst(entry) => {
+                        client.write_all(b"250-Listing\r\n")?;
+                        client.write_all(format!(" {entry}\r\n").as_bytes())?;
+                        client.write_all(b"250 End\r\n")?;
+                    }
                     protocol::ControlResponse::Epsv => {
+                        data.passive()?;
                         client.write_all(format!("229 Entering Extended Passive Mode (|||{data_frontend_bind_port}|)\r\n").as_bytes())?;
                     }
                 }
@@ -193,3 +652,99 @@ pub fn tcp_handler<'a>(
         client.flush()?;
     }
 }
+
+// Plaintext commands pipelined after AUTH would otherwise be handled as
+// if received over TLS (STARTTLS command injection), the connection is
+// closed instead
+fn read_command<R>(r: &mut io::BufReader<R>) -> Result<Option<Command>, io::Error>
+where
+    R: io::Read,
+{
+    let command = parse_command(r)?;
+    if matches!(command, Some(Command::Auth(_))) && !r.buffer().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "data received after AUTH",
+        ));
+    }
+    Ok(command)
+}
+
+pub fn tcp_handler<'a>(
+    server: &frontend::FrontendTcpServer,
+    scope: &'a thread::Scope<'a, '_>,
+    client: net::TcpStream,
+    channel: &'a channel::Channel,
+) -> Result<(), api::Error> {
+    // ABOR may be sent as urgent data (RFC 959)
+    socket2::SockRef::from(&client).set_out_of_band_inline(true)?;
+
+    let (mut client, client_read) = tls::Control::new(client)?;
+
+    // commands are read by a dedicated thread to be handled during
+    // transfers
+    let mut client_read = io::BufReader::new(client_read);
+    let (commands_send, commands) = crossbeam_channel::unbounded();
+    let (resume_send, resume) = crossbeam_channel::bounded(1);
+
+    let thread = thread::Builder::new();
+    #[cfg(feature = "log")]
+    let thread = thread.name(format!(
+        "{} {} {} control",
+        service::Kind::Frontend,
+        super::SERVICE,
+        client.socket().peer_addr()?
+    ));
+    thread.spawn_scoped(scope, move || {
+        loop {
+            let command = read_command(&mut client_read);
+            let stop = command.is_err();
+            // nothing must be read before the connection is secured
+            let auth = matches!(command, Ok(Some(Command::Auth(_))));
+            if commands_send.send(command).is_err() || stop || (auth && resume.recv().is_err()) {
+                break;
+            }
+        }
+    })?;
+
+    let res = control_loop(server, channel, &mut client, &commands, &resume_send);
+
+    // stops the command reading thread
+    let _ = client.socket().shutdown(net::Shutdown::Both);
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &[u8]) -> Result<Option<Command>, io::Error> {
+        read_command(&mut io::BufReader::new(input))
+    }
+
+    #[test]
+    fn auth() {
+        assert!(matches!(read(b"AUTH TLS\r\n"), Ok(Some(Command::Auth(m))) if m == "TLS"));
+    }
+
+    #[test]
+    fn pipelined_after_auth() {
+        let res = read(b"AUTH TLS\r\nUSER x\r\nPASS y\r\n");
+        assert!(res.is_err_and(|e| e.kind() == io::ErrorKind::InvalidData));
+        assert!(read(b"AUTH TLS\r\nU").is_err());
+    }
+
+    #[test]
+    fn pipelined_before_auth() {
+        let mut input = io::BufReader::new(&b"USER x\r\nAUTH TLS\r\n"[..]);
+        assert!(matches!(
+            read_command(&mut input),
+            Ok(Some(Command::User(_)))
+        ));
+        assert!(matches!(
+            read_command(&mut input),
+            Ok(Some(Command::Auth(_)))
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::util;
+use std::{fs, path, time};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// LIST shows the year instead of the time for older entries
+const RECENT_SECS: i64 = 182 * 24 * 3600;
+
+fn unix_secs(t: time::SystemTime) -> i64 {
+    match t.duration_since(time::UNIX_EPOCH) {
+        Ok(d) => i64::try_from(d.as_secs()).unwrap_or(i64::MAX),
+        Err(e) => -i64::try_from(e.duration().as_secs()).unwrap_or(i64::MAX),
+    }
+}
+
+fn modified(metadata: &fs::Metadata) -> i64 {
+    metadata.modified().map_or(0, unix_secs)
+}
+
+// "YYYYMMDDHHMMSS" in UTC (RFC 3659)
+pub fn format_time_val(metadata: &fs::Metadata) -> String {
+    let [year, month, day, hour, minute, second] = util::civil_from_unix(modified(metadata));
+    format!("{year:04}{month:02}{day:02}{hour:02}{minute:02}{second:02}")
+}
+
+// Fractions of seconds are accepted and ignored
+pub fn parse_time_val(s: &str) -> Option<time::SystemTime> {
+    let s = s.split_once('.').map_or(s, |(s, _)| s);
+    if s.len() != 14 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let field = |range: std::ops::Range<usize>| s[range].parse::<i64>().ok();
+
+    let civil = [
+        field(0..4)?,
+        field(4..6)?,
+        field(6..8)?,
+        field(8..10)?,
+        field(10..12)?,
+        field(12..14)?,
+    ];
+
+    let [_, month, day, hour, minute, second] = civil;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || 23 < hour
+        || 59 < minute
+        || 60 < second
+    {
+        return None;
+    }
+
+    let secs = util::unix_from_civil(civil);
+    let offset = time::Duration::from_secs(secs.unsigned_abs());
+    if secs < 0 {
+        time::UNIX_EPOCH.checked_sub(offset)
+    } else {
+        time::UNIX_EPOCH.checked_add(offset)
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    let mode = if metadata.is_dir() { 0o755 } else { 0o644 };
+    if metadata.permissions().readonly() {
+        mode & !0o222
+    } else {
+        mode
+    }
+}
+
+#[cfg(unix)]
+fn owner(metadata: &fs::Metadata) -> (u64, String, String) {
+    use std::os::unix::fs::MetadataExt;
+    (
+        metadata.nlink(),
+        metadata.uid().to_string(),
+        metadata.gid().to_string(),
+    )
+}
+
+#[cfg(not(unix))]
+fn owner(_metadata: &fs::Metadata) -> (u64, String, String) {
+    (1, "owner".to_string(), "group".to_string())
+}
+
+#[cfg(unix)]
+fn unique(_path: &path::Path, metadata: &fs::Metadata) -> String {
+    use std::os::unix::fs::MetadataExt;
+    format!("{:x}g{:x}", metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn unique(path: &path::Path, _metadata: &fs::Metadata) -> String {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+// One line of a "ls -l" like listing
+pub fn list_line(name: &str, file_type: fs::FileType, metadata: &fs::Metadata) -> String {
+    let kind = if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else {
+        '-'
+    };
+
+    let mode = mode(metadata);
+    let permissions: String = "rwxrwxrwx"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if mode & (0o400 >> i) == 0 { '-' } else { c })
+        .collect();
+
+    let (links, owner, group) = owner(metadata);
+
+    let mtime = modified(metadata);
+    let now = unix_secs(time::SystemTime::now());
+    let [year, month, day, hour, minute, _] = util::civil_from_unix(mtime);
+    let month = usize::try_from(month - 1).map_or("Jan", |month| MONTHS[month]);
+    let date = if (now - mtime).abs() < RECENT_SECS {
+        format!("{month} {day:>2} {hour:02}:{minute:02}")
+    } else {
+        format!("{month} {day:>2}  {year}")
+    };
+
+    format!(
+        "{kind}{permissions} {links:>3} {owner:<8} {group:<8} {:>12} {date} {name}",
+        metadata.len()
+    )
+}
+
+// Facts of a MLSD/MLST entry (RFC 3659), ending with a ';'
+pub fn facts(path: &path::Path, metadata: &fs::Metadata, read_only: bool) -> String {
+    let read_only = read_only || metadata.permissions().readonly();
+
+    let (kind, perm) = if metadata.is_dir() {
+        ("dir", if read_only { "el" } else { "cdeflmp" })
+    } else {
+        ("file", if read_only { "r" } else { "adfrw" })
+    };
+
+    let size = if metadata.is_dir() {
+        String::new()
+    } else {
+        format!("size={};", metadata.len())
+    };
+
+    format!(
+        "type={kind};{size}modify={};perm={perm};unique={};",
+        format_time_val(metadata),
+        unique(path, metadata)
+    )
+}
@@ -2,11 +2,19 @@
 use crate::frontend as sfrontend;
 use crate::service;
 
+#[cfg(feature = "frontend")]
+pub mod auth;
 #[cfg(feature = "backend")]
 mod backend;
 #[cfg(feature = "frontend")]
 mod frontend;
-mod protocol;
+#[cfg(feature = "backend")]
+mod listing;
+pub mod protocol;
+#[cfg(feature = "frontend")]
+mod tls;
+#[cfg(feature = "backend")]
+mod vfs;
 
 pub static SERVICE: service::Service = service::Service {
     internal: false,
@@ -16,7 +24,9 @@ pub static SERVICE: service::Service = service::Service {
         tcp: Some(sfrontend::FrontendTcp {
             default_port: 2021,
             handler: frontend::tcp_handler,
+            background: None,
         }),
+        udp: None,
     }),
     #[cfg(feature = "backend")]
     backend: Some(service::Backend {
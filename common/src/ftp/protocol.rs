@@ -59,6 +59,64 @@ impl BackendMode {
     }
 }
 
+// Restrictions of a session, sent by the frontend after the mode
+pub struct Scope {
+    // directory the session is jailed in, if any
+    pub root: Option<String>,
+    // refuses any command modifying the filesystem
+    pub read_only: bool,
+    // lets STOR replace existing files without REST
+    pub overwrite: bool,
+    // glob patterns of hidden and inaccessible paths
+    pub deny: Vec<String>,
+}
+
+impl Scope {
+    #[cfg(feature = "frontend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), api::Error>
+    where
+        W: io::Write,
+    {
+        util::serialize_string(stream, self.root.as_deref().unwrap_or_default())?;
+        stream.write_all(&[u8::from(self.read_only), u8::from(self.overwrite)])?;
+
+        let count = u16::try_from(self.deny.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        stream.write_all(&count.to_le_bytes())?;
+        for pattern in &self.deny {
+            util::serialize_string(stream, pattern)?;
+        }
+
+        stream.flush()?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "backend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, api::Error>
+    where
+        R: io::Read,
+    {
+        let root = util::deserialize_string(stream)?;
+
+        let mut flags = [0u8; 2];
+        stream.read_exact(&mut flags)?;
+
+        let mut count = [0u8; 2];
+        stream.read_exact(&mut count)?;
+        let deny = (0..u16::from_le_bytes(count))
+            .map(|_| util::deserialize_string(stream))
+            .collect::<Result<Vec<String>, io::Error>>()?;
+
+        Ok(Self {
+            root: (!root.is_empty()).then_some(root),
+            read_only: flags[0] != 0,
+            overwrite: flags[1] != 0,
+            deny,
+        })
+    }
+}
+
 const ID_CTRL_CMD_CDUP: u8 = 0x00;
 const ID_CTRL_CMD_CWD: u8 = 0x01;
 const ID_CTRL_CMD_DELE: u8 = 0x02;
@@ -67,7 +125,6 @@ const ID_CTRL_CMD_FEAT: u8 = 0x04;
 const ID_CTRL_CMD_LIST: u8 = 0x05;
 const ID_CTRL_CMD_NLST: u8 = 0x06;
 const ID_CTRL_CMD_OPTS: u8 = 0x07;
-const ID_CTRL_CMD_PASS: u8 = 0x08;
 const ID_CTRL_CMD_PASV: u8 = 0x09;
 const ID_CTRL_CMD_PWD: u8 = 0x0a;
 const ID_CTRL_CMD_QUIT: u8 = 0x0b;
@@ -75,27 +132,44 @@ const ID_CTRL_CMD_RETR: u8 = 0x0c;
 const ID_CTRL_CMD_STOR: u8 = 0x0d;
 const ID_CTRL_CMD_SIZE: u8 = 0x0e;
 const ID_CTRL_CMD_TYPE: u8 = 0x0f;
-const ID_CTRL_CMD_USER: u8 = 0x10;
+const ID_CTRL_CMD_MKD: u8 = 0x11;
+const ID_CTRL_CMD_RMD: u8 = 0x12;
+const ID_CTRL_CMD_RNFR: u8 = 0x13;
+const ID_CTRL_CMD_RNTO: u8 = 0x14;
+const ID_CTRL_CMD_REST: u8 = 0x15;
+const ID_CTRL_CMD_APPE: u8 = 0x16;
+const ID_CTRL_CMD_MDTM: u8 = 0x17;
+const ID_CTRL_CMD_MFMT: u8 = 0x18;
+const ID_CTRL_CMD_MLSD: u8 = 0x19;
+const ID_CTRL_CMD_MLST: u8 = 0x1a;
 
 #[derive(Debug)]
 pub enum ControlCommand {
+    Appe(String),
     Cdup,
     Cwd(String),
     Dele(String),
     Epsv,
     Feat,
     List,
+    Mdtm(String),
+    Mfmt(String),
+    Mkd(String),
+    Mlsd(String),
+    Mlst(String),
     Nlst,
     Opts,
-    Pass,
     Pasv,
     Pwd,
     Quit,
+    Rest(String),
     Retr(String),
+    Rmd(String),
+    Rnfr(String),
+    Rnto(String),
     Stor(String),
     Size(String),
     Type,
-    User,
 }
 
 impl ControlCommand {
@@ -105,30 +179,52 @@ impl ControlCommand {
         W: io::Write,
     {
         let code = match self {
+            Self::Appe(_) => ID_CTRL_CMD_APPE,
             Self::Cdup => ID_CTRL_CMD_CDUP,
             Self::Cwd(_) => ID_CTRL_CMD_CWD,
             Self::Dele(_) => ID_CTRL_CMD_DELE,
             Self::Epsv => ID_CTRL_CMD_EPSV,
             Self::Feat => ID_CTRL_CMD_FEAT,
             Self::List => ID_CTRL_CMD_LIST,
+            Self::Mdtm(_) => ID_CTRL_CMD_MDTM,
+            Self::Mfmt(_) => ID_CTRL_CMD_MFMT,
+            Self::Mkd(_) => ID_CTRL_CMD_MKD,
+            Self::Mlsd(_) => ID_CTRL_CMD_MLSD,
+            Self::Mlst(_) => ID_CTRL_CMD_MLST,
             Self::Nlst => ID_CTRL_CMD_NLST,
             Self::Opts => ID_CTRL_CMD_OPTS,
-            Self::Pass => ID_CTRL_CMD_PASS,
             Self::Pasv => ID_CTRL_CMD_PASV,
             Self::Pwd => ID_CTRL_CMD_PWD,
             Self::Quit => ID_CTRL_CMD_QUIT,
+            Self::Rest(_) => ID_CTRL_CMD_REST,
             Self::Retr(_) => ID_CTRL_CMD_RETR,
+            Self::Rmd(_) => ID_CTRL_CMD_RMD,
+            Self::Rnfr(_) => ID_CTRL_CMD_RNFR,
+            Self::Rnto(_) => ID_CTRL_CMD_RNTO,
             Self::Stor(_) => ID_CTRL_CMD_STOR,
             Self::Size(_) => ID_CTRL_CMD_SIZE,
             Self::Type => ID_CTRL_CMD_TYPE,
-            Self::User => ID_CTRL_CMD_USER,
         };
 
         let buf = [code; 1];
         stream.write_all(&buf)?;
 
         match self {
-            Self::Dele(s) | Self::Cwd(s) | Self::Retr(s) | Self::Stor(s) | Self::Size(s) => {
+            Self::Appe(s)
+            | Self::Dele(s)
+            | Self::Cwd(s)
+            | Self::Mdtm(s)
+            | Self::Mfmt(s)
+            | Self::Mkd(s)
+            | Self::Mlsd(s)
+            | Self::Mlst(s)
+            | Self::Rest(s)
+            | Self::Retr(s)
+            | Self::Rmd(s)
+            | Self::Rnfr(s)
+            | Self::Rnto(s)
+            | Self::Stor(s)
+            | Self::Size(s) => {
                 util::serialize_string(stream, s)?;
             }
             Self::Cdup
@@ -137,12 +233,10 @@ impl ControlCommand {
             | Self::List
             | Self::Nlst
             | Self::Opts
-            | Self::Pass
             | Self::Pasv
             | Self::Pwd
             | Self::Quit
-            | Self::Type
-            | Self::User => (),
+            | Self::Type => (),
         }
 
         stream.flush()?;
@@ -159,24 +253,37 @@ impl ControlCommand {
         stream.read_exact(&mut buf)?;
 
         let res = match buf[0] {
+            ID_CTRL_CMD_APPE => Self::Appe(util::deserialize_string(stream)?),
             ID_CTRL_CMD_CDUP => Self::Cdup,
             ID_CTRL_CMD_CWD => Self::Cwd(util::deserialize_string(stream)?),
             ID_CTRL_CMD_DELE => Self::Dele(util::deserialize_string(stream)?),
             ID_CTRL_CMD_EPSV => Self::Epsv,
             ID_CTRL_CMD_FEAT => Self::Feat,
             ID_CTRL_CMD_LIST => Self::List,
+            ID_CTRL_CMD_MDTM => Self::Mdtm(util::deserialize_string(stream)?),
+            ID_CTRL_CMD_MFMT => Self::Mfmt(util::deserialize_string(stream)?),
+            ID_CTRL_CMD_MKD => Self::Mkd(util::deserialize_string(stream)?),
+            ID_CTRL_CMD_MLSD => Self::Mlsd(util::deserialize_string(stream)?),
+            ID_CTRL_CMD_MLST => Self::Mlst(util::deserialize_string(stream)?),
             ID_CTRL_CMD_NLST => Self::Nlst,
             ID_CTRL_CMD_OPTS => Self::Opts,
-            ID_CTRL_CMD_PASS => Self::Pass,
             ID_CTRL_CMD_PASV => Self::Pasv,
             ID_CTRL_CMD_PWD => Self::Pwd,
             ID_CTRL_CMD_QUIT => Self::Quit,
+            ID_CTRL_CMD_REST => Self::Rest(util::deserialize_string(stream)?),
             ID_CTRL_CMD_RETR => Self::Retr(util::deserialize_string(stream)?),
+            ID_CTRL_CMD_RMD => Self::Rmd(util::deserialize_string(stream)?),
+            ID_CTRL_CMD_RNFR => Self::Rnfr(util::deserialize_string(stream)?),
+            ID_CTRL_CMD_RNTO => Self::Rnto(util::deserialize_string(stream)?),
             ID_CTRL_CMD_STOR => Self::Stor(util::deserialize_string(stream)?),
             ID_CTRL_CMD_SIZE => Self::Size(util::deserialize_string(stream)?),
             ID_CTRL_CMD_TYPE => Self::Type,
-            ID_CTRL_CMD_USER => Self::User,
-            v => unimplemented!("unsupported ftp data command {v}"),
+            v => {
+                return Err(api::Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported ftp control command {v}"),
+                )));
+            }
         };
 
         Ok(res)
@@ -190,6 +297,7 @@ const ID_CTRL_RESP_QUIT: u8 = 0x03;
 const ID_CTRL_RESP_FEAT: u8 = 0x04;
 const ID_CTRL_RESP_PASV: u8 = 0x05;
 const ID_CTRL_RESP_EPSV: u8 = 0x06;
+const ID_CTRL_RESP_MLST: u8 = 0x07;
 
 #[derive(Debug)]
 pub enum ControlResponse {
@@ -200,6 +308,7 @@ pub enum ControlResponse {
     Feat,
     Pasv,
     Epsv,
+    Mlst(String),
 }
 
 impl ControlResponse {
@@ -216,6 +325,7 @@ impl ControlResponse {
             Self::Feat => ID_CTRL_RESP_FEAT,
             Self::Pasv => ID_CTRL_RESP_PASV,
             Self::Epsv => ID_CTRL_RESP_EPSV,
+            Self::Mlst(_) => ID_CTRL_RESP_MLST,
         };
 
         let buf = [code; 1];
@@ -235,6 +345,9 @@ impl ControlResponse {
             Self::Data(cmd) => {
                 cmd.send(stream)?;
             }
+            Self::Mlst(entry) => {
+                util::serialize_string(stream, entry)?;
+            }
             Self::Quit | Self::Feat | Self::Pasv | Self::Epsv => (),
         }
 
@@ -276,7 +389,13 @@ impl ControlResponse {
             ID_CTRL_RESP_FEAT => Self::Feat,
             ID_CTRL_RESP_PASV => Self::Pasv,
             ID_CTRL_RESP_EPSV => Self::Epsv,
-            v => unimplemented!("unsupported ftp data response {v}"),
+            ID_CTRL_RESP_MLST => Self::Mlst(util::deserialize_string(stream)?),
+            v => {
+                return Err(api::Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported ftp control response {v}"),
+                )));
+            }
         };
 
         Ok(res)
@@ -287,13 +406,18 @@ const ID_DATA_CMD_LIST: u8 = 0x00;
 const ID_DATA_CMD_NLST: u8 = 0x01;
 const ID_DATA_CMD_RETR: u8 = 0x02;
 const ID_DATA_CMD_STOR: u8 = 0x03;
+const ID_DATA_CMD_APPE: u8 = 0x04;
+const ID_DATA_CMD_MLSD: u8 = 0x05;
 
+// Retr and Stor carry the offset set by a previous REST command
 #[derive(Debug)]
 pub enum DataCommand {
     List(String),
     Nlst(String),
-    Retr(String),
-    Stor(String),
+    Retr(String, u64),
+    Stor(String, u64),
+    Appe(String),
+    Mlsd(String),
 }
 
 impl DataCommand {
@@ -304,16 +428,22 @@ impl DataCommand {
         let code = match self {
             Self::List(_) => ID_DATA_CMD_LIST,
             Self::Nlst(_) => ID_DATA_CMD_NLST,
-            Self::Retr(_) => ID_DATA_CMD_RETR,
-            Self::Stor(_) => ID_DATA_CMD_STOR,
+            Self::Retr(_, _) => ID_DATA_CMD_RETR,
+            Self::Stor(_, _) => ID_DATA_CMD_STOR,
+            Self::Appe(_) => ID_DATA_CMD_APPE,
+            Self::Mlsd(_) => ID_DATA_CMD_MLSD,
         };
 
         let buf = [code; 1];
         stream.write_all(&buf)?;
 
         match self {
-            Self::List(p) | Self::Nlst(p) | Self::Retr(p) | Self::Stor(p) => {
+            Self::List(p) | Self::Nlst(p) | Self::Appe(p) | Self::Mlsd(p) => {
+                util::serialize_string(stream, p)?;
+            }
+            Self::Retr(p, offset) | Self::Stor(p, offset) => {
                 util::serialize_string(stream, p)?;
+                stream.write_all(&offset.to_le_bytes())?;
             }
         }
 
@@ -331,12 +461,25 @@ impl DataCommand {
 
         let path = util::deserialize_string(stream)?;
 
+        let mut read_offset = || {
+            let mut offset = [0u8; 8];
+            stream.read_exact(&mut offset)?;
+            Ok::<u64, io::Error>(u64::from_le_bytes(offset))
+        };
+
         let res = match buf[0] {
             ID_DATA_CMD_LIST => Self::List(path),
             ID_DATA_CMD_NLST => Self::Nlst(path),
-            ID_DATA_CMD_RETR => Self::Retr(path),
-            ID_DATA_CMD_STOR => Self::Stor(path),
-            v => unimplemented!("unsupported backend mode {v}"),
+            ID_DATA_CMD_RETR => Self::Retr(path, read_offset()?),
+            ID_DATA_CMD_STOR => Self::Stor(path, read_offset()?),
+            ID_DATA_CMD_APPE => Self::Appe(path),
+            ID_DATA_CMD_MLSD => Self::Mlsd(path),
+            v => {
+                return Err(api::Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported ftp data command {v}"),
+                )));
+            }
         };
 
         Ok(res)
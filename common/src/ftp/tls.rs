@@ -0,0 +1,475 @@
+use crate::{frontend, util};
+use ring::{
+    rand,
+    signature::{self, KeyPair},
+};
+use rustls::pki_types::{self, pem::PemObject};
+use std::{
+    io::{self, Write},
+    net,
+    sync::{self, Arc},
+    time,
+};
+
+// DER certificate and PKCS #8 private key
+type Identity = (Vec<u8>, Vec<u8>);
+
+// Generated once, so that clients asked to trust it are not asked again
+// for each connection
+static SELF_SIGNED: sync::OnceLock<Result<Identity, String>> = sync::OnceLock::new();
+
+const CN: &str = "soxy";
+
+// The certificate is valid from a day before its generation, for
+// clients whose clock is late, to a year after
+const VALIDITY_BEFORE: i64 = 24 * 60 * 60;
+const VALIDITY_AFTER: i64 = 365 * 24 * 60 * 60;
+
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+
+const DER_INTEGER: u8 = 0x02;
+const DER_BIT_STRING: u8 = 0x03;
+const DER_OID: u8 = 0x06;
+const DER_UTF8_STRING: u8 = 0x0c;
+const DER_UTC_TIME: u8 = 0x17;
+const DER_GENERALIZED_TIME: u8 = 0x18;
+const DER_SEQUENCE: u8 = 0x30;
+const DER_SET: u8 = 0x31;
+const DER_VERSION: u8 = 0xa0;
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut res = vec![tag];
+    let len = content.len().to_be_bytes();
+    let len = &len[len.iter().take_while(|b| **b == 0).count()..];
+    match len {
+        [] => res.push(0),
+        [len] if *len < 0x80 => res.push(*len),
+        // long form, with the number of bytes of the length first
+        _ => {
+            res.push(0x80 | u8::try_from(len.len()).unwrap_or_default());
+            res.extend_from_slice(len);
+        }
+    }
+    res.extend_from_slice(content);
+    res
+}
+
+fn bit_string(content: &[u8]) -> Vec<u8> {
+    der(DER_BIT_STRING, &[&[0][..], content].concat())
+}
+
+fn name() -> Vec<u8> {
+    let attribute = [
+        der(DER_OID, OID_COMMON_NAME),
+        der(DER_UTF8_STRING, CN.as_bytes()),
+    ]
+    .concat();
+    der(DER_SEQUENCE, &der(DER_SET, &der(DER_SEQUENCE, &attribute)))
+}
+
+// UTCTime until 2049, GeneralizedTime from 2050 (RFC 5280)
+fn time(unix: i64) -> Vec<u8> {
+    let [year, month, day, hour, minute, second] = util::civil_from_unix(unix);
+    if year < 2050 {
+        let year = year % 100;
+        der(
+            DER_UTC_TIME,
+            format!("{year:02}{month:02}{day:02}{hour:02}{minute:02}{second:02}Z").as_bytes(),
+        )
+    } else {
+        der(
+            DER_GENERALIZED_TIME,
+            format!("{year:04}{month:02}{day:02}{hour:02}{minute:02}{second:02}Z").as_bytes(),
+        )
+    }
+}
+
+fn self_signed() -> Result<Identity, ring::error::Unspecified> {
+    let now = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .ok()
+        .and_then(|now| i64::try_from(now.as_secs()).ok())
+        .unwrap_or_default();
+    self_signed_at(now)
+}
+
+// Minimal X.509 v3 certificate with an ECDSA P-256 key (RFC 5280)
+fn self_signed_at(now: i64) -> Result<Identity, ring::error::Unspecified> {
+    let rng = rand::SystemRandom::new();
+
+    let pkcs8 =
+        signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, &rng)?;
+    let key_pair = signature::EcdsaKeyPair::from_pkcs8(
+        &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+        pkcs8.as_ref(),
+        &rng,
+    )
+    .map_err(|_| ring::error::Unspecified)?;
+
+    let mut serial: [u8; 16] = rand::generate(&rng)?.expose();
+    // positive and without leading zero
+    serial[0] = (serial[0] & 0x7f) | 0x40;
+
+    let algorithm = der(DER_SEQUENCE, &der(DER_OID, OID_ECDSA_WITH_SHA256));
+
+    let validity = [time(now - VALIDITY_BEFORE), time(now + VALIDITY_AFTER)].concat();
+
+    let public_key_info = [
+        der(
+            DER_SEQUENCE,
+            &[
+                der(DER_OID, OID_EC_PUBLIC_KEY),
+                der(DER_OID, OID_PRIME256V1),
+            ]
+            .concat(),
+        ),
+        bit_string(key_pair.public_key().as_ref()),
+    ]
+    .concat();
+
+    let tbs = der(
+        DER_SEQUENCE,
+        &[
+            der(DER_VERSION, &der(DER_INTEGER, &[2])),
+            der(DER_INTEGER, &serial),
+            algorithm.clone(),
+            name(),
+            der(DER_SEQUENCE, &validity),
+            name(),
+            der(DER_SEQUENCE, &public_key_info),
+        ]
+        .concat(),
+    );
+
+    let signature = key_pair.sign(&rng, &tbs)?;
+
+    let certificate = der(
+        DER_SEQUENCE,
+        &[tbs, algorithm, bit_string(signature.as_ref())].concat(),
+    );
+
+    Ok((certificate, pkcs8.as_ref().to_vec()))
+}
+
+fn invalid<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// Uses the PEM files set with the "certificate" and "key" settings,
+// or a self-signed certificate
+pub fn config(
+    settings: &frontend::FrontendSettings,
+) -> Result<Arc<rustls::ServerConfig>, io::Error> {
+    let (certificates, key) = match (settings.get("certificate"), settings.get("key")) {
+        (Some(certificate), Some(key)) => {
+            let certificates = pki_types::CertificateDer::pem_file_iter(certificate)
+                .map_err(invalid)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(invalid)?;
+            let key = pki_types::PrivateKeyDer::from_pem_file(key).map_err(invalid)?;
+            (certificates, key)
+        }
+        (None, None) => {
+            let (certificate, key) = SELF_SIGNED
+                .get_or_init(|| {
+                    crate::info!("generating a self-signed certificate for FTPS");
+                    self_signed().map_err(|e| e.to_string())
+                })
+                .as_ref()
+                .map_err(|e| invalid(e.as_str()))?;
+            (
+                vec![pki_types::CertificateDer::from(certificate.clone())],
+                pki_types::PrivateKeyDer::Pkcs8(pki_types::PrivatePkcs8KeyDer::from(key.clone())),
+            )
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "both certificate and key must be set",
+            ));
+        }
+    };
+
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(invalid)?
+    .with_no_client_auth()
+    .with_single_cert(certificates, key)
+    .map_err(invalid)?;
+
+    Ok(Arc::new(config))
+}
+
+type Tls = Arc<sync::Mutex<Option<rustls::ServerConnection>>>;
+
+// Writing side of the control connection, shared with a ControlReader
+// once secured with AUTH TLS (RFC 4217)
+pub struct Control {
+    socket: net::TcpStream,
+    tls: Tls,
+}
+
+impl Control {
+    pub fn new(socket: net::TcpStream) -> Result<(Self, ControlReader), io::Error> {
+        let tls = Tls::default();
+        let reader = ControlReader {
+            socket: socket.try_clone()?,
+            tls: tls.clone(),
+        };
+        Ok((Self { socket, tls }, reader))
+    }
+
+    pub const fn socket(&self) -> &net::TcpStream {
+        &self.socket
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.tls.lock().unwrap().is_some()
+    }
+
+    // Following reads and writes go through TLS, starting with the
+    // handshake
+    pub fn secure(&self, config: Arc<rustls::ServerConfig>) -> Result<(), io::Error> {
+        let connection = rustls::ServerConnection::new(config).map_err(invalid)?;
+        *self.tls.lock().unwrap() = Some(connection);
+        Ok(())
+    }
+}
+
+impl io::Write for Control {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let mut tls = self.tls.lock().unwrap();
+        match tls.as_mut() {
+            None => self.socket.write(buf),
+            Some(connection) => {
+                let written = connection.writer().write(buf)?;
+                while connection.wants_write() {
+                    connection.write_tls(&mut self.socket)?;
+                }
+                Ok(written)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.socket.flush()
+    }
+}
+
+// Reading side of the control connection, which does not hold the lock
+// while waiting for data so that replies can be written meanwhile
+pub struct ControlReader {
+    socket: net::TcpStream,
+    tls: Tls,
+}
+
+impl io::Read for ControlReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        let mut received = vec![0u8; 16 * 1024];
+
+        loop {
+            {
+                let mut tls = self.tls.lock().unwrap();
+                let Some(connection) = tls.as_mut() else {
+                    drop(tls);
+                    return self.socket.read(buf);
+                };
+                match connection.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    res => return res,
+                }
+            }
+
+            let read = self.socket.read(&mut received)?;
+            if read == 0 {
+                return Ok(0);
+            }
+
+            let mut tls = self.tls.lock().unwrap();
+            let Some(connection) = tls.as_mut() else {
+                continue;
+            };
+            let mut received = &received[..read];
+            while !received.is_empty() {
+                connection.read_tls(&mut received)?;
+                connection.process_new_packets().map_err(invalid)?;
+            }
+            // handshake messages and alerts
+            while connection.wants_write() {
+                connection.write_tls(&mut self.socket)?;
+            }
+        }
+    }
+}
+
+// Data connection, secured when PROT P was sent
+pub enum DataStream {
+    Plain(net::TcpStream),
+    Tls(Box<rustls::StreamOwned<rustls::ServerConnection, net::TcpStream>>),
+}
+
+impl DataStream {
+    pub fn new(
+        socket: net::TcpStream,
+        config: Option<Arc<rustls::ServerConfig>>,
+    ) -> Result<Self, io::Error> {
+        match config {
+            None => Ok(Self::Plain(socket)),
+            Some(config) => {
+                let connection = rustls::ServerConnection::new(config).map_err(invalid)?;
+                Ok(Self::Tls(Box::new(rustls::StreamOwned::new(
+                    connection, socket,
+                ))))
+            }
+        }
+    }
+
+    // Lets the client know that all the data was sent
+    pub fn close(&mut self) -> Result<(), io::Error> {
+        if let Self::Tls(stream) = self {
+            stream.conn.send_close_notify();
+            stream.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl io::Read for DataStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl io::Write for DataStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tag, content and whole encoding of the first DER element, and what
+    // follows it
+    fn element(der: &[u8]) -> (u8, &[u8], &[u8], &[u8]) {
+        let (len, header) = match der[1] {
+            len if len < 0x80 => (usize::from(len), 2),
+            0x81 => (usize::from(der[2]), 3),
+            0x82 => (usize::from(u16::from_be_bytes([der[2], der[3]])), 4),
+            _ => panic!("unexpected length"),
+        };
+        let (whole, rest) = der.split_at(header + len);
+        (der[0], &whole[header..], whole, rest)
+    }
+
+    // Elements of a sequence
+    fn elements(mut der: &[u8]) -> Vec<(u8, &[u8], &[u8])> {
+        let mut elements = vec![];
+        while !der.is_empty() {
+            let (tag, content, whole, rest) = element(der);
+            elements.push((tag, content, whole));
+            der = rest;
+        }
+        elements
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(der(DER_INTEGER, &[]), [DER_INTEGER, 0]);
+        assert_eq!(der(DER_INTEGER, &[0; 0x7f])[..2], [DER_INTEGER, 0x7f]);
+        assert_eq!(der(DER_INTEGER, &[0; 0x80])[..3], [DER_INTEGER, 0x81, 0x80]);
+        assert_eq!(
+            der(DER_INTEGER, &[0; 0x100])[..4],
+            [DER_INTEGER, 0x82, 1, 0]
+        );
+        assert_eq!(der(DER_INTEGER, &[0; 0x100]).len(), 0x104);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(time(0), der(DER_UTC_TIME, b"700101000000Z"));
+        assert_eq!(time(2_524_607_999), der(DER_UTC_TIME, b"491231235959Z"));
+        assert_eq!(
+            time(2_524_608_000),
+            der(DER_GENERALIZED_TIME, b"20500101000000Z")
+        );
+    }
+
+    #[test]
+    fn certificate() {
+        // 2025-01-01T00:00:00Z
+        let now = 1_735_689_600;
+        let (certificate, pkcs8) = self_signed_at(now).unwrap();
+
+        let (tag, content, _, rest) = element(&certificate);
+        assert_eq!(tag, DER_SEQUENCE);
+        assert!(rest.is_empty());
+        let [(_, tbs_content, tbs), (_, algorithm, _), (_, signature, _)] = elements(content)[..]
+        else {
+            panic!("invalid certificate");
+        };
+        assert_eq!(algorithm, der(DER_OID, OID_ECDSA_WITH_SHA256));
+
+        let fields = elements(tbs_content);
+        assert_eq!(fields.len(), 7);
+        // version 3
+        assert_eq!(fields[0].1, der(DER_INTEGER, &[2]));
+        // issuer and subject
+        assert_eq!(fields[3].2, name());
+        assert_eq!(fields[5].2, name());
+        assert_eq!(
+            fields[4].1,
+            [
+                der(DER_UTC_TIME, b"241231000000Z"),
+                der(DER_UTC_TIME, b"260101000000Z")
+            ]
+            .concat()
+        );
+
+        // signed by the key of the certificate
+        let key_pair = signature::EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+            &pkcs8,
+            &rand::SystemRandom::new(),
+        )
+        .unwrap();
+        let public_key = key_pair.public_key().as_ref();
+        let (_, key, _, _) = element(elements(fields[6].1)[1].2);
+        assert_eq!(&key[1..], public_key);
+        signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, public_key)
+            .verify(tbs, &signature[1..])
+            .unwrap();
+    }
+
+    #[test]
+    fn configs() {
+        // rustls checks that the key matches the certificate
+        assert!(config(&frontend::FrontendSettings::default()).is_ok());
+
+        let mut settings = frontend::FrontendSettings::default();
+        settings.insert("certificate".to_string(), "/nonexistent".to_string());
+        assert!(config(&settings).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use super::protocol;
+use crate::util;
+use std::{env, fmt, io, path};
+
+// On Windows, drives are exposed as top-level directories ("/C/Users")
+// and UNC shares below "/UNC" ("/UNC/server/share"); elsewhere virtual
+// paths are the real ones
+const DRIVES: bool = cfg!(windows);
+
+const UNC_ROOT: &str = "UNC";
+
+fn is_drive(name: &str) -> bool {
+    name.len() == 1 && name.as_bytes()[0].is_ascii_alphabetic()
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such directory")
+}
+
+fn read_real_dir(path: &path::Path) -> Result<Vec<(String, path::PathBuf)>, io::Error> {
+    Ok(path
+        .read_dir()?
+        .filter_map(Result::ok)
+        .map(|entry| {
+            (
+                entry.file_name().to_string_lossy().to_string(),
+                entry.path(),
+            )
+        })
+        .collect())
+}
+
+// Path seen by FTP clients, as a list of normalized components
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VirtualPath(Vec<String>);
+
+impl VirtualPath {
+    pub fn from_real(path: &path::Path) -> Self {
+        let mut res = vec![];
+
+        for component in path.components() {
+            match component {
+                path::Component::Prefix(prefix) => match prefix.kind() {
+                    path::Prefix::Disk(drive) | path::Prefix::VerbatimDisk(drive) => {
+                        res.push(char::from(drive).to_ascii_uppercase().to_string());
+                    }
+                    path::Prefix::UNC(server, share) | path::Prefix::VerbatimUNC(server, share) => {
+                        res.push(UNC_ROOT.to_string());
+                        res.push(server.to_string_lossy().to_string());
+                        res.push(share.to_string_lossy().to_string());
+                    }
+                    path::Prefix::Verbatim(_) | path::Prefix::DeviceNS(_) => (),
+                },
+                path::Component::RootDir | path::Component::CurDir => (),
+                path::Component::ParentDir => {
+                    res.pop();
+                }
+                path::Component::Normal(name) => res.push(name.to_string_lossy().to_string()),
+            }
+        }
+
+        Self(res)
+    }
+
+    // Resolves a path sent by a client, absolute or relative to self;
+    // ".." never goes above the root
+    pub fn join(&self, path: &str) -> Self {
+        let separators: &[char] = if DRIVES { &['/', '\\'] } else { &['/'] };
+
+        let bytes = path.as_bytes();
+        let (mut res, path) =
+            if DRIVES && 2 <= bytes.len() && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+                // "C:\dir" or "C:/dir" sent by Windows aware clients
+                (vec![path[..1].to_ascii_uppercase()], &path[2..])
+            } else if path.starts_with(separators) {
+                (vec![], path)
+            } else {
+                (self.0.clone(), path)
+            };
+
+        for component in path.split(separators) {
+            match component {
+                "" | "." => (),
+                ".." => {
+                    res.pop();
+                }
+                name => res.push(name.to_string()),
+            }
+        }
+
+        Self(res)
+    }
+
+    // Appends a name read from the filesystem, as is
+    pub fn child(&self, name: &str) -> Self {
+        let mut res = self.0.clone();
+        res.push(name.to_string());
+        Self(res)
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        self.0.split_last().map(|(_, parent)| Self(parent.to_vec()))
+    }
+
+    // None for directories which only exist in the virtual tree (the
+    // root, "/UNC" and "/UNC/server" on Windows) or which do not
+    // match any real path
+    pub fn to_real(&self) -> Option<path::PathBuf> {
+        if !DRIVES {
+            let mut res = path::PathBuf::from("/");
+            res.extend(&self.0);
+            return Some(res);
+        }
+
+        match self.0.as_slice() {
+            [drive, rest @ ..] if is_drive(drive) => {
+                let mut res = path::PathBuf::from(format!("{}:\\", drive.to_ascii_uppercase()));
+                res.extend(rest);
+                Some(res)
+            }
+            [unc, server, share, rest @ ..] if unc.eq_ignore_ascii_case(UNC_ROOT) => {
+                let mut res = path::PathBuf::from(format!("\\\\{server}\\{share}\\"));
+                res.extend(rest);
+                Some(res)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_virtual_dir(&self) -> bool {
+        DRIVES
+            && match self.0.as_slice() {
+                [] => true,
+                [unc] | [unc, _] => unc.eq_ignore_ascii_case(UNC_ROOT),
+                _ => false,
+            }
+    }
+
+    // Entries of the directory as (name, real path). UNC servers and
+    // shares cannot be enumerated, they are reached by their path.
+    pub fn read_dir(&self) -> Result<Vec<(String, path::PathBuf)>, io::Error> {
+        match self.to_real() {
+            Some(path) => read_real_dir(&path),
+            None if self.0.is_empty() && DRIVES => Ok((b'A'..=b'Z')
+                .map(char::from)
+                .map(|drive| {
+                    (
+                        drive.to_string(),
+                        path::PathBuf::from(format!("{drive}:\\")),
+                    )
+                })
+                .filter(|(_, path)| path.is_dir())
+                .collect()),
+            None if self.is_virtual_dir() => Ok(vec![]),
+            None => Err(not_found()),
+        }
+    }
+}
+
+impl fmt::Display for VirtualPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}", self.0.join("/"))
+    }
+}
+
+// Restrictions of a session applied to virtual paths
+pub struct Scope {
+    // canonical path of the jail, which becomes the virtual root
+    root: Option<path::PathBuf>,
+    read_only: bool,
+    overwrite: bool,
+    deny: Vec<String>,
+}
+
+impl Scope {
+    pub fn new(scope: protocol::Scope) -> Result<Self, io::Error> {
+        let root = scope
+            .root
+            .map(|root| path::Path::new(&root).canonicalize())
+            .transpose()?;
+
+        Ok(Self {
+            root,
+            read_only: scope.read_only,
+            overwrite: scope.overwrite,
+            deny: scope.deny,
+        })
+    }
+
+    pub const fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub const fn can_overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    // Initial working directory of a session
+    pub fn home(&self) -> Result<VirtualPath, io::Error> {
+        if self.root.is_some() {
+            Ok(VirtualPath::default())
+        } else {
+            Ok(VirtualPath::from_real(&env::current_dir()?))
+        }
+    }
+
+    // A pattern containing a '/' is matched against the whole path,
+    // otherwise against each component; anything below a denied path
+    // is denied as well
+    pub fn is_denied(&self, path: &VirtualPath) -> bool {
+        let normalize = |s: &str| {
+            if DRIVES {
+                s.to_lowercase()
+            } else {
+                s.to_string()
+            }
+        };
+
+        (0..path.0.len()).any(|i| {
+            let name = normalize(&path.0[i]);
+            let full = normalize(&format!("/{}", path.0[..=i].join("/")));
+            self.deny.iter().any(|pattern| {
+                let pattern = normalize(pattern);
+                if pattern.contains('/') {
+                    util::glob_match(&pattern, &full)
+                } else {
+                    util::glob_match(&pattern, &name)
+                }
+            })
+        })
+    }
+
+    // None for denied paths and paths leaving the jail, including
+    // through symbolic links
+    pub fn to_real(&self, path: &VirtualPath) -> Option<path::PathBuf> {
+        if self.is_denied(path) {
+            return None;
+        }
+
+        // Windows ignores the trailing dots and spaces of names, and
+        // "name:stream" is an alternate data stream of "name"
+        if DRIVES
+            && path
+                .0
+                .iter()
+                .any(|name| name.contains(':') || name.ends_with(['.', ' ']))
+        {
+            return None;
+        }
+
+        let real = match &self.root {
+            None if self.deny.is_empty() => return path.to_real(),
+            None => path.to_real()?,
+            Some(root) => {
+                let mut res = root.clone();
+                for name in &path.0 {
+                    // rejects components such as "C:" which would replace
+                    // the path when pushed on Windows
+                    let mut components = path::Path::new(name).components();
+                    if !matches!(
+                        (components.next(), components.next()),
+                        (Some(path::Component::Normal(_)), None)
+                    ) {
+                        return None;
+                    }
+                    res.push(name);
+                }
+                res
+            }
+        };
+
+        // the last components may not exist yet (e.g. STOR or MKD);
+        // dangling symbolic links do exist, and are refused as they
+        // cannot be resolved
+        let existing = real
+            .ancestors()
+            .find(|ancestor| ancestor.symlink_metadata().is_ok())?;
+        let mut canonical = existing.canonicalize().ok()?;
+        canonical.push(real.strip_prefix(existing).ok()?);
+
+        // symbolic links and short names (8.3 on Windows) resolved, the
+        // path must still be in the jail and not denied
+        let resolved = match &self.root {
+            None => VirtualPath::from_real(&canonical),
+            Some(root) => VirtualPath::from_real(canonical.strip_prefix(root).ok()?),
+        };
+        (!self.is_denied(&resolved)).then_some(real)
+    }
+
+    pub fn is_virtual_dir(&self, path: &VirtualPath) -> bool {
+        self.root.is_none() && path.is_virtual_dir()
+    }
+
+    pub fn is_dir(&self, path: &VirtualPath) -> bool {
+        self.to_real(path)
+            .map_or_else(|| self.is_virtual_dir(path), |path| path.is_dir())
+    }
+
+    // Entries of the directory, without the denied ones
+    pub fn read_dir(&self, path: &VirtualPath) -> Result<Vec<(String, path::PathBuf)>, io::Error> {
+        if self.is_denied(path) {
+            return Err(not_found());
+        }
+
+        let entries = if self.root.is_some() {
+            read_real_dir(&self.to_real(path).ok_or_else(not_found)?)?
+        } else {
+            path.read_dir()?
+        };
+
+        Ok(entries
+            .into_iter()
+            .filter(|(name, _)| !self.is_denied(&path.child(name)))
+            .collect())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{fs, os::unix};
+
+    fn scope(name: &str) -> (path::PathBuf, Scope) {
+        let dir = env::temp_dir().join(format!("soxy-vfs-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("inside")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        fs::write(root.join("secret.key"), b"").unwrap();
+        unix::fs::symlink(dir.join("outside"), root.join("link")).unwrap();
+        unix::fs::symlink(dir.join("outside").join("new"), root.join("dangling")).unwrap();
+        unix::fs::symlink(root.join("secret.key"), root.join("alias")).unwrap();
+        let scope = Scope::new(protocol::Scope {
+            root: Some(root.to_string_lossy().to_string()),
+            read_only: false,
+            overwrite: false,
+            deny: vec!["*.key".to_string()],
+        })
+        .unwrap();
+        (dir, scope)
+    }
+
+    fn to_real(scope: &Scope, path: &str) -> Option<path::PathBuf> {
+        scope.to_real(&VirtualPath::default().join(path))
+    }
+
+    #[test]
+    fn jail() {
+        let (dir, scope) = scope("jail");
+        assert!(to_real(&scope, "/inside").is_some());
+        assert!(to_real(&scope, "/inside/new/file").is_some());
+        assert!(to_real(&scope, "/new").is_some());
+        assert!(to_real(&scope, "/link").is_none());
+        assert!(to_real(&scope, "/link/new").is_none());
+        assert!(to_real(&scope, "/dangling").is_none());
+        assert!(to_real(&scope, "/dangling/new").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn denied() {
+        let (dir, scope) = scope("denied");
+        assert!(to_real(&scope, "/secret.key").is_none());
+        assert!(to_real(&scope, "/alias").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
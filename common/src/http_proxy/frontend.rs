@@ -0,0 +1,342 @@
+use crate::socks5::{self, protocol};
+use crate::{api, channel, frontend, rdp, service, util};
+use std::{
+    hint,
+    io::{self, BufRead, Read, Write},
+    net, thread,
+};
+
+const SERVICE_KIND: service::Kind = service::Kind::Frontend;
+
+const MAX_HEADERS: usize = 128;
+
+// Longer request or header lines are refused
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+const PAC_PATHS: &[&str] = &["/proxy.pac", "/wpad.dat"];
+
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authorization",
+    "proxy-connection",
+];
+
+struct Request {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+
+fn read_line<R>(reader: &mut R) -> Result<String, io::Error>
+where
+    R: io::BufRead,
+{
+    let mut line = String::new();
+    let read = reader.by_ref().take(MAX_LINE_LENGTH).read_line(&mut line)?;
+    if read == 0 {
+        return Err(io::Error::new(io::ErrorKind::BrokenPipe, "disconnected"));
+    }
+    if !line.ends_with('\n') && u64::try_from(read).is_ok_and(|read| read == MAX_LINE_LENGTH) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    Ok(line.to_string())
+}
+
+impl Request {
+    fn read<R>(reader: &mut R) -> Result<Option<Self>, io::Error>
+    where
+        R: io::BufRead,
+    {
+        let line = read_line(reader)?;
+
+        crate::debug!("{line:?}");
+
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Ok(None);
+        };
+
+        let mut headers = vec![];
+
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Ok(None);
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                return Ok(None);
+            };
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        Ok(Some(Self {
+            method: method.to_uppercase(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers,
+        }))
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // Splits an absolute URI into the destination to connect to
+    // and the origin-form target to send to the server.
+    fn absolute_target(&self) -> Option<(String, String)> {
+        let (scheme, rest) = self.target.split_once("://")?;
+        if !scheme.eq_ignore_ascii_case("http") {
+            return None;
+        }
+
+        let (authority, path) = rest
+            .find('/')
+            .map_or((rest, "/"), |i| (&rest[..i], &rest[i..]));
+        let authority = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+
+        if authority.is_empty() {
+            return None;
+        }
+
+        let has_port = authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+
+        let dest = if has_port {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+
+        Some((dest, path.to_string()))
+    }
+}
+
+// Compares all the bytes whatever the first difference, not to tell how
+// much of the credentials is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let difference = a
+        .iter()
+        .zip(b)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    a.len() == b.len() && hint::black_box(difference) == 0
+}
+
+fn is_authorized(server: &frontend::FrontendTcpServer, request: &Request) -> bool {
+    let settings = server.settings();
+
+    let (Some(username), Some(password)) = (settings.get("username"), settings.get("password"))
+    else {
+        return true;
+    };
+
+    let expected = util::base64_encode(format!("{username}:{password}").as_bytes());
+
+    request
+        .header("Proxy-Authorization")
+        .and_then(|value| value.split_once(' '))
+        .is_some_and(|(scheme, credentials)| {
+            scheme.eq_ignore_ascii_case("basic")
+                && constant_time_eq(credentials.trim().as_bytes(), expected.as_bytes())
+        })
+}
+
+fn reply<W>(
+    stream: &mut W,
+    code: u16,
+    reason: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    write!(stream, "HTTP/1.1 {code} {reason}\r\n")?;
+    for (name, value) in headers {
+        write!(stream, "{name}: {value}\r\n")?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+// Address of the proxy given to the clients: the "pac_address" setting
+// or else the host they reached the proxy with, which can differ from
+// the address the frontend listens on (e.g. behind a port forwarding)
+fn pac_address(server: &frontend::FrontendTcpServer, request: &Request) -> Option<String> {
+    let address = server
+        .settings()
+        .get("pac_address")
+        .or_else(|| request.header("Host"))?;
+
+    // written in a JavaScript string
+    let valid = !address.is_empty()
+        && address
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-.:[]".contains(&b));
+    if !valid {
+        return None;
+    }
+
+    let has_port = address
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+
+    Some(if has_port {
+        address.to_string()
+    } else {
+        format!("{address}:80")
+    })
+}
+
+fn reply_pac(stream: &mut net::TcpStream, proxy: &str) -> Result<(), io::Error> {
+    let body = format!("function FindProxyForURL(url, host) {{\n  return \"PROXY {proxy}\";\n}}\n");
+    reply(
+        stream,
+        200,
+        "OK",
+        &[("Content-Type", "application/x-ns-proxy-autoconfig")],
+        &body,
+    )
+}
+
+fn connect<'a>(
+    channel: &'a channel::Channel,
+    stream: &mut net::TcpStream,
+    dest: &str,
+) -> Result<Option<rdp::RdpStream<'a>>, io::Error> {
+    crate::info!("connecting to {dest:?}");
+
+    let mut client_rdp = channel.connect(&socks5::SERVICE)?;
+
+    protocol::Command::Connect(dest.to_string()).send(&mut client_rdp)?;
+
+    match protocol::Response::receive(&mut client_rdp)? {
+        protocol::Response::Ok(_) => Ok(Some(client_rdp)),
+        protocol::Response::ConnectionNotAllowed => {
+            crate::warn!("connection to {dest:?} not allowed");
+            reply(stream, 403, "Forbidden", &[], "")?;
+            Ok(None)
+        }
+        protocol::Response::HostUnreachable | protocol::Response::TtlExpired => {
+            crate::warn!("failed to connect to {dest:?}: host unreachable");
+            reply(stream, 504, "Gateway Timeout", &[], "")?;
+            Ok(None)
+        }
+        resp => {
+            crate::warn!("failed to connect to {dest:?}: {resp:?}");
+            reply(stream, 502, "Bad Gateway", &[], "")?;
+            Ok(None)
+        }
+    }
+}
+
+pub fn tcp_handler(
+    server: &frontend::FrontendTcpServer,
+    _scope: &thread::Scope,
+    mut stream: net::TcpStream,
+    channel: &channel::Channel,
+) -> Result<(), api::Error> {
+    let mut client_read = io::BufReader::new(stream.try_clone()?);
+
+    // too long lines or invalid UTF-8
+    let request = match Request::read(&mut client_read) {
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => None,
+        request => request?,
+    };
+    let Some(request) = request else {
+        reply(&mut stream, 400, "Bad Request", &[], "")?;
+        return Ok(());
+    };
+
+    if request.target.starts_with('/') {
+        if request.method == "GET" && PAC_PATHS.contains(&request.target.as_str()) {
+            if let Some(proxy) = pac_address(server, &request) {
+                reply_pac(&mut stream, &proxy)?;
+            } else {
+                reply(&mut stream, 400, "Bad Request", &[], "")?;
+            }
+        } else {
+            reply(&mut stream, 404, "Not Found", &[], "")?;
+        }
+        return Ok(());
+    }
+
+    if !is_authorized(server, &request) {
+        reply(
+            &mut stream,
+            407,
+            "Proxy Authentication Required",
+            &[("Proxy-Authenticate", "Basic realm=\"soxy\"")],
+            "",
+        )?;
+        return Ok(());
+    }
+
+    let mut client_rdp = if request.method == "CONNECT" {
+        let Some(client_rdp) = connect(channel, &mut stream, &request.target)? else {
+            return Ok(());
+        };
+
+        stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+        stream.flush()?;
+
+        client_rdp
+    } else {
+        let Some((dest, path)) = request.absolute_target() else {
+            reply(&mut stream, 400, "Bad Request", &[], "")?;
+            return Ok(());
+        };
+
+        let Some(mut client_rdp) = connect(channel, &mut stream, &dest)? else {
+            return Ok(());
+        };
+
+        write!(
+            client_rdp,
+            "{} {path} {}\r\n",
+            request.method, request.version
+        )?;
+        for (name, value) in &request.headers {
+            if !HOP_BY_HOP_HEADERS.contains(&name.to_lowercase().as_str()) {
+                write!(client_rdp, "{name}: {value}\r\n")?;
+            }
+        }
+        client_rdp.write_all(b"Connection: close\r\n\r\n")?;
+
+        client_rdp
+    };
+
+    // bytes the client may already have sent after its request
+    client_rdp.write_all(client_read.buffer())?;
+    client_rdp.flush()?;
+
+    crate::debug!("starting stream copy");
+
+    Ok(service::double_stream_copy(
+        SERVICE_KIND,
+        &super::SERVICE,
+        client_rdp,
+        client_read.into_inner(),
+        true,
+    )?)
+}
@@ -0,0 +1,24 @@
+#[cfg(feature = "frontend")]
+use crate::frontend as sfrontend;
+use crate::service;
+
+#[cfg(feature = "frontend")]
+mod frontend;
+
+// The remote connections are opened by the backend of the socks5
+// service, so this service has no backend on its own.
+pub static SERVICE: service::Service = service::Service {
+    internal: false,
+    name: "http-proxy",
+    #[cfg(feature = "frontend")]
+    frontend: Some(sfrontend::Frontend {
+        tcp: Some(sfrontend::FrontendTcp {
+            default_port: 3128,
+            handler: frontend::tcp_handler,
+            background: None,
+        }),
+        udp: None,
+    }),
+    #[cfg(feature = "backend")]
+    backend: None,
+};
@@ -14,7 +14,9 @@ pub static SERVICE: service::Service = service::Service {
         tcp: Some(sfrontend::FrontendTcp {
             default_port: 1081,
             handler: frontend::tcp_handler,
+            background: None,
         }),
+        udp: None,
     }),
     #[cfg(feature = "backend")]
     backend: None,
@@ -12,20 +12,39 @@ pub mod input;
 mod rdp;
 pub mod service;
 
+#[cfg(feature = "service-archive")]
+mod archive;
 #[cfg(feature = "service-clipboard")]
 mod clipboard;
 #[cfg(feature = "service-command")]
 mod command;
+#[cfg(feature = "service-dns")]
+mod dns;
 #[cfg(feature = "service-forward")]
 pub mod forward;
 #[cfg(feature = "service-ftp")]
 mod ftp;
+#[cfg(feature = "service-http-proxy")]
+mod http_proxy;
+#[cfg(feature = "service-process")]
+mod process;
 #[cfg(feature = "service-socks5")]
 mod socks5;
+#[cfg(feature = "service-ssh")]
+mod ssh;
 #[cfg(feature = "service-stage0")]
 mod stage0;
 
 mod ilog;
+#[cfg(all(
+    feature = "backend",
+    any(
+        feature = "service-dns",
+        feature = "service-forward",
+        feature = "service-socks5"
+    )
+))]
+mod outbound;
 mod util;
 
 pub const VIRTUAL_CHANNEL_DEFAULT_NAME: &str = "SOXY";
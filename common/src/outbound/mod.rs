@@ -0,0 +1,99 @@
+use std::{
+    env, io,
+    net::{self, ToSocketAddrs},
+};
+
+mod policy;
+mod proxy;
+mod socket;
+// also tested on other targets
+#[cfg(any(target_os = "windows", test))]
+mod system_windows;
+
+// Runtime environment variables take precedence over the values
+// embedded at build time
+fn setting(name: &str, embedded: Option<&str>) -> Option<String> {
+    env::var(name)
+        .ok()
+        .or_else(|| embedded.map(ToString::to_string))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// Splits a "HOST:PORT" or "[IPV6]:PORT" destination
+fn split_destination(dest: &str) -> Result<(&str, u16), io::Error> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid destination");
+
+    let (host, port) = dest.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    let port = port.parse().map_err(|_| invalid())?;
+
+    Ok((host, port))
+}
+
+fn resolve(host: &str, port: u16) -> Result<Vec<net::SocketAddr>, io::Error> {
+    (host, port)
+        .to_socket_addrs()
+        .map(Iterator::collect)
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::HostUnreachable,
+                format!("failed to resolve {host:?}: {e}"),
+            )
+        })
+}
+
+fn not_allowed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "connection not allowed by policy",
+    )
+}
+
+// When going through the upstream proxy, the hostname is checked
+// against the policy and, if it can be resolved by the backend,
+// every resolved address as well.
+fn connect_proxy(
+    proxy: &proxy::Proxy,
+    policy: &policy::Policy,
+    host: &str,
+    port: u16,
+) -> Result<net::TcpStream, io::Error> {
+    if !policy.is_host_allowed(host, port) {
+        return Err(not_allowed());
+    }
+
+    if let Ok(addrs) = (host, port).to_socket_addrs() {
+        for addr in addrs {
+            if !policy.is_allowed(host, &addr) {
+                return Err(not_allowed());
+            }
+        }
+    }
+
+    proxy.connect(host, port)
+}
+
+pub fn connect(dest: &str) -> Result<net::TcpStream, io::Error> {
+    let (host, port) = split_destination(dest)?;
+
+    let policy = policy::get();
+
+    if let Some(proxy) = proxy::get().filter(|proxy| !proxy.is_bypassed(host)) {
+        return connect_proxy(proxy, policy, host, port);
+    }
+
+    let addrs: Vec<net::SocketAddr> = resolve(host, port)?
+        .into_iter()
+        .filter(|addr| policy.is_allowed(host, addr))
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(not_allowed());
+    }
+
+    socket::connect(&addrs)
+}
@@ -0,0 +1,307 @@
+use crate::util;
+use std::{env, fs, net, sync};
+
+// Name of the policy file looked up next to the backend executable
+const POLICY_FILE_NAME: &str = "soxy.policy";
+
+// Policy embedded at build time, used when there is no policy file
+const POLICY_EMBEDDED: Option<&str> = option_env!("SOXY_POLICY");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Allow,
+    Deny,
+}
+
+impl TryFrom<&str> for Action {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            _ => Err(format!("invalid action {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Host {
+    Any,
+    Cidr(net::IpAddr, u8),
+    Glob(String),
+}
+
+impl Host {
+    fn parse(s: &str) -> Result<Self, String> {
+        if s == "*" {
+            return Ok(Self::Any);
+        }
+
+        if let Some((ip, prefix)) = s.split_once('/') {
+            let ip: net::IpAddr = ip
+                .parse()
+                .map_err(|e| format!("invalid address {ip:?}: {e}"))?;
+            let prefix: u8 = prefix
+                .parse()
+                .map_err(|e| format!("invalid prefix {prefix:?}: {e}"))?;
+            let max = if ip.is_ipv4() { 32 } else { 128 };
+            if max < prefix {
+                return Err(format!("invalid prefix {prefix}"));
+            }
+            // IPv4-mapped networks are matched as IPv4 ones
+            return Ok(match ip.to_canonical() {
+                canonical if canonical != ip && 96 <= prefix => Self::Cidr(canonical, prefix - 96),
+                _ => Self::Cidr(ip, prefix),
+            });
+        }
+
+        if let Ok(ip) = s.parse::<net::IpAddr>() {
+            let ip = ip.to_canonical();
+            let prefix = if ip.is_ipv4() { 32 } else { 128 };
+            return Ok(Self::Cidr(ip, prefix));
+        }
+
+        Ok(Self::Glob(s.to_lowercase()))
+    }
+
+    // Without address, CIDR rules only match hosts given as an address.
+    // IPv4-mapped IPv6 addresses, to which the system connects over
+    // IPv4, are matched as IPv4 addresses.
+    fn matches(&self, host: &str, ip: Option<net::IpAddr>) -> bool {
+        let Some(ip) = ip.or_else(|| host.parse().ok()).map(|ip| ip.to_canonical()) else {
+            return match self {
+                Self::Any => true,
+                Self::Cidr(_, _) => false,
+                Self::Glob(glob) => util::glob_match(glob, &host.to_lowercase()),
+            };
+        };
+
+        match self {
+            Self::Any => true,
+            Self::Cidr(net::IpAddr::V4(net), prefix) => match ip {
+                net::IpAddr::V4(ip) => {
+                    let mask = u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0);
+                    ip.to_bits() & mask == net.to_bits() & mask
+                }
+                net::IpAddr::V6(_) => false,
+            },
+            Self::Cidr(net::IpAddr::V6(net), prefix) => match ip {
+                net::IpAddr::V6(ip) => {
+                    let mask = u128::MAX.checked_shl(128 - u32::from(*prefix)).unwrap_or(0);
+                    ip.to_bits() & mask == net.to_bits() & mask
+                }
+                net::IpAddr::V4(_) => false,
+            },
+            Self::Glob(glob) => {
+                util::glob_match(glob, &host.to_lowercase())
+                    || util::glob_match(glob, &ip.to_string())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    action: Action,
+    host: Host,
+    ports: (u16, u16),
+}
+
+impl Rule {
+    // Parses "HOST", "HOST:PORTS" or "[IPV6]:PORTS" where PORTS is
+    // "*", "PORT" or "FIRST-LAST"
+    fn parse(action: Action, target: &str) -> Result<Self, String> {
+        let (host, ports) = if let Some(rest) = target.strip_prefix('[') {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| format!("invalid target {target:?}"))?;
+            match rest {
+                "" => (host, None),
+                rest => (
+                    host,
+                    Some(
+                        rest.strip_prefix(':')
+                            .ok_or_else(|| format!("invalid target {target:?}"))?,
+                    ),
+                ),
+            }
+        } else if 1 < target.matches(':').count() {
+            (target, None)
+        } else {
+            target
+                .split_once(':')
+                .map_or((target, None), |(host, ports)| (host, Some(ports)))
+        };
+
+        let parse_port = |port: &str| {
+            port.parse::<u16>()
+                .map_err(|e| format!("invalid port {port:?}: {e}"))
+        };
+
+        let ports = match ports {
+            None | Some("*") => (u16::MIN, u16::MAX),
+            Some(ports) => match ports.split_once('-') {
+                None => {
+                    let port = parse_port(ports)?;
+                    (port, port)
+                }
+                Some((first, last)) => (parse_port(first)?, parse_port(last)?),
+            },
+        };
+
+        Ok(Self {
+            action,
+            host: Host::parse(host)?,
+            ports,
+        })
+    }
+
+    fn matches(&self, host: &str, ip: Option<net::IpAddr>, port: u16) -> bool {
+        (self.ports.0..=self.ports.1).contains(&port) && self.host.matches(host, ip)
+    }
+}
+
+// Rules are evaluated in order and the first matching rule
+// applies. Without matching rule the default action applies.
+pub struct Policy {
+    rules: Vec<Rule>,
+    default: Action,
+}
+
+impl Policy {
+    const fn allow_all() -> Self {
+        Self {
+            rules: vec![],
+            default: Action::Allow,
+        }
+    }
+
+    const fn deny_all() -> Self {
+        Self {
+            rules: vec![],
+            default: Action::Deny,
+        }
+    }
+
+    fn parse(policy: &str) -> Result<Self, String> {
+        let mut res = Self::allow_all();
+
+        for line in policy.split(['\n', ';']) {
+            let line = line.split_once('#').map_or(line, |(line, _)| line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+
+            match (tokens.next(), tokens.next(), tokens.next()) {
+                (Some(default), Some(action), None) if default.eq_ignore_ascii_case("default") => {
+                    res.default = Action::try_from(action)?;
+                }
+                (Some(action), Some(target), None) => {
+                    let action = Action::try_from(action)?;
+                    res.rules.push(Rule::parse(action, target)?);
+                }
+                _ => return Err(format!("invalid rule {line:?}")),
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn load() -> Self {
+        let file = env::current_exe()
+            .ok()
+            .map(|exe| exe.with_file_name(POLICY_FILE_NAME))
+            .filter(|path| path.is_file());
+
+        let policy = match file {
+            Some(path) => {
+                crate::info!("loading policy from {}", path.display());
+                match fs::read_to_string(&path) {
+                    Err(e) => {
+                        crate::error!("failed to read policy: {e}");
+                        return Self::deny_all();
+                    }
+                    Ok(policy) => policy,
+                }
+            }
+            None => match POLICY_EMBEDDED {
+                None => return Self::allow_all(),
+                Some(policy) => {
+                    crate::info!("loading embedded policy");
+                    policy.to_string()
+                }
+            },
+        };
+
+        Self::parse(&policy).unwrap_or_else(|e| {
+            crate::error!("invalid policy, denying all connections: {e}");
+            Self::deny_all()
+        })
+    }
+
+    fn action(&self, host: &str, ip: Option<net::IpAddr>, port: u16) -> Action {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(host, ip, port))
+            .map_or(self.default, |rule| rule.action)
+    }
+
+    pub fn is_allowed(&self, host: &str, addr: &net::SocketAddr) -> bool {
+        let action = self.action(host, Some(addr.ip()), addr.port());
+
+        if action == Action::Deny {
+            crate::warn!("connection to {host:?} ({addr}) denied by policy");
+        }
+
+        action == Action::Allow
+    }
+
+    // For destinations which are not resolved by the backend
+    // (i.e. connections through an upstream proxy)
+    pub fn is_host_allowed(&self, host: &str, port: u16) -> bool {
+        let action = self.action(host, None, port);
+
+        if action == Action::Deny {
+            crate::warn!("connection to {host:?} port {port} denied by policy");
+        }
+
+        action == Action::Allow
+    }
+}
+
+static POLICY: sync::LazyLock<Policy> = sync::LazyLock::new(Policy::load);
+
+pub fn get() -> &'static Policy {
+    &POLICY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> net::SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_mapped() {
+        let policy = Policy::parse("deny 10.0.0.0/8; deny 192.168.*").unwrap();
+        assert!(!policy.is_allowed("10.0.0.1", &addr("10.0.0.1:80")));
+        assert!(!policy.is_allowed("::ffff:10.0.0.1", &addr("[::ffff:10.0.0.1]:80")));
+        assert!(!policy.is_host_allowed("::ffff:10.0.0.1", 80));
+        assert!(!policy.is_allowed("host", &addr("[::ffff:192.168.0.1]:80")));
+        assert!(policy.is_allowed("::ffff:11.0.0.1", &addr("[::ffff:11.0.0.1]:80")));
+    }
+
+    #[test]
+    fn ipv4_mapped_rule() {
+        let policy = Policy::parse("deny ::ffff:10.0.0.0/104; deny ::ffff:11.0.0.1").unwrap();
+        assert!(!policy.is_allowed("10.0.0.1", &addr("10.0.0.1:80")));
+        assert!(!policy.is_allowed("11.0.0.1", &addr("11.0.0.1:80")));
+        assert!(policy.is_allowed("12.0.0.1", &addr("12.0.0.1:80")));
+    }
+}
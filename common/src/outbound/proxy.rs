@@ -0,0 +1,442 @@
+use super::{setting, socket};
+use crate::util;
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::{self, ToSocketAddrs},
+    sync,
+};
+
+// Upstream proxy embedded at build time, used when SOXY_PROXY is not
+// set in the environment of the backend
+const PROXY_EMBEDDED: Option<&str> = option_env!("SOXY_PROXY");
+const NO_PROXY_EMBEDDED: Option<&str> = option_env!("SOXY_NO_PROXY");
+
+// Variables looked up, in order, when the proxy is set to "system",
+// before the Internet Settings on Windows
+const SYSTEM_PROXY_VARS: &[&str] = &[
+    "https_proxy",
+    "HTTPS_PROXY",
+    "all_proxy",
+    "ALL_PROXY",
+    "http_proxy",
+    "HTTP_PROXY",
+];
+const SYSTEM_NO_PROXY_VARS: &[&str] = &["no_proxy", "NO_PROXY"];
+
+const HTTP_DEFAULT_PORT: u16 = 8080;
+const SOCKS5_DEFAULT_PORT: u16 = 1080;
+
+const HTTP_MAX_RESPONSE_LEN: usize = 8192;
+
+const SOCKS5_VERSION: u8 = 0x05;
+const SOCKS5_AUTH_NONE: u8 = 0x00;
+const SOCKS5_AUTH_USERNAME_PASSWORD: u8 = 0x02;
+const SOCKS5_AUTH_NO_ACCEPTABLE: u8 = 0xFF;
+const SOCKS5_AUTH_VERSION: u8 = 0x01;
+const SOCKS5_CMD_CONNECT: u8 = 0x01;
+const SOCKS5_ATYP_IPV4: u8 = 0x01;
+const SOCKS5_ATYP_DOMAIN: u8 = 0x03;
+const SOCKS5_ATYP_IPV6: u8 = 0x04;
+
+// Proxy URL and no proxy list of the Internet Settings
+#[cfg(target_os = "windows")]
+fn internet_settings() -> Option<(String, Option<String>)> {
+    super::system_windows::internet_settings().map(|(url, no_proxy)| (url, Some(no_proxy)))
+}
+
+#[cfg(not(target_os = "windows"))]
+const fn internet_settings() -> Option<(String, Option<String>)> {
+    None
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Http,
+    Socks5,
+}
+
+pub struct Proxy {
+    kind: Kind,
+    server: String,
+    credentials: Option<(String, String)>,
+    bypass: Vec<String>,
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let decoded = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(b) = decoded {
+            res.push(b);
+            i += 3;
+        } else {
+            res.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&res).to_string()
+}
+
+// Formats a destination as expected in a CONNECT request
+fn authority(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+impl Proxy {
+    // Parses "[SCHEME://][USER:PASSWORD@]HOST[:PORT]" where SCHEME is
+    // "http", "socks5" or "socks5h" ("http" if not set)
+    fn parse(url: &str, no_proxy: &str) -> Result<Self, String> {
+        let (kind, rest) = match url.split_once("://") {
+            None => (Kind::Http, url),
+            Some((scheme, rest)) => match scheme.to_lowercase().as_str() {
+                "http" => (Kind::Http, rest),
+                "socks5" | "socks5h" => (Kind::Socks5, rest),
+                _ => return Err(format!("unsupported proxy scheme {scheme:?}")),
+            },
+        };
+
+        let rest = rest.trim_end_matches('/');
+
+        let (credentials, server) = match rest.rsplit_once('@') {
+            None => (None, rest),
+            Some((userinfo, server)) => {
+                let (username, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (
+                    Some((percent_decode(username), percent_decode(password))),
+                    server,
+                )
+            }
+        };
+
+        if server.is_empty() {
+            return Err(format!("invalid proxy {url:?}"));
+        }
+
+        let has_port = server
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.ends_with(':') && port.parse::<u16>().is_ok());
+
+        let server = if has_port {
+            server.to_string()
+        } else {
+            let port = match kind {
+                Kind::Http => HTTP_DEFAULT_PORT,
+                Kind::Socks5 => SOCKS5_DEFAULT_PORT,
+            };
+            format!("{server}:{port}")
+        };
+
+        let bypass = no_proxy
+            .split(',')
+            .map(|entry| entry.trim().trim_start_matches('.').to_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+
+        Ok(Self {
+            kind,
+            server,
+            credentials,
+            bypass,
+        })
+    }
+
+    fn load() -> Option<Self> {
+        let config = setting("SOXY_PROXY", PROXY_EMBEDDED)?;
+
+        let (url, system_no_proxy) = match config.as_str() {
+            "none" => return None,
+            "system" => SYSTEM_PROXY_VARS
+                .iter()
+                .find_map(|var| env::var(var).ok().filter(|value| !value.trim().is_empty()))
+                .map(|url| (url, None))
+                .or_else(internet_settings)?,
+            url => (url.to_string(), None),
+        };
+
+        let no_proxy = setting("SOXY_NO_PROXY", NO_PROXY_EMBEDDED)
+            .or_else(|| {
+                SYSTEM_NO_PROXY_VARS
+                    .iter()
+                    .find_map(|var| env::var(var).ok())
+            })
+            .or(system_no_proxy)
+            .unwrap_or_default();
+
+        match Self::parse(url.trim(), &no_proxy) {
+            Err(e) => {
+                crate::error!("invalid upstream proxy, connecting directly: {e}");
+                None
+            }
+            Ok(proxy) => {
+                crate::info!("using upstream {:?} proxy {}", proxy.kind, proxy.server);
+                Some(proxy)
+            }
+        }
+    }
+
+    // Entries of the no proxy list are "*", hostnames (matching
+    // also their subdomains) or IP addresses
+    pub fn is_bypassed(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+
+        self.bypass.iter().any(|entry| {
+            entry == "*"
+                || *entry == host
+                || host
+                    .strip_suffix(entry.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+
+    pub fn connect(&self, host: &str, port: u16) -> Result<net::TcpStream, io::Error> {
+        crate::debug!(
+            "connecting to {} through {:?} proxy {}",
+            authority(host, port),
+            self.kind,
+            self.server
+        );
+
+        let addrs: Vec<net::SocketAddr> = self.server.to_socket_addrs()?.collect();
+        let mut stream = socket::connect(&addrs)?;
+
+        // the handshake with the proxy is bounded by the connect timeout
+        let timeout = socket::options().connect_timeout;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        match self.kind {
+            Kind::Http => self.http_connect(&mut stream, host, port)?,
+            Kind::Socks5 => self.socks5_connect(&mut stream, host, port)?,
+        }
+
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+
+        Ok(stream)
+    }
+
+    fn http_connect(
+        &self,
+        stream: &mut net::TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), io::Error> {
+        let target = authority(host, port);
+
+        let authorization = self
+            .credentials
+            .as_ref()
+            .map(|(username, password)| {
+                let credentials = util::base64_encode(format!("{username}:{password}").as_bytes());
+                format!("Proxy-Authorization: Basic {credentials}\r\n")
+            })
+            .unwrap_or_default();
+
+        write!(
+            stream,
+            "CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n{authorization}\r\n"
+        )?;
+        stream.flush()?;
+
+        // read byte by byte to not consume data sent by the
+        // destination right after the response
+        let mut response = Vec::with_capacity(256);
+        let mut b = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() == HTTP_MAX_RESPONSE_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "proxy response too long",
+                ));
+            }
+            stream.read_exact(&mut b)?;
+            response.push(b[0]);
+        }
+
+        let response = String::from_utf8_lossy(&response);
+        let status = response.lines().next().unwrap_or_default();
+
+        let code = status
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid proxy response {status:?}"),
+                )
+            })?;
+
+        let kind = match code {
+            200..=299 => return Ok(()),
+            403 | 407 => io::ErrorKind::PermissionDenied,
+            504 => io::ErrorKind::TimedOut,
+            _ => io::ErrorKind::ConnectionRefused,
+        };
+
+        Err(io::Error::new(kind, format!("proxy answered {status:?}")))
+    }
+
+    fn socks5_connect(
+        &self,
+        stream: &mut net::TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), io::Error> {
+        let method = if self.credentials.is_some() {
+            SOCKS5_AUTH_USERNAME_PASSWORD
+        } else {
+            SOCKS5_AUTH_NONE
+        };
+
+        stream.write_all(&[SOCKS5_VERSION, 1, method])?;
+        stream.flush()?;
+
+        let mut buf = [0u8; 2];
+        stream.read_exact(&mut buf)?;
+
+        if buf[0] != SOCKS5_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid proxy version",
+            ));
+        }
+
+        match buf[1] {
+            SOCKS5_AUTH_NONE => (),
+            SOCKS5_AUTH_USERNAME_PASSWORD if method == SOCKS5_AUTH_USERNAME_PASSWORD => {
+                let (username, password) = self.credentials.as_ref().unwrap_or_else(|| {
+                    unreachable!("username/password method without credentials")
+                });
+                socks5_authenticate(stream, username, password)?;
+            }
+            SOCKS5_AUTH_NO_ACCEPTABLE => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "no acceptable proxy authentication method",
+                ));
+            }
+            method => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected proxy authentication method {method:#x}"),
+                ));
+            }
+        }
+
+        let mut request = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0x00];
+        match host.parse::<net::IpAddr>() {
+            Ok(net::IpAddr::V4(ip)) => {
+                request.push(SOCKS5_ATYP_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(net::IpAddr::V6(ip)) => {
+                request.push(SOCKS5_ATYP_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let len = u8::try_from(host.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "hostname too long")
+                })?;
+                request.push(SOCKS5_ATYP_DOMAIN);
+                request.push(len);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+
+        stream.write_all(&request)?;
+        stream.flush()?;
+
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf)?;
+
+        let kind = match buf[1] {
+            0x00 => None,
+            0x02 => Some(io::ErrorKind::PermissionDenied),
+            0x03 => Some(io::ErrorKind::NetworkUnreachable),
+            0x04 => Some(io::ErrorKind::HostUnreachable),
+            0x05 => Some(io::ErrorKind::ConnectionRefused),
+            0x06 => Some(io::ErrorKind::TimedOut),
+            _ => Some(io::ErrorKind::Other),
+        };
+
+        if let Some(kind) = kind {
+            return Err(io::Error::new(
+                kind,
+                format!("proxy answered {:#x}", buf[1]),
+            ));
+        }
+
+        // skip the bound address
+        let len = match buf[3] {
+            SOCKS5_ATYP_IPV4 => 4,
+            SOCKS5_ATYP_IPV6 => 16,
+            SOCKS5_ATYP_DOMAIN => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len)?;
+                usize::from(len[0])
+            }
+            atyp => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid proxy address type {atyp:#x}"),
+                ));
+            }
+        };
+        let mut bound = vec![0u8; len + 2];
+        stream.read_exact(&mut bound)?;
+
+        Ok(())
+    }
+}
+
+// RFC 1929
+fn socks5_authenticate(
+    stream: &mut net::TcpStream,
+    username: &str,
+    password: &str,
+) -> Result<(), io::Error> {
+    let too_long = || io::Error::new(io::ErrorKind::InvalidInput, "proxy credentials too long");
+
+    let mut request = vec![SOCKS5_AUTH_VERSION];
+    request.push(u8::try_from(username.len()).map_err(|_| too_long())?);
+    request.extend_from_slice(username.as_bytes());
+    request.push(u8::try_from(password.len()).map_err(|_| too_long())?);
+    request.extend_from_slice(password.as_bytes());
+
+    stream.write_all(&request)?;
+    stream.flush()?;
+
+    let mut buf = [0u8; 2];
+    stream.read_exact(&mut buf)?;
+
+    if buf[1] != 0x00 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "proxy authentication failed",
+        ));
+    }
+
+    crate::debug!("authenticated to proxy as {username:?}");
+
+    Ok(())
+}
+
+static PROXY: sync::LazyLock<Option<Proxy>> = sync::LazyLock::new(Proxy::load);
+
+pub fn get() -> Option<&'static Proxy> {
+    PROXY.as_ref()
+}
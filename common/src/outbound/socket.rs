@@ -0,0 +1,245 @@
+use super::setting;
+use std::{
+    io, net,
+    str::FromStr,
+    sync::{self, mpsc},
+    thread, time,
+};
+
+const CONNECT_TIMEOUT_EMBEDDED: Option<&str> = option_env!("SOXY_CONNECT_TIMEOUT");
+const BIND_ADDRESS_EMBEDDED: Option<&str> = option_env!("SOXY_BIND_ADDRESS");
+const TCP_KEEPALIVE_EMBEDDED: Option<&str> = option_env!("SOXY_TCP_KEEPALIVE");
+const TCP_NODELAY_EMBEDDED: Option<&str> = option_env!("SOXY_TCP_NODELAY");
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+// RFC 8305 recommended delay before starting the next attempt
+const CONNECTION_ATTEMPT_DELAY: time::Duration = time::Duration::from_millis(250);
+
+pub struct Options {
+    pub connect_timeout: time::Duration,
+    bind4: Option<net::Ipv4Addr>,
+    bind6: Option<net::Ipv6Addr>,
+    keepalive: Option<time::Duration>,
+    nodelay: bool,
+}
+
+fn parse_setting<T>(name: &str, embedded: Option<&str>, default: T) -> T
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let Some(value) = setting(name, embedded) else {
+        return default;
+    };
+
+    value.parse().unwrap_or_else(|e| {
+        crate::error!("invalid {name} value {value:?}, using default: {e}");
+        default
+    })
+}
+
+impl Options {
+    fn load() -> Self {
+        let connect_timeout = parse_setting(
+            "SOXY_CONNECT_TIMEOUT",
+            CONNECT_TIMEOUT_EMBEDDED,
+            DEFAULT_CONNECT_TIMEOUT_SECS,
+        );
+        let keepalive: u64 = parse_setting("SOXY_TCP_KEEPALIVE", TCP_KEEPALIVE_EMBEDDED, 0);
+        let nodelay = parse_setting("SOXY_TCP_NODELAY", TCP_NODELAY_EMBEDDED, false);
+
+        let mut bind4 = None;
+        let mut bind6 = None;
+
+        // at most one address per family, the first one is kept
+        for address in setting("SOXY_BIND_ADDRESS", BIND_ADDRESS_EMBEDDED)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+        {
+            match address.parse::<net::IpAddr>() {
+                Err(e) => crate::error!("invalid bind address {address:?}: {e}"),
+                Ok(net::IpAddr::V4(ip)) => bind4 = bind4.or(Some(ip)),
+                Ok(net::IpAddr::V6(ip)) => bind6 = bind6.or(Some(ip)),
+            }
+        }
+
+        Self {
+            connect_timeout: time::Duration::from_secs(connect_timeout.max(1)),
+            bind4,
+            bind6,
+            keepalive: (0 < keepalive).then(|| time::Duration::from_secs(keepalive)),
+            nodelay,
+        }
+    }
+
+    // When source addresses are configured, destinations of a family
+    // without source address cannot be reached
+    const fn can_reach(&self, addr: &net::SocketAddr) -> bool {
+        if self.bind4.is_none() && self.bind6.is_none() {
+            return true;
+        }
+        match addr {
+            net::SocketAddr::V4(_) => self.bind4.is_some(),
+            net::SocketAddr::V6(_) => self.bind6.is_some(),
+        }
+    }
+
+    fn attempt(
+        &self,
+        addr: &net::SocketAddr,
+        timeout: time::Duration,
+    ) -> Result<net::TcpStream, io::Error> {
+        let socket = socket2::Socket::new(
+            socket2::Domain::for_address(*addr),
+            socket2::Type::STREAM,
+            Some(socket2::Protocol::TCP),
+        )?;
+
+        let source = match addr {
+            net::SocketAddr::V4(_) => self.bind4.map(net::IpAddr::from),
+            net::SocketAddr::V6(_) => self.bind6.map(net::IpAddr::from),
+        };
+        if let Some(source) = source {
+            socket.bind(&net::SocketAddr::new(source, 0).into())?;
+        }
+
+        socket.connect_timeout(&(*addr).into(), timeout)?;
+
+        if let Some(keepalive) = self.keepalive {
+            socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(keepalive))?;
+        }
+
+        let stream = net::TcpStream::from(socket);
+        stream.set_nodelay(self.nodelay)?;
+
+        Ok(stream)
+    }
+}
+
+static OPTIONS: sync::LazyLock<Options> = sync::LazyLock::new(Options::load);
+
+pub fn options() -> &'static Options {
+    &OPTIONS
+}
+
+// Alternates address families starting with the family of the first
+// address returned by the resolver (RFC 8305 section 4)
+fn interleave(addrs: &[net::SocketAddr]) -> Vec<net::SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return vec![];
+    };
+
+    let (primary, secondary): (Vec<net::SocketAddr>, Vec<net::SocketAddr>) = addrs
+        .iter()
+        .partition(|addr| addr.is_ipv4() == first.is_ipv4());
+
+    let mut primary = primary.into_iter();
+    let mut secondary = secondary.into_iter();
+    let mut res = Vec::with_capacity(addrs.len());
+
+    loop {
+        match (primary.next(), secondary.next()) {
+            (None, None) => return res,
+            (a, b) => res.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+// Keeps the most meaningful error of the failed attempts: a refused
+// connection means that the destination was reached
+fn select_error(current: Option<io::Error>, new: io::Error) -> io::Error {
+    match current {
+        Some(current) if current.kind() == io::ErrorKind::ConnectionRefused => current,
+        Some(current) if new.kind() != io::ErrorKind::ConnectionRefused => current,
+        _ => new,
+    }
+}
+
+// Connection attempts are started one after the other, every
+// CONNECTION_ATTEMPT_DELAY or as soon as the previous attempt failed,
+// and run concurrently; the first established connection is returned.
+pub fn connect(addrs: &[net::SocketAddr]) -> Result<net::TcpStream, io::Error> {
+    let options = options();
+
+    let addrs: Vec<net::SocketAddr> = interleave(addrs)
+        .into_iter()
+        .filter(|addr| options.can_reach(addr))
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no source address for the destination",
+        ));
+    }
+
+    let deadline = time::Instant::now() + options.connect_timeout;
+
+    let (result_send, result_recv) = mpsc::channel();
+    let mut addrs = addrs.into_iter();
+    let mut pending = 0usize;
+    let mut error = None;
+    let mut start_next = true;
+
+    loop {
+        if start_next && let Some(addr) = addrs.next() {
+            let result_send = result_send.clone();
+            let timeout = deadline.saturating_duration_since(time::Instant::now());
+
+            let thread = thread::Builder::new();
+            #[cfg(feature = "log")]
+            let thread = thread.name(format!(
+                "{} {addr}",
+                thread::current().name().unwrap_or("connect")
+            ));
+
+            let spawned = thread.spawn(move || {
+                let _ = result_send.send((addr, options.attempt(&addr, timeout)));
+            });
+
+            if let Err(e) = spawned {
+                error = Some(select_error(error, e));
+            } else {
+                crate::trace!("connecting to {addr}");
+                pending += 1;
+            }
+        }
+
+        if pending == 0 && addrs.len() == 0 {
+            break;
+        }
+
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection timed out",
+            ));
+        }
+
+        let wait = if addrs.len() == 0 {
+            remaining
+        } else {
+            remaining.min(CONNECTION_ATTEMPT_DELAY)
+        };
+
+        start_next = match result_recv.recv_timeout(wait) {
+            Ok((addr, Ok(stream))) => {
+                crate::debug!("connected to {addr}");
+                return Ok(stream);
+            }
+            Ok((addr, Err(e))) => {
+                crate::debug!("failed to connect to {addr}: {e}");
+                pending -= 1;
+                error = Some(select_error(error, e));
+                true
+            }
+            Err(_) => true,
+        };
+    }
+
+    Err(error.unwrap_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "connection timed out")))
+}
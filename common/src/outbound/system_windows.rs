@@ -0,0 +1,164 @@
+#[cfg(target_os = "windows")]
+use std::{mem, ptr};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::{Foundation, System::Registry};
+
+// Internet Settings (WinINet) of the user running the backend, as set
+// in the Windows proxy settings or by group policies
+#[cfg(target_os = "windows")]
+const INTERNET_SETTINGS: &str = r"Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+
+#[cfg(target_os = "windows")]
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+#[cfg(target_os = "windows")]
+fn dword(name: &str) -> Option<u32> {
+    let (key, name) = (wide(INTERNET_SETTINGS), wide(name));
+    let mut value = 0u32;
+    let mut len = u32::try_from(mem::size_of::<u32>()).ok()?;
+    // SAFETY: the strings are NUL-terminated and the pointers are valid
+    // for the duration of the call
+    let res = unsafe {
+        Registry::RegGetValueW(
+            Registry::HKEY_CURRENT_USER,
+            key.as_ptr(),
+            name.as_ptr(),
+            Registry::RRF_RT_REG_DWORD,
+            ptr::null_mut(),
+            (&raw mut value).cast(),
+            &raw mut len,
+        )
+    };
+    (res == Foundation::ERROR_SUCCESS).then_some(value)
+}
+
+#[cfg(target_os = "windows")]
+fn string(name: &str) -> Option<String> {
+    let (key, name) = (wide(INTERNET_SETTINGS), wide(name));
+    let get = |buf: *mut u16, len: &mut u32| {
+        // SAFETY: the strings are NUL-terminated, buf is either null or
+        // valid for len bytes
+        unsafe {
+            Registry::RegGetValueW(
+                Registry::HKEY_CURRENT_USER,
+                key.as_ptr(),
+                name.as_ptr(),
+                Registry::RRF_RT_REG_SZ,
+                ptr::null_mut(),
+                buf.cast(),
+                len,
+            )
+        }
+    };
+
+    // the first call gives the size in bytes
+    let mut len = 0u32;
+    if get(ptr::null_mut(), &mut len) != Foundation::ERROR_SUCCESS {
+        return None;
+    }
+    let mut buf = vec![0u16; usize::try_from(len).ok()?.div_ceil(2)];
+    if get(buf.as_mut_ptr(), &mut len) != Foundation::ERROR_SUCCESS {
+        return None;
+    }
+    let end = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+    Some(String::from_utf16_lossy(&buf[..end]))
+}
+
+// Proxy URL from the "ProxyServer" value, either "host:port" for all
+// protocols or "scheme=host:port" entries separated by ';', among
+// which the HTTPS, HTTP then SOCKS proxies are used
+pub fn proxy_url(server: &str) -> Option<String> {
+    let server = server.trim();
+    if !server.contains('=') {
+        return (!server.is_empty()).then(|| {
+            if server.contains("://") {
+                server.to_string()
+            } else {
+                format!("http://{server}")
+            }
+        });
+    }
+
+    let entries: Vec<(String, &str)> = server
+        .split(';')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(scheme, server)| (scheme.trim().to_lowercase(), server.trim()))
+        .filter(|(_, server)| !server.is_empty())
+        .collect();
+    let find = |scheme: &str| {
+        entries
+            .iter()
+            .find(|(entry, _)| entry == scheme)
+            .map(|(_, server)| *server)
+    };
+
+    find("https")
+        .or_else(|| find("http"))
+        .map(|server| format!("http://{server}"))
+        .or_else(|| find("socks").map(|server| format!("socks5://{server}")))
+}
+
+// No proxy list from the "ProxyOverride" value, entries separated by
+// ';'; only "*", hostnames, "*.domain" and addresses can be expressed
+// as no proxy entries, others (e.g. "<local>" or "10.*") are ignored
+pub fn no_proxy(bypass: &str) -> String {
+    bypass
+        .split(';')
+        .map(str::trim)
+        .filter_map(|entry| {
+            let entry = entry.strip_prefix("*.").unwrap_or(entry);
+            if entry == "*" || !entry.contains(['*', '?', '<']) {
+                Some(entry)
+            } else {
+                crate::debug!("ignoring proxy bypass entry {entry:?}");
+                None
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Proxy URL and no proxy list set in the Internet Settings, if the
+// proxy is enabled
+#[cfg(target_os = "windows")]
+pub fn internet_settings() -> Option<(String, String)> {
+    if dword("ProxyEnable")? == 0 {
+        return None;
+    }
+    let url = proxy_url(&string("ProxyServer")?)?;
+    let no_proxy = string("ProxyOverride")
+        .map(|bypass| no_proxy(&bypass))
+        .unwrap_or_default();
+    Some((url, no_proxy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        assert_eq!(proxy_url("proxy:3128").unwrap(), "http://proxy:3128");
+        assert_eq!(proxy_url("http://proxy:3128").unwrap(), "http://proxy:3128");
+        assert_eq!(
+            proxy_url("ftp=f:21;http=h:80;https=s:443").unwrap(),
+            "http://s:443"
+        );
+        assert_eq!(proxy_url("http=h:80").unwrap(), "http://h:80");
+        assert_eq!(proxy_url("socks=s:1080").unwrap(), "socks5://s:1080");
+        assert!(proxy_url("ftp=f:21").is_none());
+        assert!(proxy_url(" ").is_none());
+    }
+
+    #[test]
+    fn bypass() {
+        assert_eq!(
+            no_proxy("*.corp.example;<local>;10.*;intranet; 192.168.0.1"),
+            "corp.example,intranet,192.168.0.1"
+        );
+        assert_eq!(no_proxy("*"), "*");
+    }
+}
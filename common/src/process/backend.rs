@@ -0,0 +1,175 @@
+use super::{protocol, system};
+#[cfg(feature = "log")]
+use crate::service;
+use crate::{rdp, util};
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::process::CommandExt;
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+use std::{env, fs, io, path, process, thread};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Threading;
+
+// Directory of the output files embedded at build time, used when
+// SOXY_PROCESS_OUTPUT_DIR is not set
+const OUTPUT_DIR_EMBEDDED: Option<&str> = option_env!("SOXY_PROCESS_OUTPUT_DIR");
+
+// Output files must be in the SOXY_PROCESS_OUTPUT_DIR directory if one
+// is set, without being symbolic links
+fn check_output(output: &str) -> Result<(), io::Error> {
+    let Some(dir) = env::var("SOXY_PROCESS_OUTPUT_DIR")
+        .ok()
+        .or_else(|| OUTPUT_DIR_EMBEDDED.map(ToString::to_string))
+        .filter(|dir| !dir.trim().is_empty())
+    else {
+        return Ok(());
+    };
+
+    let denied = || {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{output:?} is not allowed"),
+        )
+    };
+
+    let dir = path::Path::new(dir.trim())
+        .canonicalize()
+        .map_err(|e| io::Error::new(e.kind(), format!("output directory {dir:?}: {e}")))?;
+
+    let output = path::Path::new(output);
+    if !output.is_absolute()
+        || output.file_name().is_none()
+        || output
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.file_type().is_symlink())
+    {
+        return Err(denied());
+    }
+    let parent = output
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .ok_or_else(denied)?;
+    if parent.starts_with(&dir) {
+        Ok(())
+    } else {
+        Err(denied())
+    }
+}
+
+// Starts a program which outlives the stream, its standard output and
+// error appended to a file; as for the command service, the program
+// must be in the SOXY_COMMAND_ALLOW allowlist if one is set
+fn start(program: &str, args: &[String], output: &str) -> Result<u32, io::Error> {
+    if !util::is_command_allowed(program) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{program:?} is not allowed"),
+        ));
+    }
+    check_output(output)?;
+
+    let output = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output)
+        .map_err(|e| io::Error::new(e.kind(), format!("{output:?}: {e}")))?;
+
+    let mut command = process::Command::new(program);
+    command
+        .args(args)
+        .stdin(process::Stdio::null())
+        .stdout(output.try_clone()?)
+        .stderr(output);
+    // not killed along with the backend by terminal signals
+    #[cfg(not(target_os = "windows"))]
+    command.process_group(0);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(Threading::DETACHED_PROCESS | Threading::CREATE_NEW_PROCESS_GROUP);
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("{program:?}: {e}")))?;
+    let pid = child.id();
+
+    // reaps the process once it exits
+    let thread = thread::Builder::new();
+    #[cfg(feature = "log")]
+    let thread = thread.name(format!(
+        "{} {} {pid} wait",
+        service::Kind::Backend,
+        super::SERVICE
+    ));
+    thread.spawn(move || {
+        let status = child.wait();
+        crate::debug!("process {pid} exited: {status:?}");
+    })?;
+
+    Ok(pid)
+}
+
+// The backend must not be killed or suspended through itself
+fn not_self(pid: u32) -> Result<u32, io::Error> {
+    if pid == process::id() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the backend process cannot be targeted",
+        ));
+    }
+    Ok(pid)
+}
+
+fn done(res: Result<(), io::Error>) -> protocol::Response {
+    match res {
+        Ok(()) => protocol::Response::Done,
+        Err(e) => protocol::Response::Error(e.to_string()),
+    }
+}
+
+pub fn handler(mut stream: rdp::RdpStream<'_>) -> Result<(), io::Error> {
+    crate::debug!("starting");
+
+    loop {
+        let command = protocol::Command::receive(&mut stream)?;
+
+        crate::debug!("{command:?}");
+
+        let response = match command {
+            protocol::Command::List => match system::list() {
+                Ok(processes) => protocol::Response::Processes(processes),
+                Err(e) => protocol::Response::Error(e.to_string()),
+            },
+            protocol::Command::Kill(pid) => {
+                crate::info!("killing process {pid}");
+                done(not_self(pid).and_then(system::kill))
+            }
+            protocol::Command::Suspend(pid) => {
+                crate::info!("suspending process {pid}");
+                done(not_self(pid).and_then(system::suspend))
+            }
+            protocol::Command::Resume(pid) => {
+                crate::info!("resuming process {pid}");
+                done(system::resume(pid))
+            }
+            protocol::Command::Start {
+                program,
+                args,
+                output,
+            } => match start(&program, &args, &output) {
+                Ok(pid) => {
+                    crate::info!("started {program:?} {args:?} as process {pid}");
+                    protocol::Response::Started(pid)
+                }
+                Err(e) => {
+                    crate::warn!("failed to start: {e}");
+                    protocol::Response::Error(e.to_string())
+                }
+            },
+        };
+
+        if let protocol::Response::Error(msg) = &response {
+            crate::debug!("error: {msg}");
+        }
+
+        response.send(&mut stream)?;
+    }
+}
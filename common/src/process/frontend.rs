@@ -0,0 +1,241 @@
+use super::protocol;
+use crate::{api, channel, frontend, rdp, service};
+use std::{
+    io::{self, BufRead, Write},
+    net, thread,
+};
+
+// https://patorjk.com/software/taag/#p=display&h=0&v=0&f=Ogre&t=process%0A
+const LOGO: &str = r"
+ _ __   _ __   ___    ___   ___  ___  ___
+| '_ \ | '__| / _ \  / __| / _ \/ __|/ __|
+| |_) || |   | (_) || (__ |  __/\__ \\__ \
+| .__/ |_|    \___/  \___| \___||___/|___/
+|_|";
+
+const HELP: &str = r#"
+Available commands:
+- "list [PATTERN]" or "ps [PATTERN]" to list the processes, those with
+  PATTERN in their name, user or command line if given;
+- "kill PID" to kill a process;
+- "suspend PID" or "stop PID" to suspend a process;
+- "resume PID" or "cont PID" to resume a suspended process;
+- "start FILE PROGRAM [ARG]..." to start a detached program, its output
+  appended to FILE (double quotes group words);
+- "exit" or "quit" to exit this interface.
+"#;
+
+const PROMPT: &str = "process> ";
+
+// Splits on spaces, except between double quotes
+fn split_args(s: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut arg: Option<String> = None;
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    args.extend(arg);
+    Some(args)
+}
+
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    let mut unit = "B";
+    let mut value = size;
+    let mut tenths = 0;
+    for next in UNITS {
+        if value < 1024 {
+            break;
+        }
+        tenths = (value % 1024) * 10 / 1024;
+        value /= 1024;
+        unit = next;
+    }
+    if value < 10 && unit != "B" {
+        format!("{value}.{tenths}{unit}")
+    } else {
+        format!("{value}{unit}")
+    }
+}
+
+// Tabs and line breaks would break the columns
+fn field(s: &str) -> String {
+    s.replace(['\t', '\r', '\n'], " ")
+}
+
+fn write_processes<W>(
+    client: &mut W,
+    processes: &[protocol::Process],
+    machine_readable: bool,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    if machine_readable {
+        for process in processes {
+            writeln!(
+                client,
+                "{}\t{}\t{}\t{}\t{}",
+                process.pid,
+                field(&process.user),
+                process.memory,
+                field(&process.name),
+                field(&process.command_line)
+            )?;
+        }
+        return Ok(());
+    }
+
+    let user_width = processes
+        .iter()
+        .map(|process| process.user.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let name_width = processes
+        .iter()
+        .map(|process| process.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .clamp(4, 24);
+
+    writeln!(
+        client,
+        "{:>7} {:user_width$} {:>6} {:name_width$} COMMAND",
+        "PID", "USER", "MEMORY", "NAME"
+    )?;
+    for process in processes {
+        writeln!(
+            client,
+            "{:>7} {:user_width$} {:>6} {:name_width$} {}",
+            process.pid,
+            field(&process.user),
+            human_size(process.memory),
+            field(&process.name),
+            field(&process.command_line)
+        )?;
+    }
+    Ok(())
+}
+
+fn parse_pid(args: &str) -> Option<u32> {
+    args.trim().parse().ok()
+}
+
+fn request(
+    rdp: &mut rdp::RdpStream<'_>,
+    command: &protocol::Command,
+) -> Result<protocol::Response, io::Error> {
+    command.send(rdp)?;
+    protocol::Response::receive(rdp)
+}
+
+// Unless the "machine_readable" setting is true, the client gets a
+// banner, a prompt and a table of processes; otherwise each answer
+// ends with an "ok" or "error <message>" line, the processes being
+// listed before as tab separated lines of pid, user, memory in bytes,
+// name and command line
+pub fn tcp_handler<'a>(
+    server: &frontend::FrontendTcpServer,
+    _scope: &'a thread::Scope<'a, '_>,
+    stream: net::TcpStream,
+    channel: &'a channel::Channel,
+) -> Result<(), api::Error> {
+    let machine_readable = server.settings().get_bool("machine_readable");
+
+    let lstream = stream.try_clone()?;
+    let mut client_read = io::BufReader::new(lstream);
+
+    let mut client_write = io::BufWriter::new(stream);
+
+    if !machine_readable {
+        client_write.write_fmt(format_args!("{}\n{}\n{}\n", service::LOGO, LOGO, HELP))?;
+        client_write.flush()?;
+    }
+
+    let mut rdp = channel.connect(&super::SERVICE)?;
+
+    let mut line = String::new();
+
+    loop {
+        if !machine_readable {
+            client_write.write_all(PROMPT.as_bytes())?;
+            client_write.flush()?;
+        }
+
+        line.clear();
+        if client_read.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let cline = line.trim();
+        let (command, args) = cline.split_once(' ').unwrap_or((cline, ""));
+        let command = command.to_uppercase();
+
+        crate::debug!("{cline:?}");
+
+        let command = match command.as_str() {
+            "" => continue,
+            "LIST" | "PS" => Ok(protocol::Command::List),
+            "KILL" => parse_pid(args)
+                .map(protocol::Command::Kill)
+                .ok_or("invalid pid"),
+            "SUSPEND" | "STOP" => parse_pid(args)
+                .map(protocol::Command::Suspend)
+                .ok_or("invalid pid"),
+            "RESUME" | "CONT" => parse_pid(args)
+                .map(protocol::Command::Resume)
+                .ok_or("invalid pid"),
+            "START" => match split_args(args).as_deref() {
+                Some([output, program, args @ ..]) => Ok(protocol::Command::Start {
+                    program: program.clone(),
+                    args: args.to_vec(),
+                    output: output.clone(),
+                }),
+                _ => Err("usage: start FILE PROGRAM [ARG]..."),
+            },
+            "EXIT" | "QUIT" => {
+                let lstream = client_read.into_inner();
+                let _ = lstream.shutdown(net::Shutdown::Both);
+                return Ok(());
+            }
+            _ => Err("invalid command"),
+        };
+
+        match command {
+            Err(msg) => writeln!(client_write, "error {msg}")?,
+            Ok(command) => match request(&mut rdp, &command)? {
+                protocol::Response::Processes(processes) => {
+                    let pattern = args.trim().to_lowercase();
+                    let processes = processes
+                        .into_iter()
+                        .filter(|process| {
+                            [&process.name, &process.user, &process.command_line]
+                                .iter()
+                                .any(|value| value.to_lowercase().contains(&pattern))
+                        })
+                        .collect::<Vec<_>>();
+                    write_processes(&mut client_write, &processes, machine_readable)?;
+                    if machine_readable {
+                        writeln!(client_write, "ok")?;
+                    }
+                }
+                protocol::Response::Started(pid) => writeln!(client_write, "ok {pid}")?,
+                protocol::Response::Done => writeln!(client_write, "ok")?,
+                protocol::Response::Error(msg) => writeln!(client_write, "error {msg}")?,
+            },
+        }
+        client_write.flush()?;
+    }
+}
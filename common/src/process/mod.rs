@@ -0,0 +1,36 @@
+#[cfg(feature = "frontend")]
+use crate::frontend as sfrontend;
+use crate::service;
+
+#[cfg(feature = "backend")]
+mod backend;
+#[cfg(feature = "frontend")]
+mod frontend;
+mod protocol;
+#[cfg(all(feature = "backend", not(target_os = "windows")))]
+mod system_unix;
+#[cfg(all(feature = "backend", target_os = "windows"))]
+mod system_windows;
+
+#[cfg(all(feature = "backend", not(target_os = "windows")))]
+use system_unix as system;
+#[cfg(all(feature = "backend", target_os = "windows"))]
+use system_windows as system;
+
+pub static SERVICE: service::Service = service::Service {
+    internal: false,
+    name: "process",
+    #[cfg(feature = "frontend")]
+    frontend: Some(sfrontend::Frontend {
+        tcp: Some(sfrontend::FrontendTcp {
+            default_port: 3035,
+            handler: frontend::tcp_handler,
+            background: None,
+        }),
+        udp: None,
+    }),
+    #[cfg(feature = "backend")]
+    backend: Some(service::Backend {
+        handler: backend::handler,
+    }),
+};
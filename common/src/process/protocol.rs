@@ -0,0 +1,219 @@
+use crate::util;
+use std::io;
+
+const ID_COMMAND_LIST: u8 = 0x00;
+const ID_COMMAND_KILL: u8 = 0x01;
+const ID_COMMAND_SUSPEND: u8 = 0x02;
+const ID_COMMAND_RESUME: u8 = 0x03;
+const ID_COMMAND_START: u8 = 0x04;
+
+fn read_u32<R>(stream: &mut R) -> Result<u32, io::Error>
+where
+    R: io::Read,
+{
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[derive(Debug)]
+pub struct Process {
+    pub pid: u32,
+    pub name: String,
+    pub user: String,
+    // resident memory, in bytes
+    pub memory: u64,
+    pub command_line: String,
+}
+
+impl Process {
+    #[cfg(feature = "backend")]
+    fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        stream.write_all(&self.pid.to_le_bytes())?;
+        util::serialize_string(stream, &self.name)?;
+        util::serialize_string(stream, &self.user)?;
+        stream.write_all(&self.memory.to_le_bytes())?;
+        util::serialize_string(stream, &self.command_line)
+    }
+
+    #[cfg(feature = "frontend")]
+    fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let pid = read_u32(stream)?;
+        let name = util::deserialize_string(stream)?;
+        let user = util::deserialize_string(stream)?;
+        let mut memory = [0u8; 8];
+        stream.read_exact(&mut memory)?;
+        let command_line = util::deserialize_string(stream)?;
+        Ok(Self {
+            pid,
+            name,
+            user,
+            memory: u64::from_le_bytes(memory),
+            command_line,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    List,
+    Kill(u32),
+    Suspend(u32),
+    Resume(u32),
+    // detached program, its standard output and error appended to a file
+    Start {
+        program: String,
+        args: Vec<String>,
+        output: String,
+    },
+}
+
+impl Command {
+    #[cfg(feature = "frontend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::List => stream.write_all(&[ID_COMMAND_LIST])?,
+            Self::Kill(pid) => {
+                stream.write_all(&[ID_COMMAND_KILL])?;
+                stream.write_all(&pid.to_le_bytes())?;
+            }
+            Self::Suspend(pid) => {
+                stream.write_all(&[ID_COMMAND_SUSPEND])?;
+                stream.write_all(&pid.to_le_bytes())?;
+            }
+            Self::Resume(pid) => {
+                stream.write_all(&[ID_COMMAND_RESUME])?;
+                stream.write_all(&pid.to_le_bytes())?;
+            }
+            Self::Start {
+                program,
+                args,
+                output,
+            } => {
+                stream.write_all(&[ID_COMMAND_START])?;
+                util::serialize_string(stream, program)?;
+                let count = u16::try_from(args.len())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+                stream.write_all(&count.to_le_bytes())?;
+                for arg in args {
+                    util::serialize_string(stream, arg)?;
+                }
+                util::serialize_string(stream, output)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "backend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            ID_COMMAND_LIST => Ok(Self::List),
+            ID_COMMAND_KILL => Ok(Self::Kill(read_u32(stream)?)),
+            ID_COMMAND_SUSPEND => Ok(Self::Suspend(read_u32(stream)?)),
+            ID_COMMAND_RESUME => Ok(Self::Resume(read_u32(stream)?)),
+            ID_COMMAND_START => {
+                let program = util::deserialize_string(stream)?;
+                let mut count = [0u8; 2];
+                stream.read_exact(&mut count)?;
+                let args = (0..u16::from_le_bytes(count))
+                    .map(|_| util::deserialize_string(stream))
+                    .collect::<Result<Vec<String>, io::Error>>()?;
+                let output = util::deserialize_string(stream)?;
+                Ok(Self::Start {
+                    program,
+                    args,
+                    output,
+                })
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid command",
+            )),
+        }
+    }
+}
+
+const ID_RESPONSE_PROCESSES: u8 = 0x00;
+const ID_RESPONSE_STARTED: u8 = 0x01;
+const ID_RESPONSE_DONE: u8 = 0x02;
+const ID_RESPONSE_ERROR: u8 = 0x03;
+
+#[derive(Debug)]
+pub enum Response {
+    Processes(Vec<Process>),
+    // pid of the started program
+    Started(u32),
+    Done,
+    Error(String),
+}
+
+impl Response {
+    #[cfg(feature = "backend")]
+    pub fn send<W>(&self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Self::Processes(processes) => {
+                stream.write_all(&[ID_RESPONSE_PROCESSES])?;
+                let count = u32::try_from(processes.len())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+                stream.write_all(&count.to_le_bytes())?;
+                for process in processes {
+                    process.send(stream)?;
+                }
+            }
+            Self::Started(pid) => {
+                stream.write_all(&[ID_RESPONSE_STARTED])?;
+                stream.write_all(&pid.to_le_bytes())?;
+            }
+            Self::Done => stream.write_all(&[ID_RESPONSE_DONE])?,
+            Self::Error(msg) => {
+                stream.write_all(&[ID_RESPONSE_ERROR])?;
+                util::serialize_string(stream, msg)?;
+            }
+        }
+        stream.flush()
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn receive<R>(stream: &mut R) -> Result<Self, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf)?;
+
+        match buf[0] {
+            ID_RESPONSE_PROCESSES => {
+                let count = read_u32(stream)?;
+                let processes = (0..count)
+                    .map(|_| Process::receive(stream))
+                    .collect::<Result<Vec<Process>, io::Error>>()?;
+                Ok(Self::Processes(processes))
+            }
+            ID_RESPONSE_STARTED => Ok(Self::Started(read_u32(stream)?)),
+            ID_RESPONSE_DONE => Ok(Self::Done),
+            ID_RESPONSE_ERROR => Ok(Self::Error(util::deserialize_string(stream)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid response",
+            )),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use super::protocol;
+use std::{collections, fs, io};
+
+const PROC: &str = "/proc";
+
+// Names of the users by uid, from /etc/passwd
+fn users() -> collections::HashMap<u32, String> {
+    fs::read_to_string("/etc/passwd")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?.parse().ok()?;
+            Some((uid, name.to_string()))
+        })
+        .collect()
+}
+
+fn read(
+    pid: u32,
+    users: &collections::HashMap<u32, String>,
+) -> Result<protocol::Process, io::Error> {
+    let status = fs::read_to_string(format!("{PROC}/{pid}/status"))?;
+
+    let mut name = String::new();
+    let mut user = String::new();
+    let mut memory = 0;
+    for line in status.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            "Name" => name = value.to_string(),
+            "Uid" => {
+                // the real one, followed by the effective one, etc.
+                let uid = value.split_whitespace().next().unwrap_or_default();
+                user = uid
+                    .parse()
+                    .ok()
+                    .and_then(|uid| users.get(&uid).cloned())
+                    .unwrap_or_else(|| uid.to_string());
+            }
+            "VmRSS" => {
+                memory = value
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .unwrap_or(0)
+                    .saturating_mul(1024);
+            }
+            _ => (),
+        }
+    }
+
+    // empty for kernel threads
+    let command_line = fs::read(format!("{PROC}/{pid}/cmdline"))?
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Ok(protocol::Process {
+        pid,
+        name,
+        user,
+        memory,
+        command_line,
+    })
+}
+
+pub fn list() -> Result<Vec<protocol::Process>, io::Error> {
+    let users = users();
+
+    let mut processes = fs::read_dir(PROC)?
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        // the process may have exited in the meantime
+        .filter_map(|pid| read(pid, &users).ok())
+        .collect::<Vec<_>>();
+    processes.sort_by_key(|process| process.pid);
+
+    Ok(processes)
+}
+
+fn signal(pid: u32, signal: libc::c_int) -> Result<(), io::Error> {
+    // 0 would be the process group of the backend
+    let pid = libc::pid_t::try_from(pid)
+        .ok()
+        .filter(|pid| 0 < *pid)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid pid"))?;
+    // SAFETY: kill only takes integers, pid is a single positive process
+    if unsafe { libc::kill(pid, signal) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn kill(pid: u32) -> Result<(), io::Error> {
+    signal(pid, libc::SIGKILL)
+}
+
+pub fn suspend(pid: u32) -> Result<(), io::Error> {
+    signal(pid, libc::SIGSTOP)
+}
+
+pub fn resume(pid: u32) -> Result<(), io::Error> {
+    signal(pid, libc::SIGCONT)
+}
@@ -0,0 +1,232 @@
+use super::protocol;
+use std::{
+    ffi, io, mem,
+    os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    ptr,
+};
+use windows_sys::{
+    Wdk::System::Threading as WdkThreading,
+    Win32::{
+        Foundation, Security,
+        System::{Diagnostics::ToolHelp, ProcessStatus, Threading},
+    },
+};
+
+fn owned(handle: Foundation::HANDLE) -> Result<OwnedHandle, io::Error> {
+    if handle.is_null() || handle == Foundation::INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle) })
+}
+
+fn open(pid: u32, access: Threading::PROCESS_ACCESS_RIGHTS) -> Result<OwnedHandle, io::Error> {
+    owned(unsafe { Threading::OpenProcess(access, 0, pid) })
+}
+
+fn wide_string(s: &[u16]) -> String {
+    let len = s.iter().position(|c| *c == 0).unwrap_or(s.len());
+    String::from_utf16_lossy(&s[..len])
+}
+
+// DOMAIN\name of the owner of the process
+fn user(process: &OwnedHandle) -> Option<String> {
+    let mut token = ptr::null_mut();
+    if unsafe {
+        Threading::OpenProcessToken(
+            process.as_raw_handle(),
+            Security::TOKEN_QUERY,
+            &raw mut token,
+        )
+    } == 0
+    {
+        return None;
+    }
+    let token = owned(token).ok()?;
+
+    let mut len = 0;
+    unsafe {
+        Security::GetTokenInformation(
+            token.as_raw_handle(),
+            Security::TokenUser,
+            ptr::null_mut(),
+            0,
+            &raw mut len,
+        );
+    }
+    // u64 for the alignment of TOKEN_USER
+    let mut buf = vec![0u64; usize::try_from(len).ok()?.div_ceil(mem::size_of::<u64>())];
+    if unsafe {
+        Security::GetTokenInformation(
+            token.as_raw_handle(),
+            Security::TokenUser,
+            buf.as_mut_ptr().cast(),
+            len,
+            &raw mut len,
+        )
+    } == 0
+    {
+        return None;
+    }
+    let sid = unsafe { (*buf.as_ptr().cast::<Security::TOKEN_USER>()).User.Sid };
+
+    let mut name = [0u16; 256];
+    let mut name_len = 256;
+    let mut domain = [0u16; 256];
+    let mut domain_len = 256;
+    let mut sid_use = 0;
+    if unsafe {
+        Security::LookupAccountSidW(
+            ptr::null(),
+            sid,
+            name.as_mut_ptr(),
+            &raw mut name_len,
+            domain.as_mut_ptr(),
+            &raw mut domain_len,
+            &raw mut sid_use,
+        )
+    } == 0
+    {
+        return None;
+    }
+
+    Some(format!("{}\\{}", wide_string(&domain), wide_string(&name)))
+}
+
+fn memory(process: &OwnedHandle) -> Option<u64> {
+    let mut counters = ProcessStatus::PROCESS_MEMORY_COUNTERS::default();
+    let size = u32::try_from(mem::size_of::<ProcessStatus::PROCESS_MEMORY_COUNTERS>()).ok()?;
+    counters.cb = size;
+    if unsafe {
+        ProcessStatus::K32GetProcessMemoryInfo(process.as_raw_handle(), &raw mut counters, size)
+    } == 0
+    {
+        return None;
+    }
+    u64::try_from(counters.WorkingSetSize).ok()
+}
+
+fn command_line(process: &OwnedHandle) -> Option<String> {
+    let mut len = 0;
+    unsafe {
+        WdkThreading::NtQueryInformationProcess(
+            process.as_raw_handle(),
+            WdkThreading::ProcessCommandLineInformation,
+            ptr::null_mut(),
+            0,
+            &raw mut len,
+        );
+    }
+    if len == 0 {
+        return None;
+    }
+    // a UNICODE_STRING pointing to the rest of the buffer
+    let mut buf = vec![0u64; usize::try_from(len).ok()?.div_ceil(mem::size_of::<u64>())];
+    if unsafe {
+        WdkThreading::NtQueryInformationProcess(
+            process.as_raw_handle(),
+            WdkThreading::ProcessCommandLineInformation,
+            buf.as_mut_ptr().cast::<ffi::c_void>(),
+            len,
+            &raw mut len,
+        )
+    } < 0
+    {
+        return None;
+    }
+    let command_line = unsafe { &*buf.as_ptr().cast::<Foundation::UNICODE_STRING>() };
+    if command_line.Buffer.is_null() {
+        return None;
+    }
+    let command_line = unsafe {
+        std::slice::from_raw_parts(
+            command_line.Buffer,
+            usize::from(command_line.Length) / mem::size_of::<u16>(),
+        )
+    };
+    Some(String::from_utf16_lossy(command_line))
+}
+
+pub fn list() -> Result<Vec<protocol::Process>, io::Error> {
+    let snapshot =
+        owned(unsafe { ToolHelp::CreateToolhelp32Snapshot(ToolHelp::TH32CS_SNAPPROCESS, 0) })?;
+
+    let mut processes = vec![];
+
+    let mut entry = ToolHelp::PROCESSENTRY32W {
+        dwSize: u32::try_from(mem::size_of::<ToolHelp::PROCESSENTRY32W>()).unwrap_or(0),
+        ..Default::default()
+    };
+    let mut found = unsafe { ToolHelp::Process32FirstW(snapshot.as_raw_handle(), &raw mut entry) };
+    while found != 0 {
+        let pid = entry.th32ProcessID;
+
+        // some details are not available without privileges
+        let process = open(pid, Threading::PROCESS_QUERY_LIMITED_INFORMATION).ok();
+        let process = process.as_ref();
+
+        processes.push(protocol::Process {
+            pid,
+            name: wide_string(&entry.szExeFile),
+            user: process.and_then(user).unwrap_or_default(),
+            memory: process.and_then(memory).unwrap_or(0),
+            command_line: process.and_then(command_line).unwrap_or_default(),
+        });
+
+        found = unsafe { ToolHelp::Process32NextW(snapshot.as_raw_handle(), &raw mut entry) };
+    }
+
+    processes.sort_by_key(|process| process.pid);
+
+    Ok(processes)
+}
+
+pub fn kill(pid: u32) -> Result<(), io::Error> {
+    let process = open(pid, Threading::PROCESS_TERMINATE)?;
+    if unsafe { Threading::TerminateProcess(process.as_raw_handle(), 1) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Windows has no signal to stop a process, each of its threads is
+// suspended or resumed instead
+fn threads<F>(pid: u32, f: F) -> Result<(), io::Error>
+where
+    F: Fn(Foundation::HANDLE) -> u32,
+{
+    let snapshot =
+        owned(unsafe { ToolHelp::CreateToolhelp32Snapshot(ToolHelp::TH32CS_SNAPTHREAD, 0) })?;
+
+    let mut count = 0;
+
+    let mut entry = ToolHelp::THREADENTRY32 {
+        dwSize: u32::try_from(mem::size_of::<ToolHelp::THREADENTRY32>()).unwrap_or(0),
+        ..Default::default()
+    };
+    let mut found = unsafe { ToolHelp::Thread32First(snapshot.as_raw_handle(), &raw mut entry) };
+    while found != 0 {
+        if entry.th32OwnerProcessID == pid {
+            let thread = owned(unsafe {
+                Threading::OpenThread(Threading::THREAD_SUSPEND_RESUME, 0, entry.th32ThreadID)
+            })?;
+            if f(thread.as_raw_handle()) == u32::MAX {
+                return Err(io::Error::last_os_error());
+            }
+            count += 1;
+        }
+        found = unsafe { ToolHelp::Thread32Next(snapshot.as_raw_handle(), &raw mut entry) };
+    }
+
+    if count == 0 {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no such process"));
+    }
+    Ok(())
+}
+
+pub fn suspend(pid: u32) -> Result<(), io::Error> {
+    threads(pid, |thread| unsafe { Threading::SuspendThread(thread) })
+}
+
+pub fn resume(pid: u32) -> Result<(), io::Error> {
+    threads(pid, |thread| unsafe { Threading::ResumeThread(thread) })
+}
@@ -95,7 +95,10 @@ impl<'a> Handle<'a> {
     }
 
     fn receive(&self) -> Result<api::Chunk, api::Error> {
-        let chunk = self.state.read().unwrap().will_receive()?.recv()?;
+        // the lock must not be held while waiting, the state may be
+        // changed concurrently by a writer closing its side
+        let from_rdp = self.state.read().unwrap().will_receive()?.clone();
+        let chunk = from_rdp.recv()?;
 
         crate::trace!("RDP receive {chunk}");
 
@@ -191,7 +194,7 @@ impl<'a> RdpStream<'a> {
         }
     }
 
-    #[cfg(any(feature = "frontend", feature = "log"))]
+    #[cfg(any(feature = "frontend", feature = "log", feature = "service-command"))]
     pub(crate) fn client_id(&self) -> api::ClientId {
         self.handle.client_id
     }
@@ -2,18 +2,28 @@
 use crate::frontend;
 use crate::{api, rdp};
 
+#[cfg(feature = "service-archive")]
+use crate::archive;
 #[cfg(feature = "service-clipboard")]
 use crate::clipboard;
 #[cfg(feature = "service-command")]
 use crate::command;
+#[cfg(feature = "service-dns")]
+use crate::dns;
 #[cfg(feature = "service-forward")]
 use crate::forward;
 #[cfg(feature = "service-ftp")]
 use crate::ftp;
+#[cfg(feature = "service-http-proxy")]
+use crate::http_proxy;
 #[cfg(feature = "service-input")]
 use crate::input;
+#[cfg(feature = "service-process")]
+use crate::process;
 #[cfg(feature = "service-socks5")]
 use crate::socks5;
+#[cfg(feature = "service-ssh")]
+use crate::ssh;
 #[cfg(feature = "service-stage0")]
 use crate::stage0;
 
@@ -175,18 +185,28 @@ pub(crate) const LOGO: &str = r"
                    |___/";
 
 pub static SERVICES: &[&Service] = &[
+    #[cfg(feature = "service-archive")]
+    &archive::SERVICE,
     #[cfg(feature = "service-clipboard")]
     &clipboard::SERVICE,
     #[cfg(feature = "service-command")]
     &command::SERVICE,
+    #[cfg(feature = "service-dns")]
+    &dns::SERVICE,
     #[cfg(feature = "service-forward")]
     &forward::SERVICE,
     #[cfg(feature = "service-ftp")]
     &ftp::SERVICE,
+    #[cfg(feature = "service-http-proxy")]
+    &http_proxy::SERVICE,
     #[cfg(feature = "service-input")]
     &input::SERVICE,
+    #[cfg(feature = "service-process")]
+    &process::SERVICE,
     #[cfg(feature = "service-socks5")]
     &socks5::SERVICE,
+    #[cfg(feature = "service-ssh")]
+    &ssh::SERVICE,
     #[cfg(feature = "service-stage0")]
     &stage0::SERVICE,
 ];
@@ -1,5 +1,5 @@
 use super::protocol;
-use crate::{rdp, service, util};
+use crate::{outbound, rdp, service, util};
 use std::{
     io::{self, Write},
     net,
@@ -28,21 +28,26 @@ fn encode_addr(addr: &net::SocketAddr) -> Result<Vec<u8>, io::Error> {
 fn command_connect(mut stream: rdp::RdpStream<'_>, to_tcp: &str) -> Result<(), io::Error> {
     crate::info!("connecting to {to_tcp:#?}");
 
-    match net::TcpStream::connect(to_tcp) {
+    match outbound::connect(to_tcp) {
         Err(e) => {
             crate::error!("failed to connect to {to_tcp:#?}: {e}");
-            match e.kind() {
-                io::ErrorKind::ConnectionAborted | io::ErrorKind::TimedOut => {
-                    protocol::Response::HostUnreachable.send(&mut stream)
+            let response = match e.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                    protocol::Response::TtlExpired
                 }
-                io::ErrorKind::ConnectionRefused => {
-                    protocol::Response::ConnectionRefused.send(&mut stream)
+                io::ErrorKind::HostUnreachable | io::ErrorKind::ConnectionAborted => {
+                    protocol::Response::HostUnreachable
                 }
-                _ => {
-                    crate::error!("failed to connect to {to_tcp:#?}: {e}");
-                    protocol::Response::NetworkUnreachable.send(&mut stream)
+                io::ErrorKind::NetworkUnreachable | io::ErrorKind::AddrNotAvailable => {
+                    protocol::Response::NetworkUnreachable
                 }
-            }
+                io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
+                    protocol::Response::ConnectionRefused
+                }
+                io::ErrorKind::PermissionDenied => protocol::Response::ConnectionNotAllowed,
+                _ => protocol::Response::GeneralFailure,
+            };
+            response.send(&mut stream)
         }
         Ok(server) => {
             crate::debug!("connected to {to_tcp:#?}");
@@ -6,7 +6,7 @@ use crate::service;
 mod backend;
 #[cfg(feature = "frontend")]
 mod frontend;
-mod protocol;
+pub mod protocol;
 
 pub static SERVICE: service::Service = service::Service {
     internal: false,
@@ -16,7 +16,9 @@ pub static SERVICE: service::Service = service::Service {
         tcp: Some(sfrontend::FrontendTcp {
             default_port: 1080,
             handler: frontend::tcp_handler,
+            background: None,
         }),
+        udp: None,
     }),
     #[cfg(feature = "backend")]
     backend: Some(service::Backend {
@@ -101,9 +101,9 @@ impl Command {
                 reader.read_exact(&mut buf)?;
                 let port = u16::from_be_bytes(buf);
 
-                crate::info!("connect to {ip}:{port}",);
+                crate::info!("connect to [{ip}]:{port}",);
 
-                format!("{ip}:{port}")
+                format!("[{ip}]:{port}")
             }
             t => return Err(Error::AddressTypeNotSupported(t)),
         };
@@ -155,7 +155,10 @@ impl Command {
                 Ok(Self::Connect(to_tcp))
             }
             ID_CMD_BIND => Ok(Self::Bind),
-            v => unimplemented!("unsupported socks command {v}"),
+            v => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported socks command {v}"),
+            )),
         }
     }
 }
@@ -165,6 +168,9 @@ const ID_RESP_NETWORK_UNREACHABLE: u8 = 0xD1;
 const ID_RESP_HOST_UNREACHABLE: u8 = 0xD2;
 const ID_RESP_CONNECTION_REFUSED: u8 = 0xD3;
 const ID_RESP_BIND_FAILED: u8 = 0xD4;
+const ID_RESP_CONNECTION_NOT_ALLOWED: u8 = 0xD5;
+const ID_RESP_TTL_EXPIRED: u8 = 0xD6;
+const ID_RESP_GENERAL_FAILURE: u8 = 0xD7;
 
 #[derive(Debug)]
 pub enum Response {
@@ -173,20 +179,25 @@ pub enum Response {
     HostUnreachable,
     ConnectionRefused,
     BindFailed,
+    ConnectionNotAllowed,
+    TtlExpired,
+    GeneralFailure,
 }
 
 #[cfg(feature = "frontend")]
 const RSP_OK: u8 = 0x00;
 #[cfg(feature = "frontend")]
 const RSP_GENERAL_SOCKS_SERVER_FAILURE: u8 = 0x01;
-//const RSP_CONNECTION_NOT_ALLOWED: u8 = 0x02;
+#[cfg(feature = "frontend")]
+const RSP_CONNECTION_NOT_ALLOWED: u8 = 0x02;
 #[cfg(feature = "frontend")]
 const RSP_NETWORK_UNREACHABLE: u8 = 0x03;
 #[cfg(feature = "frontend")]
 const RSP_HOST_UNREACHABLE: u8 = 0x04;
 #[cfg(feature = "frontend")]
 const RSP_CONNECTION_REFUSED: u8 = 0x05;
-//const RSP_TTL_EXPIRED: u8 = 0x06;
+#[cfg(feature = "frontend")]
+const RSP_TTL_EXPIRED: u8 = 0x06;
 //const RSP_COMMAND_NOT_SUPPORTED: u8 = 0x07;
 //const RSP_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
 
@@ -247,7 +258,7 @@ impl Response {
                 ];
                 writer.write_all(&buf)?;
             }
-            Self::BindFailed => {
+            Self::BindFailed | Self::GeneralFailure => {
                 let buf = [
                     VERSION,
                     RSP_GENERAL_SOCKS_SERVER_FAILURE,
@@ -262,6 +273,36 @@ impl Response {
                 ];
                 writer.write_all(&buf)?;
             }
+            Self::ConnectionNotAllowed => {
+                let buf = [
+                    VERSION,
+                    RSP_CONNECTION_NOT_ALLOWED,
+                    0x00,
+                    0x01,
+                    0x00,
+                    0x00,
+                    0x00,
+                    0x00,
+                    0x00,
+                    0x00,
+                ];
+                writer.write_all(&buf)?;
+            }
+            Self::TtlExpired => {
+                let buf = [
+                    VERSION,
+                    RSP_TTL_EXPIRED,
+                    0x00,
+                    0x01,
+                    0x00,
+                    0x00,
+                    0x00,
+                    0x00,
+                    0x00,
+                    0x00,
+                ];
+                writer.write_all(&buf)?;
+            }
             Self::Ok(data) => {
                 writer.write_all(&[VERSION, RSP_OK, 0x00])?;
                 writer.write_all(data)?;
@@ -281,6 +322,9 @@ impl Response {
             Self::HostUnreachable => (ID_RESP_HOST_UNREACHABLE, None),
             Self::ConnectionRefused => (ID_RESP_CONNECTION_REFUSED, None),
             Self::BindFailed => (ID_RESP_BIND_FAILED, None),
+            Self::ConnectionNotAllowed => (ID_RESP_CONNECTION_NOT_ALLOWED, None),
+            Self::TtlExpired => (ID_RESP_TTL_EXPIRED, None),
+            Self::GeneralFailure => (ID_RESP_GENERAL_FAILURE, None),
         };
         let buf = [id; 1];
         stream.write_all(&buf)?;
@@ -317,7 +361,13 @@ impl Response {
             ID_RESP_HOST_UNREACHABLE => Ok(Self::HostUnreachable),
             ID_RESP_CONNECTION_REFUSED => Ok(Self::ConnectionRefused),
             ID_RESP_BIND_FAILED => Ok(Self::BindFailed),
-            v => unimplemented!("unsupported socks response {v}"),
+            ID_RESP_CONNECTION_NOT_ALLOWED => Ok(Self::ConnectionNotAllowed),
+            ID_RESP_TTL_EXPIRED => Ok(Self::TtlExpired),
+            ID_RESP_GENERAL_FAILURE => Ok(Self::GeneralFailure),
+            v => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported socks response {v}"),
+            )),
         }
     }
 }
@@ -0,0 +1,858 @@
+use super::{keys, sftp, transport, wire};
+#[cfg(feature = "log")]
+use crate::service;
+use crate::{api, channel, command, forward, frontend, ftp, rdp, util};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    net,
+    sync::{Arc, Condvar, Mutex},
+    thread, time,
+};
+
+#[cfg(feature = "log")]
+const SERVICE_KIND: service::Kind = service::Kind::Frontend;
+
+const MSG_UNIMPLEMENTED: u8 = 3;
+const MSG_SERVICE_REQUEST: u8 = 5;
+const MSG_SERVICE_ACCEPT: u8 = 6;
+const MSG_USERAUTH_REQUEST: u8 = 50;
+const MSG_USERAUTH_FAILURE: u8 = 51;
+const MSG_USERAUTH_SUCCESS: u8 = 52;
+const MSG_USERAUTH_PK_OK: u8 = 60;
+const MSG_GLOBAL_REQUEST: u8 = 80;
+const MSG_REQUEST_FAILURE: u8 = 82;
+const MSG_CHANNEL_OPEN: u8 = 90;
+const MSG_CHANNEL_OPEN_CONFIRMATION: u8 = 91;
+const MSG_CHANNEL_OPEN_FAILURE: u8 = 92;
+const MSG_CHANNEL_WINDOW_ADJUST: u8 = 93;
+const MSG_CHANNEL_DATA: u8 = 94;
+const MSG_CHANNEL_EXTENDED_DATA: u8 = 95;
+const MSG_CHANNEL_EOF: u8 = 96;
+const MSG_CHANNEL_CLOSE: u8 = 97;
+const MSG_CHANNEL_REQUEST: u8 = 98;
+const MSG_CHANNEL_SUCCESS: u8 = 99;
+const MSG_CHANNEL_FAILURE: u8 = 100;
+
+const OPEN_CONNECT_FAILED: u32 = 2;
+const OPEN_UNKNOWN_CHANNEL_TYPE: u32 = 3;
+
+const EXTENDED_DATA_STDERR: u32 = 1;
+
+const MAX_AUTH_ATTEMPTS: usize = 10;
+
+const AUTH_FAILURE_DELAY: time::Duration = time::Duration::from_secs(1);
+
+// Data the client may send on a channel before being granted more
+const WINDOW_SIZE: u32 = 2 * 1024 * 1024;
+
+const MAX_PACKET_SIZE: u32 = 32 * 1024;
+
+// Environment variables accepted without "accept_env" setting
+const DEFAULT_ACCEPT_ENV: [&str; 2] = ["LANG", "LC_*"];
+
+// Public key authentication (RFC 4252 section 7) against the
+// authorized keys; any user name is accepted as everything runs with
+// the identity of the backend
+fn authenticate(
+    reader: &mut transport::Reader,
+    writer: &transport::Writer,
+    settings: &frontend::FrontendSettings,
+) -> Result<Option<String>, io::Error> {
+    let request = reader.receive(writer)?;
+    if request[0] != MSG_SERVICE_REQUEST
+        || wire::Reader::new(&request[1..]).string()? != b"ssh-userauth"
+    {
+        writer.disconnect(
+            transport::DISCONNECT_SERVICE_NOT_AVAILABLE,
+            "service not available",
+        );
+        return Ok(None);
+    }
+    writer.send(
+        &wire::Writer::new(MSG_SERVICE_ACCEPT)
+            .string(b"ssh-userauth")
+            .finish(),
+    )?;
+
+    let authorized_keys = keys::authorized_keys(settings);
+    if authorized_keys.is_empty() {
+        crate::warn!("no authorized key");
+    }
+
+    let mut failures = 0;
+
+    loop {
+        let request = reader.receive(writer)?;
+        if request[0] != MSG_USERAUTH_REQUEST {
+            writer.disconnect(
+                transport::DISCONNECT_PROTOCOL_ERROR,
+                "authentication required",
+            );
+            return Ok(None);
+        }
+
+        let mut message = wire::Reader::new(&request[1..]);
+        let user = message.utf8()?;
+        let service = message.string()?;
+        let method = message.string()?;
+
+        if service == b"ssh-connection" && method == b"publickey" {
+            let signed = message.bool()?;
+            let algorithm = message.utf8()?;
+            let key = message.string()?;
+
+            let authorized = keys::USER_KEY_ALGORITHMS.contains(&algorithm.as_str())
+                && authorized_keys.iter().any(|authorized| authorized == key);
+
+            if authorized && !signed {
+                writer.send(
+                    &wire::Writer::new(MSG_USERAUTH_PK_OK)
+                        .string(algorithm.as_bytes())
+                        .string(key)
+                        .finish(),
+                )?;
+                continue;
+            }
+
+            if authorized {
+                let signature = message.string()?;
+                // the request without the signature
+                let signed_data = wire::Writer::raw()
+                    .string(reader.session_id())
+                    .bytes(&request[..request.len() - 4 - signature.len()])
+                    .finish();
+                if keys::verify(&algorithm, key, &signed_data, signature) {
+                    writer.send(&[MSG_USERAUTH_SUCCESS])?;
+                    return Ok(Some(user));
+                }
+                crate::warn!("invalid signature of {user:?}");
+                // slows down guessing
+                thread::sleep(AUTH_FAILURE_DELAY);
+            }
+        }
+
+        if method != b"none" {
+            failures += 1;
+            if MAX_AUTH_ATTEMPTS <= failures {
+                crate::warn!("authentication of {user:?} failed");
+                writer.disconnect(
+                    transport::DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE,
+                    "too many authentication failures",
+                );
+                return Ok(None);
+            }
+        }
+
+        writer.send(
+            &wire::Writer::new(MSG_USERAUTH_FAILURE)
+                .name_list(&["publickey"])
+                .bool(false)
+                .finish(),
+        )?;
+    }
+}
+
+// Where the data received on a channel goes
+enum Sink<'a> {
+    // standard input of a program or forwarded connection
+    Raw(rdp::RdpWriter<'a>),
+    Pty(rdp::RdpWriter<'a>),
+    Sftp(crossbeam_channel::Sender<Vec<u8>>),
+}
+
+impl Sink<'_> {
+    fn write(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        match self {
+            Self::Raw(rdp) => {
+                rdp.write_all(data)?;
+                rdp.flush()
+            }
+            Self::Pty(rdp) => data
+                .chunks(usize::from(u16::MAX))
+                .try_for_each(|data| command::protocol::Input::Data(data.to_vec()).send(rdp)),
+            Self::Sftp(sender) => sender
+                .send(data.to_vec())
+                .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())),
+        }
+    }
+}
+
+struct ChannelState {
+    // data the client still accepts
+    window: u32,
+    // SSH_MSG_CHANNEL_CLOSE was sent
+    closed: bool,
+}
+
+struct SshChannel<'a> {
+    // identifier of the channel on the client side
+    remote_id: u32,
+    max_packet: usize,
+    state: Mutex<ChannelState>,
+    window_changed: Condvar,
+    sink: Mutex<Option<Sink<'a>>>,
+}
+
+impl SshChannel<'_> {
+    // Waits for the client to accept more data if needed, fails once
+    // the channel is closed
+    fn send_data(
+        &self,
+        writer: &transport::Writer,
+        extended: Option<u32>,
+        mut data: &[u8],
+    ) -> Result<(), io::Error> {
+        while !data.is_empty() {
+            let mut state = self
+                .window_changed
+                .wait_while(self.state.lock().unwrap(), |state| {
+                    state.window == 0 && !state.closed
+                })
+                .unwrap();
+            if state.closed {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "channel closed"));
+            }
+
+            let len = data
+                .len()
+                .min(self.max_packet)
+                .min(usize::try_from(state.window).unwrap_or(usize::MAX));
+            let (chunk, rest) = data.split_at(len);
+
+            let mut message = match extended {
+                None => wire::Writer::new(MSG_CHANNEL_DATA),
+                Some(_) => wire::Writer::new(MSG_CHANNEL_EXTENDED_DATA),
+            };
+            message.u32(self.remote_id);
+            if let Some(code) = extended {
+                message.u32(code);
+            }
+            writer.send(&message.string(chunk).finish())?;
+            state.window -= u32::try_from(len).unwrap_or_default();
+            data = rest;
+        }
+        Ok(())
+    }
+
+    // Sends EOF, the exit status if any, then CLOSE; nothing is sent on
+    // a closed channel
+    fn close(&self, writer: &transport::Writer, exit_status: Option<u32>) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        state.closed = true;
+        self.window_changed.notify_all();
+
+        let _ = writer.send(
+            &wire::Writer::new(MSG_CHANNEL_EOF)
+                .u32(self.remote_id)
+                .finish(),
+        );
+        if let Some(exit_status) = exit_status {
+            let _ = writer.send(
+                &wire::Writer::new(MSG_CHANNEL_REQUEST)
+                    .u32(self.remote_id)
+                    .string(b"exit-status")
+                    .bool(false)
+                    .u32(exit_status)
+                    .finish(),
+            );
+        }
+        let _ = writer.send(
+            &wire::Writer::new(MSG_CHANNEL_CLOSE)
+                .u32(self.remote_id)
+                .finish(),
+        );
+    }
+
+    // Replies to the client unless the channel is closed
+    fn send(&self, writer: &transport::Writer, message: &[u8]) -> Result<(), io::Error> {
+        let state = self.state.lock().unwrap();
+        if state.closed {
+            return Ok(());
+        }
+        writer.send(message)
+    }
+
+    fn reply(
+        &self,
+        writer: &transport::Writer,
+        want_reply: bool,
+        success: bool,
+    ) -> Result<(), io::Error> {
+        if !want_reply {
+            return Ok(());
+        }
+        let kind = if success {
+            MSG_CHANNEL_SUCCESS
+        } else {
+            MSG_CHANNEL_FAILURE
+        };
+        self.send(
+            writer,
+            &wire::Writer::new(kind).u32(self.remote_id).finish(),
+        )
+    }
+}
+
+// Settings of a session channel before its program starts
+#[derive(Default)]
+struct Session {
+    terminal: Option<command::protocol::Terminal>,
+    env: Vec<(String, String)>,
+    started: bool,
+}
+
+struct Entry<'a> {
+    channel: Arc<SshChannel<'a>>,
+    // data received since the last window adjustment
+    consumed: u32,
+    session: Option<Session>,
+}
+
+fn size(cols: u32, rows: u32) -> command::protocol::Size {
+    let default = command::protocol::Size::default();
+    command::protocol::Size {
+        cols: u16::try_from(cols)
+            .ok()
+            .filter(|cols| *cols != 0)
+            .unwrap_or(default.cols),
+        rows: u16::try_from(rows)
+            .ok()
+            .filter(|rows| *rows != 0)
+            .unwrap_or(default.rows),
+    }
+}
+
+fn connect_forward<'a>(
+    channel: &'a channel::Channel,
+    destination: &str,
+) -> Result<rdp::RdpStream<'a>, io::Error> {
+    let mut rdp = channel.connect(&forward::SERVICE)?;
+    forward::protocol::Command::Connect(destination.to_string()).send(&mut rdp)?;
+    match forward::protocol::Response::receive(&mut rdp)? {
+        forward::protocol::Response::Connected => Ok(rdp),
+        forward::protocol::Response::Error(msg) => {
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, msg))
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "log"), allow(unused_variables))]
+fn spawn<'s, F>(scope: &'s thread::Scope<'s, '_>, id: u32, f: F) -> Result<(), io::Error>
+where
+    F: FnOnce() + Send + 's,
+{
+    let thread = thread::Builder::new();
+    #[cfg(feature = "log")]
+    let thread = thread.name(format!("{SERVICE_KIND} {} channel {id}", super::SERVICE));
+    thread.spawn_scoped(scope, f)?;
+    Ok(())
+}
+
+struct Connection<'a> {
+    writer: &'a transport::Writer,
+    channel: &'a channel::Channel,
+    settings: &'a frontend::FrontendSettings,
+    channels: Mutex<HashMap<u32, Entry<'a>>>,
+}
+
+impl<'a> Connection<'a> {
+    // As the AcceptEnv option of OpenSSH, only the environment variables
+    // matching the "accept_env" patterns are passed to the programs
+    fn accepts_env(&self, name: &str) -> bool {
+        let accept = self.settings.get_all("accept_env");
+        if accept.is_empty() {
+            DEFAULT_ACCEPT_ENV
+                .iter()
+                .any(|pattern| util::glob_match(pattern, name))
+        } else {
+            accept.iter().any(|pattern| util::glob_match(pattern, name))
+        }
+    }
+
+    // Copies what the backend sends to the client
+    fn output<R>(&self, channel: &SshChannel<'_>, mut rdp: R)
+    where
+        R: io::Read,
+    {
+        let mut buf = vec![0u8; api::Chunk::max_payload_length()];
+        loop {
+            match rdp.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => {
+                    if channel.send_data(self.writer, None, &buf[..read]).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn open<'s>(
+        &'s self,
+        scope: &'s thread::Scope<'s, '_>,
+        id: u32,
+        message: &mut wire::Reader<'_>,
+    ) -> Result<(), io::Error>
+    where
+        'a: 's,
+    {
+        let kind = message.string()?;
+        let remote_id = message.u32()?;
+        let window = message.u32()?;
+        let max_packet = message.u32()?.min(MAX_PACKET_SIZE);
+
+        let channel = Arc::new(SshChannel {
+            remote_id,
+            max_packet: usize::try_from(max_packet).unwrap_or_default(),
+            state: Mutex::new(ChannelState {
+                window,
+                closed: false,
+            }),
+            window_changed: Condvar::new(),
+            sink: Mutex::new(None),
+        });
+
+        let confirmation = wire::Writer::new(MSG_CHANNEL_OPEN_CONFIRMATION)
+            .u32(remote_id)
+            .u32(id)
+            .u32(WINDOW_SIZE)
+            .u32(MAX_PACKET_SIZE)
+            .finish();
+
+        match kind {
+            b"session" => {
+                self.channels.lock().unwrap().insert(
+                    id,
+                    Entry {
+                        channel,
+                        consumed: 0,
+                        session: Some(Session::default()),
+                    },
+                );
+                self.writer.send(&confirmation)
+            }
+            b"direct-tcpip" => {
+                let host = message.utf8()?;
+                let port = message.u32()?;
+                let destination = if host.contains(':') {
+                    format!("[{host}]:{port}")
+                } else {
+                    format!("{host}:{port}")
+                };
+
+                self.channels.lock().unwrap().insert(
+                    id,
+                    Entry {
+                        channel: channel.clone(),
+                        consumed: 0,
+                        session: None,
+                    },
+                );
+
+                // the connection may take a while
+                spawn(scope, id, move || {
+                    match connect_forward(self.channel, &destination) {
+                        Err(e) => {
+                            crate::warn!("failed to forward to {destination}: {e}");
+                            self.channels.lock().unwrap().remove(&id);
+                            let _ = self.writer.send(
+                                &wire::Writer::new(MSG_CHANNEL_OPEN_FAILURE)
+                                    .u32(remote_id)
+                                    .u32(OPEN_CONNECT_FAILED)
+                                    .string(e.to_string().as_bytes())
+                                    .string(b"")
+                                    .finish(),
+                            );
+                        }
+                        Ok(rdp) => {
+                            crate::debug!("forwarding to {destination}");
+                            let (rdp_read, rdp_write) = rdp.split();
+                            *channel.sink.lock().unwrap() = Some(Sink::Raw(rdp_write));
+                            if self.writer.send(&confirmation).is_ok() {
+                                self.output(&channel, rdp_read);
+                            }
+                            channel.close(self.writer, None);
+                        }
+                    }
+                })
+            }
+            _ => self.writer.send(
+                &wire::Writer::new(MSG_CHANNEL_OPEN_FAILURE)
+                    .u32(remote_id)
+                    .u32(OPEN_UNKNOWN_CHANNEL_TYPE)
+                    .string(b"unknown channel type")
+                    .string(b"")
+                    .finish(),
+            ),
+        }
+    }
+
+    // Starts the default shell of the backend, in a pseudo-terminal if
+    // one was requested
+    fn shell<'s>(
+        &'s self,
+        scope: &'s thread::Scope<'s, '_>,
+        id: u32,
+        channel: &Arc<SshChannel<'a>>,
+        session: &mut Session,
+        want_reply: bool,
+    ) -> Result<(), io::Error>
+    where
+        'a: 's,
+    {
+        let program = command::protocol::Program {
+            env: session.env.clone(),
+            ..Default::default()
+        };
+
+        let mut rdp = self.channel.connect(&command::SERVICE)?;
+        let pty = session.terminal.take();
+        let is_pty = pty.is_some();
+        match pty {
+            Some(terminal) => command::protocol::Start::Pty(terminal, program).send(&mut rdp)?,
+            None => command::protocol::Start::Pipes(program).send(&mut rdp)?,
+        }
+
+        let (rdp_read, rdp_write) = rdp.split();
+        *channel.sink.lock().unwrap() = Some(if is_pty {
+            Sink::Pty(rdp_write)
+        } else {
+            Sink::Raw(rdp_write)
+        });
+        channel.reply(self.writer, want_reply, true)?;
+
+        let channel = channel.clone();
+        spawn(scope, id, move || {
+            self.output(&channel, rdp_read);
+            // the backend does not report the exit code of shells
+            channel.close(self.writer, Some(0));
+        })
+    }
+
+    // Runs a command line with the default shell of the backend
+    fn exec<'s>(
+        &'s self,
+        scope: &'s thread::Scope<'s, '_>,
+        id: u32,
+        channel: &Arc<SshChannel<'a>>,
+        session: &Session,
+        command_line: String,
+        want_reply: bool,
+    ) -> Result<(), io::Error>
+    where
+        'a: 's,
+    {
+        let request = command::protocol::Exec {
+            program: command::protocol::Program {
+                path: String::new(),
+                args: vec![command_line],
+                env: session.env.clone(),
+            },
+            ..Default::default()
+        };
+
+        crate::debug!("{request:?}");
+
+        let mut rdp = self.channel.connect(&command::SERVICE)?;
+        command::protocol::Start::Exec(request).send(&mut rdp)?;
+
+        let (mut rdp_read, rdp_write) = rdp.split();
+        *channel.sink.lock().unwrap() = Some(Sink::Raw(rdp_write));
+        channel.reply(self.writer, want_reply, true)?;
+
+        let channel = channel.clone();
+        spawn(scope, id, move || {
+            let exit_status = loop {
+                let res = match command::protocol::Output::receive(&mut rdp_read) {
+                    Ok(command::protocol::Output::Stdout(data)) => {
+                        channel.send_data(self.writer, None, &data)
+                    }
+                    Ok(command::protocol::Output::Stderr(data)) => {
+                        channel.send_data(self.writer, Some(EXTENDED_DATA_STDERR), &data)
+                    }
+                    Ok(command::protocol::Output::Exit(code)) => break Some(code.cast_unsigned()),
+                    Ok(command::protocol::Output::Error(e)) => {
+                        let _ = channel.send_data(
+                            self.writer,
+                            Some(EXTENDED_DATA_STDERR),
+                            format!("{e}\r\n").as_bytes(),
+                        );
+                        break None;
+                    }
+                    Err(_) => break None,
+                };
+                if res.is_err() {
+                    break None;
+                }
+            };
+            channel.close(self.writer, exit_status);
+        })
+    }
+
+    // Serves SFTP with the backend of the ftp service, restricted with
+    // the "sftp.root", "sftp.read_only" and "sftp.deny" settings
+    fn sftp<'s>(
+        &'s self,
+        scope: &'s thread::Scope<'s, '_>,
+        id: u32,
+        channel: &Arc<SshChannel<'a>>,
+        want_reply: bool,
+    ) -> Result<(), io::Error>
+    where
+        'a: 's,
+    {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        *channel.sink.lock().unwrap() = Some(Sink::Sftp(sender));
+        channel.reply(self.writer, want_reply, true)?;
+
+        let scope_settings = ftp::auth::scope(self.settings, "sftp.");
+        let channel = channel.clone();
+        spawn(scope, id, move || {
+            if let Err(e) = sftp::serve(self.channel, scope_settings, receiver, |data| {
+                channel.send_data(self.writer, None, data)
+            }) {
+                crate::debug!("error: {e}");
+            }
+            channel.close(self.writer, None);
+        })
+    }
+
+    fn request<'s>(
+        &'s self,
+        scope: &'s thread::Scope<'s, '_>,
+        message: &mut wire::Reader<'_>,
+    ) -> Result<(), io::Error>
+    where
+        'a: 's,
+    {
+        let id = message.u32()?;
+        let kind = message.utf8()?;
+        let want_reply = message.bool()?;
+
+        let mut channels = self.channels.lock().unwrap();
+        let Some(entry) = channels.get_mut(&id) else {
+            return Ok(());
+        };
+        let channel = entry.channel.clone();
+        let Some(session) = entry.session.as_mut().filter(|session| !session.started) else {
+            if kind == "window-change" {
+                let cols = message.u32()?;
+                let rows = message.u32()?;
+                if let Some(Sink::Pty(rdp)) = channel.sink.lock().unwrap().as_mut() {
+                    command::protocol::Input::Resize(size(cols, rows)).send(rdp)?;
+                }
+                return Ok(());
+            }
+            drop(channels);
+            return channel.reply(self.writer, want_reply, false);
+        };
+
+        match kind.as_str() {
+            "pty-req" => {
+                let term = message.utf8()?;
+                let cols = message.u32()?;
+                let rows = message.u32()?;
+                session.terminal = Some(command::protocol::Terminal {
+                    term,
+                    size: size(cols, rows),
+                });
+            }
+            "env" => {
+                let name = message.utf8()?;
+                let value = message.utf8()?;
+                if !self.accepts_env(&name) {
+                    crate::debug!("environment variable {name:?} refused");
+                    drop(channels);
+                    return channel.reply(self.writer, want_reply, false);
+                }
+                session.env.push((name, value));
+            }
+            "shell" => {
+                session.started = true;
+                return self.shell(scope, id, &channel, session, want_reply);
+            }
+            "exec" => {
+                session.started = true;
+                let command_line = message.utf8()?;
+                return self.exec(scope, id, &channel, session, command_line, want_reply);
+            }
+            "subsystem" if message.string()? == b"sftp" => {
+                session.started = true;
+                return self.sftp(scope, id, &channel, want_reply);
+            }
+            _ => {
+                drop(channels);
+                return channel.reply(self.writer, want_reply, false);
+            }
+        }
+
+        drop(channels);
+        channel.reply(self.writer, want_reply, true)
+    }
+
+    fn data(&self, id: u32, message: &mut wire::Reader<'_>) -> Result<(), io::Error> {
+        let data = message.string()?;
+
+        let channel = {
+            let mut channels = self.channels.lock().unwrap();
+            let Some(entry) = channels.get_mut(&id) else {
+                return Ok(());
+            };
+            entry.consumed += u32::try_from(data.len()).unwrap_or(u32::MAX);
+            if WINDOW_SIZE / 2 <= entry.consumed {
+                entry.channel.send(
+                    self.writer,
+                    &wire::Writer::new(MSG_CHANNEL_WINDOW_ADJUST)
+                        .u32(entry.channel.remote_id)
+                        .u32(entry.consumed)
+                        .finish(),
+                )?;
+                entry.consumed = 0;
+            }
+            entry.channel.clone()
+        };
+
+        let mut sink = channel.sink.lock().unwrap();
+        if let Some(Err(e)) = sink.as_mut().map(|sink| sink.write(data)) {
+            crate::debug!("error: {e}");
+            *sink = None;
+        }
+
+        Ok(())
+    }
+
+    // Errors on malformed messages are returned for the caller to clean
+    // up the channels
+    fn dispatch<'s>(
+        &'s self,
+        scope: &'s thread::Scope<'s, '_>,
+        next_id: &mut u32,
+        sequence: u32,
+        message: &[u8],
+    ) -> Result<(), io::Error>
+    where
+        'a: 's,
+    {
+        let Some((kind, payload)) = message.split_first() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty message"));
+        };
+        let mut payload = wire::Reader::new(payload);
+
+        match *kind {
+            MSG_GLOBAL_REQUEST => {
+                let _name = payload.string()?;
+                if payload.bool()? {
+                    self.writer.send(&[MSG_REQUEST_FAILURE])
+                } else {
+                    Ok(())
+                }
+            }
+            MSG_CHANNEL_OPEN => {
+                let id = *next_id;
+                *next_id = next_id.wrapping_add(1);
+                self.open(scope, id, &mut payload)
+            }
+            MSG_CHANNEL_WINDOW_ADJUST => {
+                let id = payload.u32()?;
+                let len = payload.u32()?;
+                if let Some(entry) = self.channels.lock().unwrap().get(&id) {
+                    let mut state = entry.channel.state.lock().unwrap();
+                    state.window = state.window.saturating_add(len);
+                    entry.channel.window_changed.notify_all();
+                }
+                Ok(())
+            }
+            MSG_CHANNEL_DATA => {
+                let id = payload.u32()?;
+                self.data(id, &mut payload)
+            }
+            MSG_CHANNEL_EOF => {
+                let id = payload.u32()?;
+                if let Some(entry) = self.channels.lock().unwrap().get(&id) {
+                    // ends the input of the backend
+                    entry.channel.sink.lock().unwrap().take();
+                }
+                Ok(())
+            }
+            MSG_CHANNEL_CLOSE => {
+                let id = payload.u32()?;
+                let entry = self.channels.lock().unwrap().remove(&id);
+                if let Some(entry) = entry {
+                    entry.channel.close(self.writer, None);
+                    entry.channel.sink.lock().unwrap().take();
+                }
+                Ok(())
+            }
+            MSG_CHANNEL_REQUEST => self.request(scope, &mut payload),
+            // no standard error from the client and no reply expected
+            MSG_CHANNEL_EXTENDED_DATA | MSG_CHANNEL_SUCCESS | MSG_CHANNEL_FAILURE => Ok(()),
+            _ => self
+                .writer
+                .send(&wire::Writer::new(MSG_UNIMPLEMENTED).u32(sequence).finish()),
+        }
+    }
+
+    fn run(&self, reader: &mut transport::Reader) -> Result<(), io::Error> {
+        thread::scope(|scope| {
+            let mut next_id = 0u32;
+
+            let res = loop {
+                let message = match reader.receive(self.writer) {
+                    Ok(message) => message,
+                    Err(e) => break Err(e),
+                };
+                if let Err(e) = self.dispatch(scope, &mut next_id, reader.sequence(), &message) {
+                    break Err(e);
+                }
+            };
+
+            // stops the threads of the channels, the backend streams
+            // end with the sinks
+            for (_, entry) in self.channels.lock().unwrap().drain() {
+                entry.channel.state.lock().unwrap().closed = true;
+                entry.channel.window_changed.notify_all();
+                entry.channel.sink.lock().unwrap().take();
+            }
+            reader.shutdown();
+
+            res
+        })
+    }
+}
+
+pub fn tcp_handler(
+    server: &frontend::FrontendTcpServer,
+    _scope: &thread::Scope,
+    client: net::TcpStream,
+    channel: &channel::Channel,
+) -> Result<(), api::Error> {
+    let settings = server.settings();
+
+    let host_key = keys::HostKey::load(settings)?;
+    let (mut reader, writer) = transport::handshake(client, host_key)?;
+
+    let Some(user) = authenticate(&mut reader, &writer, settings)? else {
+        return Ok(());
+    };
+
+    crate::info!("{user:?} authenticated");
+
+    let connection = Connection {
+        writer: &writer,
+        channel,
+        settings,
+        channels: Mutex::new(HashMap::new()),
+    };
+
+    if let Err(e) = connection.run(&mut reader) {
+        crate::debug!("error: {e}");
+    }
+
+    writer.disconnect(transport::DISCONNECT_BY_APPLICATION, "bye");
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+#[cfg(feature = "frontend")]
+use crate::frontend as sfrontend;
+use crate::service;
+
+#[cfg(feature = "frontend")]
+mod frontend;
+#[cfg(feature = "frontend")]
+mod keys;
+#[cfg(feature = "frontend")]
+mod sftp;
+#[cfg(feature = "frontend")]
+mod transport;
+#[cfg(feature = "frontend")]
+mod wire;
+
+// Sessions are run by the backend of the command service, forwarded
+// connections by the one of the forward service and SFTP by the one of
+// the ftp service, so this service has no backend on its own.
+pub static SERVICE: service::Service = service::Service {
+    internal: false,
+    name: "ssh",
+    #[cfg(feature = "frontend")]
+    frontend: Some(sfrontend::Frontend {
+        tcp: Some(sfrontend::FrontendTcp {
+            default_port: 2222,
+            handler: frontend::tcp_handler,
+            background: None,
+        }),
+        udp: None,
+    }),
+    #[cfg(feature = "backend")]
+    backend: None,
+};
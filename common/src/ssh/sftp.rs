@@ -0,0 +1,818 @@
+use super::wire;
+use crate::ftp::{self, protocol};
+use crate::{api, channel, rdp, util};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    time,
+};
+
+// SFTP version 3 (draft-ietf-secsh-filexfer-02) served by the backend
+// of the ftp service: requests are translated into FTP commands sent
+// on a control stream, file contents and listings go through data
+// streams
+
+const VERSION: u32 = 3;
+
+const MAX_PACKET_LEN: usize = 1024 * 1024;
+
+const MAX_READ_LEN: usize = 64 * 1024;
+
+const READDIR_BATCH: usize = 100;
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_LSTAT: u8 = 7;
+const FXP_FSTAT: u8 = 8;
+const FXP_SETSTAT: u8 = 9;
+const FXP_FSETSTAT: u8 = 10;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_MKDIR: u8 = 14;
+const FXP_RMDIR: u8 = 15;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_RENAME: u8 = 18;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_FAILURE: u32 = 4;
+const FX_OP_UNSUPPORTED: u32 = 8;
+
+const FXF_WRITE: u32 = 0x02;
+const FXF_APPEND: u32 = 0x04;
+const FXF_CREAT: u32 = 0x08;
+const FXF_TRUNC: u32 = 0x10;
+const FXF_EXCL: u32 = 0x20;
+
+const ATTR_SIZE: u32 = 0x01;
+const ATTR_UIDGID: u32 = 0x02;
+const ATTR_PERMISSIONS: u32 = 0x04;
+const ATTR_ACMODTIME: u32 = 0x08;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+const MODE_DIR: u32 = 0o040_000;
+const MODE_FILE: u32 = 0o100_000;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// Listings show the year instead of the time for older entries
+const RECENT_SECS: i64 = 182 * 24 * 3600;
+
+// "YYYYMMDDHHMMSS" in UTC, as in MLST facts and MFMT commands
+fn parse_time_val(s: &str) -> Option<u32> {
+    let s = s.split_once('.').map_or(s, |(s, _)| s);
+    if s.len() != 14 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| s[range].parse::<i64>().ok();
+    let secs = util::unix_from_civil([
+        field(0..4)?,
+        field(4..6)?,
+        field(6..8)?,
+        field(8..10)?,
+        field(10..12)?,
+        field(12..14)?,
+    ]);
+    u32::try_from(secs).ok()
+}
+
+fn format_time_val(secs: u32) -> String {
+    let [year, month, day, hour, minute, second] = util::civil_from_unix(i64::from(secs));
+    format!("{year:04}{month:02}{day:02}{hour:02}{minute:02}{second:02}")
+}
+
+#[derive(Debug, Default)]
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    mtime: Option<u32>,
+}
+
+impl Attrs {
+    fn read(message: &mut wire::Reader<'_>) -> Result<Self, io::Error> {
+        let flags = message.u32()?;
+        let mut attrs = Self::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(message.u64()?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            message.u32()?;
+            message.u32()?;
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(message.u32()?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            message.u32()?;
+            attrs.mtime = Some(message.u32()?);
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..message.u32()? {
+                message.string()?;
+                message.string()?;
+            }
+        }
+        Ok(attrs)
+    }
+
+    fn write(&self, message: &mut wire::Writer) {
+        let flags = self.size.map_or(0, |_| ATTR_SIZE)
+            | self.permissions.map_or(0, |_| ATTR_PERMISSIONS)
+            | self.mtime.map_or(0, |_| ATTR_ACMODTIME);
+        message.u32(flags);
+        if let Some(size) = self.size {
+            message.u64(size);
+        }
+        if let Some(permissions) = self.permissions {
+            message.u32(permissions);
+        }
+        if let Some(mtime) = self.mtime {
+            message.u32(mtime).u32(mtime);
+        }
+    }
+
+    // From the facts of a MLST or MLSD entry
+    fn from_facts(facts: &str) -> Self {
+        let (mut is_dir, mut writable) = (false, false);
+        let mut attrs = Self::default();
+        for fact in facts.split(';') {
+            match fact.split_once('=') {
+                Some(("type", kind)) => is_dir = kind.ends_with("dir"),
+                Some(("size", size)) => attrs.size = size.parse().ok(),
+                Some(("modify", time_val)) => attrs.mtime = parse_time_val(time_val),
+                Some(("perm", perm)) => writable = perm.contains(['c', 'w']),
+                _ => (),
+            }
+        }
+        attrs.permissions = Some(match (is_dir, writable) {
+            (true, true) => MODE_DIR | 0o755,
+            (true, false) => MODE_DIR | 0o555,
+            (false, true) => MODE_FILE | 0o644,
+            (false, false) => MODE_FILE | 0o444,
+        });
+        attrs
+    }
+
+    fn is_dir(&self) -> bool {
+        self.permissions
+            .is_some_and(|permissions| permissions & MODE_DIR != 0)
+    }
+
+    // An "ls -l" like line
+    fn longname(&self, name: &str) -> String {
+        let mode = self.permissions.unwrap_or_default();
+        let kind = if self.is_dir() { 'd' } else { '-' };
+        let permissions: String = "rwxrwxrwx"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if mode & (0o400 >> i) == 0 { '-' } else { c })
+            .collect();
+
+        let mtime = i64::from(self.mtime.unwrap_or_default());
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |now| i64::try_from(now.as_secs()).unwrap_or(i64::MAX));
+        let [year, month, day, hour, minute, _] = util::civil_from_unix(mtime);
+        let month = usize::try_from(month - 1).map_or("Jan", |month| MONTHS[month]);
+        let date = if (now - mtime).abs() < RECENT_SECS {
+            format!("{month} {day:>2} {hour:02}:{minute:02}")
+        } else {
+            format!("{month} {day:>2}  {year}")
+        };
+
+        format!(
+            "{kind}{permissions}   1 owner    group    {:>12} {date} {name}",
+            self.size.unwrap_or_default()
+        )
+    }
+}
+
+// Path of a 257 reply to PWD
+fn unquote(message: &str) -> Option<String> {
+    let start = message.find('"')?;
+    let end = message.rfind('"')?;
+    (start < end).then(|| message[start + 1..end].replace("\"\"", "\""))
+}
+
+enum Handle<'a> {
+    Dir(Vec<(String, Attrs)>),
+    File {
+        path: String,
+        append: bool,
+        // the next offset and the stream of the transfer in progress
+        download: Option<(u64, rdp::RdpStream<'a>)>,
+        upload: Option<(u64, rdp::RdpStream<'a>)>,
+    },
+}
+
+// Waits for the backend to write everything
+fn finish_upload(upload: Option<(u64, rdp::RdpStream<'_>)>) {
+    if let Some((_, stream)) = upload {
+        let (mut reader, writer) = stream.split();
+        drop(writer);
+        let _ = io::copy(&mut reader, &mut io::sink());
+    }
+}
+
+fn status(id: u32, code: u32) -> Vec<u8> {
+    let message = match code {
+        FX_OK => "Success",
+        FX_EOF => "End of file",
+        FX_NO_SUCH_FILE => "No such file",
+        FX_OP_UNSUPPORTED => "Operation unsupported",
+        _ => "Failure",
+    };
+    wire::Writer::new(FXP_STATUS)
+        .u32(id)
+        .u32(code)
+        .string(message.as_bytes())
+        .string(b"")
+        .finish()
+}
+
+// The status of a request modifying the filesystem
+fn attrs_reply(id: u32, attrs: Option<Attrs>) -> Vec<u8> {
+    attrs.map_or_else(
+        || status(id, FX_NO_SUCH_FILE),
+        |attrs| {
+            let mut reply = wire::Writer::new(FXP_ATTRS);
+            reply.u32(id);
+            attrs.write(&mut reply);
+            reply.finish()
+        },
+    )
+}
+
+fn done(id: u32, response: &protocol::ControlResponse) -> Vec<u8> {
+    if matches!(response, protocol::ControlResponse::Ok(_, _)) {
+        status(id, FX_OK)
+    } else {
+        status(id, FX_FAILURE)
+    }
+}
+
+struct Session<'a> {
+    channel: &'a channel::Channel,
+    scope: protocol::Scope,
+    control: rdp::RdpStream<'a>,
+    home: String,
+    handles: HashMap<u32, Handle<'a>>,
+    next_handle: u32,
+}
+
+impl<'a> Session<'a> {
+    fn control(
+        &mut self,
+        command: &protocol::ControlCommand,
+    ) -> Result<protocol::ControlResponse, api::Error> {
+        command.send(&mut self.control)?;
+        protocol::ControlResponse::receive(&mut self.control)
+    }
+
+    fn data(&self, command: &protocol::DataCommand) -> Result<rdp::RdpStream<'a>, api::Error> {
+        let mut stream = self.channel.connect(&ftp::SERVICE)?;
+        protocol::BackendMode::Data.send(&mut stream)?;
+        self.scope.send(&mut stream)?;
+        command.send(&mut stream)?;
+        Ok(stream)
+    }
+
+    // Absolute and normalized, relative paths start from the home
+    // directory
+    fn resolve(&self, path: &str) -> String {
+        let base = if path.starts_with('/') {
+            ""
+        } else {
+            &self.home
+        };
+        let mut components = vec![];
+        for component in base.split('/').chain(path.split('/')) {
+            match component {
+                "" | "." => (),
+                ".." => {
+                    components.pop();
+                }
+                name => components.push(name),
+            }
+        }
+        format!("/{}", components.join("/"))
+    }
+
+    fn stat(&mut self, path: &str) -> Result<Option<Attrs>, api::Error> {
+        match self.control(&protocol::ControlCommand::Mlst(path.to_string()))? {
+            protocol::ControlResponse::Mlst(entry) => {
+                let facts = entry
+                    .split_once(' ')
+                    .map_or(entry.as_str(), |(facts, _)| facts);
+                Ok(Some(Attrs::from_facts(facts)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Starts a download at the given offset, fails with the FTP reply
+    // code
+    fn download(
+        &mut self,
+        path: &str,
+        offset: u64,
+    ) -> Result<Result<rdp::RdpStream<'a>, u16>, api::Error> {
+        let response = match self.control(&protocol::ControlCommand::Rest(offset.to_string()))? {
+            protocol::ControlResponse::Ok(_, _) => {
+                self.control(&protocol::ControlCommand::Retr(path.to_string()))?
+            }
+            response => response,
+        };
+        match response {
+            protocol::ControlResponse::Data(command) => Ok(Ok(self.data(&command)?)),
+            protocol::ControlResponse::Error(code) => Ok(Err(code)),
+            _ => Ok(Err(0)),
+        }
+    }
+
+    // Starts an upload at the given offset, the backend drops what
+    // follows it in the file
+    fn upload(
+        &mut self,
+        path: &str,
+        offset: u64,
+        append: bool,
+    ) -> Result<Option<rdp::RdpStream<'a>>, api::Error> {
+        let response = if append {
+            self.control(&protocol::ControlCommand::Appe(path.to_string()))?
+        } else {
+            match self.control(&protocol::ControlCommand::Rest(offset.to_string()))? {
+                protocol::ControlResponse::Ok(_, _) => {
+                    self.control(&protocol::ControlCommand::Stor(path.to_string()))?
+                }
+                response => response,
+            }
+        };
+        match response {
+            protocol::ControlResponse::Data(command) => Ok(Some(self.data(&command)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn new_handle(&mut self, id: u32, handle: Handle<'a>) -> Vec<u8> {
+        let value = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        self.handles.insert(value, handle);
+        wire::Writer::new(FXP_HANDLE)
+            .u32(id)
+            .string(&value.to_be_bytes())
+            .finish()
+    }
+
+    fn open(&mut self, id: u32, path: &str, flags: u32) -> Result<Vec<u8>, api::Error> {
+        let path = self.resolve(path);
+        let attrs = self.stat(&path)?;
+
+        if attrs.as_ref().is_some_and(Attrs::is_dir) {
+            return Ok(status(id, FX_FAILURE));
+        }
+
+        let append = flags & FXF_APPEND != 0;
+        let mut upload = None;
+
+        if flags & FXF_WRITE == 0 {
+            if attrs.is_none() {
+                return Ok(status(id, FX_NO_SUCH_FILE));
+            }
+        } else {
+            match attrs {
+                Some(_) if flags & (FXF_CREAT | FXF_EXCL) == FXF_CREAT | FXF_EXCL => {
+                    return Ok(status(id, FX_FAILURE));
+                }
+                None if flags & FXF_CREAT == 0 => return Ok(status(id, FX_NO_SUCH_FILE)),
+                _ => (),
+            }
+            // creates or truncates the file right away
+            if flags & FXF_TRUNC != 0 || attrs.is_none() {
+                let Some(stream) = self.upload(&path, 0, append)? else {
+                    return Ok(status(id, FX_FAILURE));
+                };
+                upload = Some((0, stream));
+            }
+        }
+
+        Ok(self.new_handle(
+            id,
+            Handle::File {
+                path,
+                append,
+                download: None,
+                upload,
+            },
+        ))
+    }
+
+    fn read(
+        &mut self,
+        id: u32,
+        handle: &mut Handle<'a>,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, api::Error> {
+        let Handle::File {
+            path,
+            download,
+            upload,
+            ..
+        } = handle
+        else {
+            return Ok(status(id, FX_FAILURE));
+        };
+
+        // a new transfer is started unless reading right after the
+        // previous read
+        if download.as_ref().is_none_or(|(next, _)| *next != offset) {
+            *download = None;
+            finish_upload(upload.take());
+            match self.download(path, offset)? {
+                Ok(stream) => *download = Some((offset, stream)),
+                Err(554) => return Ok(status(id, FX_EOF)),
+                Err(_) => return Ok(status(id, FX_FAILURE)),
+            }
+        }
+
+        let Some((next, stream)) = download.as_mut() else {
+            return Ok(status(id, FX_FAILURE));
+        };
+
+        let mut buf = vec![0u8; len.min(MAX_READ_LEN)];
+        let mut read = 0;
+        while read < buf.len() {
+            match stream.read(&mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+
+        if read < buf.len() {
+            // the end of the file was reached, the stream is closed
+            *download = None;
+        } else {
+            *next += read as u64;
+        }
+
+        if read == 0 {
+            return Ok(status(id, FX_EOF));
+        }
+
+        buf.truncate(read);
+        Ok(wire::Writer::new(FXP_DATA).u32(id).string(&buf).finish())
+    }
+
+    fn write(
+        &mut self,
+        id: u32,
+        handle: &mut Handle<'a>,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<Vec<u8>, api::Error> {
+        let Handle::File {
+            path,
+            append,
+            download,
+            upload,
+        } = handle
+        else {
+            return Ok(status(id, FX_FAILURE));
+        };
+
+        // a new transfer is started unless writing right after the
+        // previous write, or appending
+        if upload
+            .as_ref()
+            .is_none_or(|(next, _)| !*append && *next != offset)
+        {
+            *download = None;
+            finish_upload(upload.take());
+            let Some(stream) = self.upload(path, offset, *append)? else {
+                return Ok(status(id, FX_FAILURE));
+            };
+            *upload = Some((offset, stream));
+        }
+
+        let Some((next, stream)) = upload.as_mut() else {
+            return Ok(status(id, FX_FAILURE));
+        };
+
+        if stream.write_all(data).is_err() {
+            *upload = None;
+            return Ok(status(id, FX_FAILURE));
+        }
+        *next += data.len() as u64;
+
+        Ok(status(id, FX_OK))
+    }
+
+    fn opendir(&mut self, id: u32, path: &str) -> Result<Vec<u8>, api::Error> {
+        let path = self.resolve(path);
+        let protocol::ControlResponse::Data(command) =
+            self.control(&protocol::ControlCommand::Mlsd(path))?
+        else {
+            return Ok(status(id, FX_NO_SUCH_FILE));
+        };
+
+        let mut listing = String::new();
+        self.data(&command)?.read_to_string(&mut listing)?;
+
+        let entries = listing
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(facts, name)| (name.to_string(), Attrs::from_facts(facts)))
+            .collect();
+
+        Ok(self.new_handle(id, Handle::Dir(entries)))
+    }
+
+    fn setstat(&mut self, id: u32, path: &str, attrs: &Attrs) -> Result<Vec<u8>, api::Error> {
+        // only the modification time is supported
+        let Some(mtime) = attrs.mtime else {
+            return Ok(status(id, FX_OK));
+        };
+        let response = self.control(&protocol::ControlCommand::Mfmt(format!(
+            "{} {path}",
+            format_time_val(mtime)
+        )))?;
+        Ok(done(id, &response))
+    }
+
+    fn name(id: u32, entries: &[(String, String, Attrs)]) -> Vec<u8> {
+        let mut message = wire::Writer::new(FXP_NAME);
+        message
+            .u32(id)
+            .u32(u32::try_from(entries.len()).unwrap_or_default());
+        for (name, longname, attrs) in entries {
+            message.string(name.as_bytes()).string(longname.as_bytes());
+            attrs.write(&mut message);
+        }
+        message.finish()
+    }
+
+    // Requests on handles take them out of the table while they are
+    // processed
+    fn with_handle<F>(&mut self, id: u32, handle: &[u8], f: F) -> Result<Vec<u8>, api::Error>
+    where
+        F: FnOnce(&mut Self, &mut Handle<'a>) -> Result<Vec<u8>, api::Error>,
+    {
+        let Some(mut value) = <[u8; 4]>::try_from(handle)
+            .ok()
+            .map(u32::from_be_bytes)
+            .and_then(|key| Some((key, self.handles.remove(&key)?)))
+        else {
+            return Ok(status(id, FX_FAILURE));
+        };
+        let res = f(self, &mut value.1);
+        self.handles.insert(value.0, value.1);
+        res
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn request(
+        &mut self,
+        kind: u8,
+        id: u32,
+        message: &mut wire::Reader<'_>,
+    ) -> Result<Vec<u8>, api::Error> {
+        match kind {
+            FXP_OPEN => {
+                let path = message.utf8()?;
+                let flags = message.u32()?;
+                self.open(id, &path, flags)
+            }
+            FXP_CLOSE => {
+                let handle = message.string()?;
+                let key = <[u8; 4]>::try_from(handle).ok().map(u32::from_be_bytes);
+                match key.and_then(|key| self.handles.remove(&key)) {
+                    None => Ok(status(id, FX_FAILURE)),
+                    Some(Handle::File { upload, .. }) => {
+                        finish_upload(upload);
+                        Ok(status(id, FX_OK))
+                    }
+                    Some(Handle::Dir(_)) => Ok(status(id, FX_OK)),
+                }
+            }
+            FXP_READ => {
+                let handle = message.string()?;
+                let offset = message.u64()?;
+                let len = usize::try_from(message.u32()?).unwrap_or(MAX_READ_LEN);
+                self.with_handle(id, handle, |session, handle| {
+                    session.read(id, handle, offset, len)
+                })
+            }
+            FXP_WRITE => {
+                let handle = message.string()?;
+                let offset = message.u64()?;
+                let data = message.string()?;
+                self.with_handle(id, handle, |session, handle| {
+                    session.write(id, handle, offset, data)
+                })
+            }
+            FXP_STAT | FXP_LSTAT => {
+                let path = self.resolve(&message.utf8()?);
+                Ok(attrs_reply(id, self.stat(&path)?))
+            }
+            FXP_FSTAT => {
+                let handle = message.string()?;
+                self.with_handle(id, handle, |session, handle| {
+                    let Handle::File { path, upload, .. } = handle else {
+                        return Ok(status(id, FX_FAILURE));
+                    };
+                    finish_upload(upload.take());
+                    Ok(attrs_reply(id, session.stat(path)?))
+                })
+            }
+            FXP_SETSTAT => {
+                let path = self.resolve(&message.utf8()?);
+                let attrs = Attrs::read(message)?;
+                self.setstat(id, &path, &attrs)
+            }
+            FXP_FSETSTAT => {
+                let handle = message.string()?;
+                let attrs = Attrs::read(message)?;
+                self.with_handle(id, handle, |session, handle| {
+                    let Handle::File { path, upload, .. } = handle else {
+                        return Ok(status(id, FX_FAILURE));
+                    };
+                    // later writes would change the modification time
+                    finish_upload(upload.take());
+                    session.setstat(id, &path.clone(), &attrs)
+                })
+            }
+            FXP_OPENDIR => {
+                let path = message.utf8()?;
+                self.opendir(id, &path)
+            }
+            FXP_READDIR => {
+                let handle = message.string()?;
+                self.with_handle(id, handle, |_, handle| {
+                    let Handle::Dir(entries) = handle else {
+                        return Ok(status(id, FX_FAILURE));
+                    };
+                    if entries.is_empty() {
+                        return Ok(status(id, FX_EOF));
+                    }
+                    let batch: Vec<_> = entries
+                        .drain(..entries.len().min(READDIR_BATCH))
+                        .map(|(name, attrs)| (name.clone(), attrs.longname(&name), attrs))
+                        .collect();
+                    Ok(Self::name(id, &batch))
+                })
+            }
+            FXP_REMOVE => {
+                let path = self.resolve(&message.utf8()?);
+                let response = self.control(&protocol::ControlCommand::Dele(path))?;
+                Ok(done(id, &response))
+            }
+            FXP_MKDIR => {
+                let path = self.resolve(&message.utf8()?);
+                let response = self.control(&protocol::ControlCommand::Mkd(path))?;
+                Ok(done(id, &response))
+            }
+            FXP_RMDIR => {
+                let path = self.resolve(&message.utf8()?);
+                let response = self.control(&protocol::ControlCommand::Rmd(path))?;
+                Ok(done(id, &response))
+            }
+            FXP_REALPATH => {
+                let path = self.resolve(&message.utf8()?);
+                Ok(Self::name(id, &[(path.clone(), path, Attrs::default())]))
+            }
+            FXP_RENAME => {
+                let from = self.resolve(&message.utf8()?);
+                let to = self.resolve(&message.utf8()?);
+                let response = match self.control(&protocol::ControlCommand::Rnfr(from))? {
+                    protocol::ControlResponse::Ok(_, _) => {
+                        self.control(&protocol::ControlCommand::Rnto(to))?
+                    }
+                    _ => return Ok(status(id, FX_NO_SUCH_FILE)),
+                };
+                Ok(done(id, &response))
+            }
+            // links and extensions
+            _ => Ok(status(id, FX_OP_UNSUPPORTED)),
+        }
+    }
+}
+
+// Data received on the SSH channel
+struct Input {
+    receiver: crossbeam_channel::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl io::Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        while self.offset == self.pending.len() {
+            let Ok(data) = self.receiver.recv() else {
+                return Ok(0);
+            };
+            self.pending = data;
+            self.offset = 0;
+        }
+        let len = buf.len().min(self.pending.len() - self.offset);
+        buf[..len].copy_from_slice(&self.pending[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+fn read_packet<R>(input: &mut R) -> Result<Option<Vec<u8>>, io::Error>
+where
+    R: io::Read,
+{
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+        Ok(()) => (),
+    }
+    let len = usize::try_from(u32::from_be_bytes(len))
+        .ok()
+        .filter(|len| (1..=MAX_PACKET_LEN).contains(len))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid SFTP packet"))?;
+    let mut packet = vec![0u8; len];
+    input.read_exact(&mut packet)?;
+    Ok(Some(packet))
+}
+
+// Serves the requests received until the client closes the channel,
+// the replies are given to the output function
+pub fn serve<O>(
+    channel: &channel::Channel,
+    scope: protocol::Scope,
+    receiver: crossbeam_channel::Receiver<Vec<u8>>,
+    mut output: O,
+) -> Result<(), api::Error>
+where
+    O: FnMut(&[u8]) -> Result<(), io::Error>,
+{
+    let mut input = Input {
+        receiver,
+        pending: vec![],
+        offset: 0,
+    };
+
+    let mut control = channel.connect(&ftp::SERVICE)?;
+    protocol::BackendMode::Control.send(&mut control)?;
+    scope.send(&mut control)?;
+
+    let mut session = Session {
+        channel,
+        scope,
+        control,
+        home: String::new(),
+        handles: HashMap::new(),
+        next_handle: 0,
+    };
+
+    session.home = match session.control(&protocol::ControlCommand::Pwd)? {
+        protocol::ControlResponse::Ok(_, Some(message)) => unquote(&message),
+        _ => None,
+    }
+    .unwrap_or_else(|| "/".to_string());
+
+    while let Some(packet) = read_packet(&mut input)? {
+        let mut message = wire::Reader::new(&packet);
+        let kind = message.u8()?;
+
+        let reply = if kind == FXP_INIT {
+            wire::Writer::new(FXP_VERSION).u32(VERSION).finish()
+        } else {
+            let id = message.u32()?;
+            session.request(kind, id, &mut message)?
+        };
+
+        let len = u32::try_from(reply.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        output(&[&len.to_be_bytes(), reply.as_slice()].concat())?;
+    }
+
+    for handle in std::mem::take(&mut session.handles).into_values() {
+        if let Handle::File { upload, .. } = handle {
+            finish_upload(upload);
+        }
+    }
+
+    let _ = session.control(&protocol::ControlCommand::Quit);
+
+    Ok(())
+}
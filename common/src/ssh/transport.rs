@@ -0,0 +1,948 @@
+use super::{keys, wire};
+use ring::{
+    aead::{self, chacha20_poly1305_openssh},
+    agreement, digest,
+    rand::{self, SecureRandom},
+};
+use std::{
+    io::{self, BufRead, Read, Write},
+    net,
+    sync::{Condvar, Mutex},
+};
+
+// SSH transport layer (RFC 4253) restricted to curve25519-sha256
+// key exchanges (RFC 8731), ssh-ed25519 host keys and AEAD ciphers
+
+const VERSION: &str = concat!("SSH-2.0-soxy_", env!("CARGO_PKG_VERSION"));
+
+const MAX_VERSION_LEN: u64 = 255;
+
+// OpenSSH sends packets up to 256 KiB
+const MAX_PACKET_LEN: usize = 256 * 1024 + 64;
+
+const MIN_PADDING_LEN: usize = 4;
+
+const TAG_LEN: usize = 16;
+
+const MSG_DISCONNECT: u8 = 1;
+const MSG_IGNORE: u8 = 2;
+const MSG_UNIMPLEMENTED: u8 = 3;
+const MSG_DEBUG: u8 = 4;
+const MSG_EXT_INFO: u8 = 7;
+const MSG_KEXINIT: u8 = 20;
+const MSG_NEWKEYS: u8 = 21;
+const MSG_KEX_ECDH_INIT: u8 = 30;
+const MSG_KEX_ECDH_REPLY: u8 = 31;
+
+pub const DISCONNECT_PROTOCOL_ERROR: u32 = 2;
+pub const DISCONNECT_SERVICE_NOT_AVAILABLE: u32 = 7;
+pub const DISCONNECT_BY_APPLICATION: u32 = 11;
+pub const DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE: u32 = 14;
+
+const KEX_ALGORITHMS: &[&str] = &["curve25519-sha256", "curve25519-sha256@libssh.org"];
+
+// Strict key exchange mode of OpenSSH, against prefix truncation
+// attacks (CVE-2023-48795)
+const KEX_STRICT_CLIENT: &str = "kex-strict-c-v00@openssh.com";
+const KEX_STRICT_SERVER: &str = "kex-strict-s-v00@openssh.com";
+
+// The client accepts SSH_MSG_EXT_INFO (RFC 8308)
+const EXT_INFO_CLIENT: &str = "ext-info-c";
+
+const CHACHA20_POLY1305: &str = "chacha20-poly1305@openssh.com";
+const AES256_GCM: &str = "aes256-gcm@openssh.com";
+const AES128_GCM: &str = "aes128-gcm@openssh.com";
+
+const CIPHERS: &[&str] = &[CHACHA20_POLY1305, AES256_GCM, AES128_GCM];
+
+// Only offered for the negotiation to succeed, AEAD ciphers do not use
+// any MAC
+const MACS: &[&str] = &["hmac-sha2-256"];
+
+const COMPRESSION: &str = "none";
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed packet")
+}
+
+fn kex_failed<E>(e: &E) -> io::Error
+where
+    E: ToString + ?Sized,
+{
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("key exchange failed: {}", e.to_string()),
+    )
+}
+
+fn disconnected(payload: &[u8]) -> io::Error {
+    let mut message = wire::Reader::new(&payload[1..]);
+    let description = message
+        .u32()
+        .and_then(|_| message.utf8())
+        .unwrap_or_default();
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        format!("disconnected by the client: {description}"),
+    )
+}
+
+// The length of the key, then of the IV
+fn key_lengths(cipher: &str) -> (usize, usize) {
+    match cipher {
+        CHACHA20_POLY1305 => (chacha20_poly1305_openssh::KEY_LEN, 0),
+        AES256_GCM => (32, aead::NONCE_LEN),
+        _ => (16, aead::NONCE_LEN),
+    }
+}
+
+fn gcm_key(cipher: &str, key: &[u8]) -> Result<aead::LessSafeKey, io::Error> {
+    let algorithm = if cipher == AES256_GCM {
+        &aead::AES_256_GCM
+    } else {
+        &aead::AES_128_GCM
+    };
+    aead::UnboundKey::new(algorithm, key)
+        .map(aead::LessSafeKey::new)
+        .map_err(|e| kex_failed(&e))
+}
+
+// The IV is made of a fixed part and of a 64-bit counter incremented
+// for each packet (RFC 5647)
+fn next_nonce(iv: &mut [u8; aead::NONCE_LEN]) -> aead::Nonce {
+    let nonce = aead::Nonce::assume_unique_for_key(*iv);
+    let mut counter = [0u8; 8];
+    counter.copy_from_slice(&iv[4..]);
+    iv[4..].copy_from_slice(&u64::from_be_bytes(counter).wrapping_add(1).to_be_bytes());
+    nonce
+}
+
+enum Sealing {
+    None,
+    ChaCha20Poly1305(Box<chacha20_poly1305_openssh::SealingKey>),
+    AesGcm(Box<aead::LessSafeKey>, [u8; aead::NONCE_LEN]),
+}
+
+impl Sealing {
+    fn new(cipher: &str, key: &[u8], iv: &[u8]) -> Result<Self, io::Error> {
+        if cipher == CHACHA20_POLY1305 {
+            let key = key.try_into().map_err(|e| kex_failed(&e))?;
+            Ok(Self::ChaCha20Poly1305(Box::new(
+                chacha20_poly1305_openssh::SealingKey::new(key),
+            )))
+        } else {
+            Ok(Self::AesGcm(
+                Box::new(gcm_key(cipher, key)?),
+                iv.try_into().map_err(|e| kex_failed(&e))?,
+            ))
+        }
+    }
+
+    fn seal(
+        &mut self,
+        sequence: u32,
+        payload: &[u8],
+        rng: &rand::SystemRandom,
+    ) -> Result<Vec<u8>, io::Error> {
+        // with AEAD ciphers, the length field is not part of the
+        // aligned data
+        let (block_len, aligned_len) = match self {
+            Self::None => (8, 4 + 1 + payload.len()),
+            Self::ChaCha20Poly1305(_) => (8, 1 + payload.len()),
+            Self::AesGcm(_, _) => (16, 1 + payload.len()),
+        };
+        let mut padding_len = block_len - aligned_len % block_len;
+        if padding_len < MIN_PADDING_LEN {
+            padding_len += block_len;
+        }
+
+        let packet_len = u32::try_from(1 + payload.len() + padding_len).map_err(|_| malformed())?;
+        let mut packet = Vec::with_capacity(4 + 1 + payload.len() + padding_len + TAG_LEN);
+        packet.extend_from_slice(&packet_len.to_be_bytes());
+        packet.push(u8::try_from(padding_len).map_err(|_| malformed())?);
+        packet.extend_from_slice(payload);
+        let padding_start = packet.len();
+        packet.resize(padding_start + padding_len, 0);
+        rng.fill(&mut packet[padding_start..])
+            .map_err(|e| io::Error::other(e.to_string()))?;
+
+        match self {
+            Self::None => (),
+            Self::ChaCha20Poly1305(key) => {
+                let mut tag = [0u8; TAG_LEN];
+                key.seal_in_place(sequence, &mut packet, &mut tag);
+                packet.extend_from_slice(&tag);
+            }
+            Self::AesGcm(key, iv) => {
+                let tag = key
+                    .seal_in_place_separate_tag(
+                        next_nonce(iv),
+                        aead::Aad::from(packet_len.to_be_bytes()),
+                        &mut packet[4..],
+                    )
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                packet.extend_from_slice(tag.as_ref());
+            }
+        }
+
+        Ok(packet)
+    }
+}
+
+enum Opening {
+    None,
+    ChaCha20Poly1305(Box<chacha20_poly1305_openssh::OpeningKey>),
+    AesGcm(Box<aead::LessSafeKey>, [u8; aead::NONCE_LEN]),
+}
+
+impl Opening {
+    fn new(cipher: &str, key: &[u8], iv: &[u8]) -> Result<Self, io::Error> {
+        if cipher == CHACHA20_POLY1305 {
+            let key = key.try_into().map_err(|e| kex_failed(&e))?;
+            Ok(Self::ChaCha20Poly1305(Box::new(
+                chacha20_poly1305_openssh::OpeningKey::new(key),
+            )))
+        } else {
+            Ok(Self::AesGcm(
+                Box::new(gcm_key(cipher, key)?),
+                iv.try_into().map_err(|e| kex_failed(&e))?,
+            ))
+        }
+    }
+
+    // Reads a packet and returns its payload
+    fn open<R>(&mut self, stream: &mut R, sequence: u32) -> Result<Vec<u8>, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len)?;
+
+        let packet_len = match self {
+            Self::ChaCha20Poly1305(key) => key.decrypt_packet_length(sequence, len),
+            Self::None | Self::AesGcm(_, _) => len,
+        };
+        let packet_len =
+            usize::try_from(u32::from_be_bytes(packet_len)).map_err(|_| malformed())?;
+        if !(1 + MIN_PADDING_LEN..=MAX_PACKET_LEN).contains(&packet_len) {
+            return Err(malformed());
+        }
+
+        let tag_len = if matches!(self, Self::None) {
+            0
+        } else {
+            TAG_LEN
+        };
+        let mut packet = vec![0u8; 4 + packet_len + tag_len];
+        packet[..4].copy_from_slice(&len);
+        stream.read_exact(&mut packet[4..])?;
+
+        let corrupted = |_| io::Error::new(io::ErrorKind::InvalidData, "corrupted packet");
+
+        let plaintext: &[u8] = match self {
+            Self::None => &packet[4..],
+            Self::ChaCha20Poly1305(key) => {
+                let (packet, tag) = packet.split_at_mut(4 + packet_len);
+                let tag = (&*tag).try_into().map_err(|_| malformed())?;
+                key.open_in_place(sequence, packet, tag)
+                    .map_err(corrupted)?
+            }
+            Self::AesGcm(key, iv) => key
+                .open_in_place(next_nonce(iv), aead::Aad::from(len), &mut packet[4..])
+                .map_err(corrupted)?,
+        };
+
+        let padding_len = usize::from(plaintext[0]);
+        if plaintext.len() < 1 + padding_len {
+            return Err(malformed());
+        }
+
+        Ok(plaintext[1..plaintext.len() - padding_len].to_vec())
+    }
+}
+
+struct Algorithms {
+    cipher_client_to_server: &'static str,
+    cipher_server_to_client: &'static str,
+    strict: bool,
+    ext_info: bool,
+    // the client sent a key exchange packet for an algorithm which was
+    // not chosen, it must be ignored
+    wrong_guess: bool,
+}
+
+fn choose(client: &[String], server: &[&'static str]) -> Result<&'static str, io::Error> {
+    client
+        .iter()
+        .find_map(|name| server.iter().find(|s| **s == name).copied())
+        .ok_or_else(|| kex_failed(&format!("no common algorithm in {client:?}")))
+}
+
+impl Algorithms {
+    fn negotiate(client_kexinit: &[u8]) -> Result<Self, io::Error> {
+        let mut message = wire::Reader::new(&client_kexinit[1..]);
+        message.bytes(16)?;
+        let kex = message.name_list()?;
+        let host_key = message.name_list()?;
+        let cipher_client_to_server = message.name_list()?;
+        let cipher_server_to_client = message.name_list()?;
+        let _mac_client_to_server = message.name_list()?;
+        let _mac_server_to_client = message.name_list()?;
+        let compression_client_to_server = message.name_list()?;
+        let compression_server_to_client = message.name_list()?;
+        let _language_client_to_server = message.name_list()?;
+        let _language_server_to_client = message.name_list()?;
+        let first_kex_packet_follows = message.bool()?;
+
+        let kex_algorithm = choose(&kex, KEX_ALGORITHMS)?;
+        let host_key_algorithm = choose(&host_key, &[keys::HOST_KEY_ALGORITHM])?;
+        choose(&compression_client_to_server, &[COMPRESSION])?;
+        choose(&compression_server_to_client, &[COMPRESSION])?;
+
+        Ok(Self {
+            cipher_client_to_server: choose(&cipher_client_to_server, CIPHERS)?,
+            cipher_server_to_client: choose(&cipher_server_to_client, CIPHERS)?,
+            strict: kex.iter().any(|name| name == KEX_STRICT_CLIENT),
+            ext_info: kex.iter().any(|name| name == EXT_INFO_CLIENT),
+            wrong_guess: first_kex_packet_follows
+                && (kex.first().map(String::as_str) != Some(kex_algorithm)
+                    || host_key.first().map(String::as_str) != Some(host_key_algorithm)),
+        })
+    }
+}
+
+fn kexinit(rng: &rand::SystemRandom) -> Result<Vec<u8>, io::Error> {
+    let mut cookie = [0u8; 16];
+    rng.fill(&mut cookie)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let kex = [KEX_ALGORITHMS, &[KEX_STRICT_SERVER]].concat();
+
+    Ok(wire::Writer::new(MSG_KEXINIT)
+        .bytes(&cookie)
+        .name_list(&kex)
+        .name_list(&[keys::HOST_KEY_ALGORITHM])
+        .name_list(CIPHERS)
+        .name_list(CIPHERS)
+        .name_list(MACS)
+        .name_list(MACS)
+        .name_list(&[COMPRESSION])
+        .name_list(&[COMPRESSION])
+        .name_list(&[])
+        .name_list(&[])
+        .bool(false)
+        .u32(0)
+        .finish())
+}
+
+// HASH(K || H || letter || session_id), extended with HASH(K || H ||
+// key so far) up to the expected length (RFC 4253 section 7.2)
+fn derive(secret: &[u8], hash: &[u8], letter: u8, session_id: &[u8], len: usize) -> Vec<u8> {
+    let mut key = digest::digest(
+        &digest::SHA256,
+        &[secret, hash, &[letter], session_id].concat(),
+    )
+    .as_ref()
+    .to_vec();
+    while key.len() < len {
+        let more = digest::digest(&digest::SHA256, &[secret, hash, &key].concat());
+        key.extend_from_slice(more.as_ref());
+    }
+    key.truncate(len);
+    key
+}
+
+struct WriterState {
+    stream: net::TcpStream,
+    sealing: Sealing,
+    sequence: u32,
+    // only key exchange messages may be sent
+    in_kex: bool,
+}
+
+// Sending side of the connection, shared by the threads of the
+// channels
+pub struct Writer {
+    state: Mutex<WriterState>,
+    kex_done: Condvar,
+    rng: rand::SystemRandom,
+}
+
+impl Writer {
+    fn write(&self, state: &mut WriterState, payload: &[u8]) -> Result<(), io::Error> {
+        let packet = state.sealing.seal(state.sequence, payload, &self.rng)?;
+        state.sequence = state.sequence.wrapping_add(1);
+        state.stream.write_all(&packet)
+    }
+
+    // Waits for the end of a key exchange in progress
+    pub fn send(&self, payload: &[u8]) -> Result<(), io::Error> {
+        let mut state = self
+            .kex_done
+            .wait_while(self.state.lock().unwrap(), |state| state.in_kex)
+            .unwrap();
+        self.write(&mut state, payload)
+    }
+
+    fn start_kex(&self, kexinit: &[u8]) -> Result<(), io::Error> {
+        let mut state = self.state.lock().unwrap();
+        state.in_kex = true;
+        self.write(&mut state, kexinit)
+    }
+
+    fn send_kex(&self, payload: &[u8]) -> Result<(), io::Error> {
+        let mut state = self.state.lock().unwrap();
+        self.write(&mut state, payload)
+    }
+
+    fn finish_kex(
+        &self,
+        sealing: Sealing,
+        strict: bool,
+        ext_info: Option<&[u8]>,
+    ) -> Result<(), io::Error> {
+        let mut state = self.state.lock().unwrap();
+        self.write(&mut state, &[MSG_NEWKEYS])?;
+        state.sealing = sealing;
+        if strict {
+            state.sequence = 0;
+        }
+        if let Some(ext_info) = ext_info {
+            self.write(&mut state, ext_info)?;
+        }
+        state.in_kex = false;
+        self.kex_done.notify_all();
+        Ok(())
+    }
+
+    pub fn disconnect(&self, reason: u32, description: &str) {
+        crate::debug!("disconnecting: {description}");
+        let payload = wire::Writer::new(MSG_DISCONNECT)
+            .u32(reason)
+            .string(description.as_bytes())
+            .string(b"")
+            .finish();
+        let mut state = self.state.lock().unwrap();
+        let _ = self.write(&mut state, &payload);
+        let _ = state.stream.shutdown(net::Shutdown::Both);
+    }
+}
+
+// Receiving side of the connection, which also runs the key exchanges
+pub struct Reader {
+    stream: io::BufReader<net::TcpStream>,
+    opening: Opening,
+    sequence: u32,
+    host_key: keys::HostKey,
+    client_version: Vec<u8>,
+    // the exchange hash of the first key exchange, empty before
+    session_id: Vec<u8>,
+    strict: bool,
+    rng: rand::SystemRandom,
+}
+
+impl Reader {
+    pub fn session_id(&self) -> &[u8] {
+        &self.session_id
+    }
+
+    // Sequence number of the last received packet, for
+    // SSH_MSG_UNIMPLEMENTED replies
+    pub const fn sequence(&self) -> u32 {
+        self.sequence.wrapping_sub(1)
+    }
+
+    // Also makes the writer fail
+    pub fn shutdown(&self) {
+        let _ = self.stream.get_ref().shutdown(net::Shutdown::Both);
+    }
+
+    fn read_packet(&mut self) -> Result<Vec<u8>, io::Error> {
+        let payload = self.opening.open(&mut self.stream, self.sequence)?;
+        self.sequence = self.sequence.wrapping_add(1);
+        if payload.is_empty() {
+            return Err(malformed());
+        }
+        Ok(payload)
+    }
+
+    // Only messages of the transport layer are allowed during a key
+    // exchange, none at all during the first one
+    fn receive_kex(&mut self, expected: u8) -> Result<Vec<u8>, io::Error> {
+        loop {
+            let payload = self.read_packet()?;
+            match payload[0] {
+                message if message == expected => return Ok(payload),
+                MSG_DISCONNECT => return Err(disconnected(&payload)),
+                MSG_IGNORE | MSG_DEBUG | MSG_UNIMPLEMENTED if !self.session_id.is_empty() => (),
+                message => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected message {message} during key exchange"),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn key_exchange(
+        &mut self,
+        writer: &Writer,
+        client_kexinit: Option<Vec<u8>>,
+    ) -> Result<(), io::Error> {
+        let first = self.session_id.is_empty();
+
+        let server_kexinit = kexinit(&self.rng)?;
+        writer.start_kex(&server_kexinit)?;
+
+        let client_kexinit = match client_kexinit {
+            Some(client_kexinit) => client_kexinit,
+            None => self.receive_kex(MSG_KEXINIT)?,
+        };
+
+        let algorithms = Algorithms::negotiate(&client_kexinit)?;
+        if first {
+            self.strict = algorithms.strict;
+        }
+        if algorithms.wrong_guess {
+            self.read_packet()?;
+        }
+
+        crate::debug!(
+            "ciphers {} and {}",
+            algorithms.cipher_client_to_server,
+            algorithms.cipher_server_to_client
+        );
+
+        let init = self.receive_kex(MSG_KEX_ECDH_INIT)?;
+        let client_public = wire::Reader::new(&init[1..]).string()?;
+
+        let private = agreement::EphemeralPrivateKey::generate(&agreement::X25519, &self.rng)
+            .map_err(|e| kex_failed(&e))?;
+        let server_public = private.compute_public_key().map_err(|e| kex_failed(&e))?;
+        // encoded as a mpint for the hash and the key derivation
+        let secret = agreement::agree_ephemeral(
+            private,
+            &agreement::UnparsedPublicKey::new(&agreement::X25519, client_public),
+            |secret| wire::Writer::raw().mpint(secret).finish(),
+        )
+        .map_err(|e| kex_failed(&e))?;
+
+        let host_key = self.host_key.blob();
+        let hash = digest::digest(
+            &digest::SHA256,
+            &wire::Writer::raw()
+                .string(&self.client_version)
+                .string(VERSION.as_bytes())
+                .string(&client_kexinit)
+                .string(&server_kexinit)
+                .string(&host_key)
+                .string(client_public)
+                .string(server_public.as_ref())
+                .bytes(&secret)
+                .finish(),
+        );
+        let hash = hash.as_ref();
+        if first {
+            self.session_id = hash.to_vec();
+        }
+
+        writer.send_kex(
+            &wire::Writer::new(MSG_KEX_ECDH_REPLY)
+                .string(&host_key)
+                .string(server_public.as_ref())
+                .string(&self.host_key.sign(hash))
+                .finish(),
+        )?;
+
+        let derive = |iv_letter, key_letter, (key_len, iv_len)| {
+            (
+                derive(&secret, hash, key_letter, &self.session_id, key_len),
+                derive(&secret, hash, iv_letter, &self.session_id, iv_len),
+            )
+        };
+
+        let cipher = algorithms.cipher_server_to_client;
+        let (key, iv) = derive(b'B', b'D', key_lengths(cipher));
+        let sealing = Sealing::new(cipher, &key, &iv)?;
+
+        let ext_info = (first && algorithms.ext_info).then(|| {
+            wire::Writer::new(MSG_EXT_INFO)
+                .u32(1)
+                .string(b"server-sig-algs")
+                .name_list(keys::USER_KEY_ALGORITHMS)
+                .finish()
+        });
+
+        writer.finish_kex(sealing, self.strict, ext_info.as_deref())?;
+
+        let cipher = algorithms.cipher_client_to_server;
+        let (key, iv) = derive(b'A', b'C', key_lengths(cipher));
+        self.receive_kex(MSG_NEWKEYS)?;
+        self.opening = Opening::new(cipher, &key, &iv)?;
+        if self.strict {
+            self.sequence = 0;
+        }
+
+        Ok(())
+    }
+
+    // Returns the next message above the transport layer, running the
+    // key exchanges initiated by the client
+    pub fn receive(&mut self, writer: &Writer) -> Result<Vec<u8>, io::Error> {
+        loop {
+            let payload = self.read_packet()?;
+            match payload[0] {
+                MSG_DISCONNECT => return Err(disconnected(&payload)),
+                MSG_IGNORE | MSG_DEBUG | MSG_UNIMPLEMENTED => (),
+                MSG_KEXINIT => self.key_exchange(writer, Some(payload))?,
+                _ => return Ok(payload),
+            }
+        }
+    }
+}
+
+fn read_version<R>(stream: &mut R) -> Result<Vec<u8>, io::Error>
+where
+    R: io::BufRead,
+{
+    let mut line = vec![];
+    stream
+        .by_ref()
+        .take(MAX_VERSION_LEN)
+        .read_until(b'\n', &mut line)?;
+    let Some(line) = line.strip_suffix(b"\n") else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid version",
+        ));
+    };
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if !(line.starts_with(b"SSH-2.0-") || line.starts_with(b"SSH-1.99-")) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported version",
+        ));
+    }
+    Ok(line.to_vec())
+}
+
+// Exchanges the versions and runs the first key exchange
+pub fn handshake(
+    stream: net::TcpStream,
+    host_key: keys::HostKey,
+) -> Result<(Reader, Writer), io::Error> {
+    let mut writer_stream = stream.try_clone()?;
+    writer_stream.write_all(format!("{VERSION}\r\n").as_bytes())?;
+
+    let mut stream = io::BufReader::new(stream);
+    let client_version = read_version(&mut stream)?;
+
+    crate::debug!(
+        "client version {}",
+        String::from_utf8_lossy(&client_version)
+    );
+
+    let writer = Writer {
+        state: Mutex::new(WriterState {
+            stream: writer_stream,
+            sealing: Sealing::None,
+            sequence: 0,
+            in_kex: false,
+        }),
+        kex_done: Condvar::new(),
+        rng: rand::SystemRandom::new(),
+    };
+
+    let mut reader = Reader {
+        stream,
+        opening: Opening::None,
+        sequence: 0,
+        host_key,
+        client_version,
+        session_id: vec![],
+        strict: false,
+        rng: rand::SystemRandom::new(),
+    };
+
+    reader.key_exchange(&writer, None)?;
+
+    Ok((reader, writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature;
+    use std::thread;
+
+    // Vectors computed with an independent implementation (Python
+    // cryptography)
+
+    const PAYLOAD: &[u8] = b"\x02\x00\x00\x00\x05hello";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn open(opening: &mut Opening, packet: &[u8], sequence: u32) -> Result<Vec<u8>, io::Error> {
+        opening.open(&mut &packet[..], sequence)
+    }
+
+    #[test]
+    fn key_derivation() {
+        let secret = wire::Writer::raw()
+            .mpint(&(0x80..0xa0).collect::<Vec<u8>>())
+            .finish();
+        let hash = (0..32).collect::<Vec<u8>>();
+        let session_id = (32..64).collect::<Vec<u8>>();
+        assert_eq!(
+            derive(&secret, &hash, b'C', &session_id, 64),
+            hex(
+                "f7485e21c18112aea6ec905802e1fd91d387c0ab2402942abdf7313ed923ed20\
+                 dcbc1eb0d3282d974f0b7a28ff6c2ab9357c7ed49493fe09fe5c5f528648e4fd"
+            )
+        );
+        assert_eq!(
+            derive(&secret, &hash, b'A', &session_id, 12),
+            hex("b98476b078bd018779fa3412")
+        );
+    }
+
+    #[test]
+    fn aes_gcm_packets() {
+        let iv = hex("22222222ffffffffffffffff");
+        for (cipher, key, packets) in [
+            (
+                AES128_GCM,
+                [0x11; 16].to_vec(),
+                [
+                    "00000010ae7b1d28ae74bdc4446086366320c99f3a687649e7b67c989848d32e221321be",
+                    "0000001086a782a0f47056302accd973a5ef8027ff86b2de7a7d341d2494c982d5d9b9dd",
+                ],
+            ),
+            (
+                AES256_GCM,
+                [0x11; 32].to_vec(),
+                [
+                    "00000010b861ea84a0693958bda5f60eea7e815328ff04c52df2b965f54326cff2fb0f7b",
+                    "00000010f906ebb8b1d3ea7e4613f9b71334a79a9c03d2460ad3eb804e227f6664b82581",
+                ],
+            ),
+        ] {
+            let mut opening = Opening::new(cipher, &key, &iv).unwrap();
+            // the invocation counter of the IV wraps
+            for (sequence, packet) in (0..).zip(packets) {
+                assert_eq!(open(&mut opening, &hex(packet), sequence).unwrap(), PAYLOAD);
+            }
+
+            let mut opening = Opening::new(cipher, &key, &iv).unwrap();
+            let mut packet = hex(packets[0]);
+            packet[10] ^= 1;
+            assert!(open(&mut opening, &packet, 0).is_err());
+        }
+    }
+
+    #[test]
+    fn chacha20_poly1305_packets() {
+        let key = (0..64).collect::<Vec<u8>>();
+        let packet =
+            hex("a39afcba2d4415434e86423b0001d4f0d78fd32c39bc16111d67a8b463bba33c22ede960");
+        let mut opening = Opening::new(CHACHA20_POLY1305, &key, &[]).unwrap();
+        assert_eq!(open(&mut opening, &packet, 7).unwrap(), PAYLOAD);
+        // the sequence number is the nonce
+        assert!(open(&mut opening, &packet, 8).is_err());
+    }
+
+    #[test]
+    fn packets() {
+        let rng = rand::SystemRandom::new();
+        for cipher in CIPHERS {
+            let (key_len, iv_len) = key_lengths(cipher);
+            let (key, iv) = (vec![1; key_len], vec![2; iv_len]);
+            let mut sealing = Sealing::new(cipher, &key, &iv).unwrap();
+            let mut opening = Opening::new(cipher, &key, &iv).unwrap();
+            for (sequence, len) in (0..).zip([0, 1, 15, 16, 17, 1000]) {
+                let payload = vec![3; len];
+                let packet = sealing.seal(sequence, &payload, &rng).unwrap();
+                let block_len = if *cipher == CHACHA20_POLY1305 { 8 } else { 16 };
+                assert_eq!((packet.len() - 4 - TAG_LEN) % block_len, 0);
+                assert_eq!(open(&mut opening, &packet, sequence).unwrap(), payload);
+            }
+        }
+
+        let mut packet = Sealing::None.seal(0, PAYLOAD, &rng).unwrap();
+        assert_eq!(open(&mut Opening::None, &packet, 0).unwrap(), PAYLOAD);
+        // larger than the maximum packet length
+        packet[..4].copy_from_slice(&[0, 0x10, 0, 0]);
+        assert!(open(&mut Opening::None, &packet, 0).is_err());
+    }
+
+    fn client_kexinit(kex: &[&str], ciphers: &[&str]) -> Vec<u8> {
+        wire::Writer::new(MSG_KEXINIT)
+            .bytes(&[0; 16])
+            .name_list(kex)
+            .name_list(&["ecdsa-sha2-nistp256", keys::HOST_KEY_ALGORITHM])
+            .name_list(ciphers)
+            .name_list(ciphers)
+            .name_list(MACS)
+            .name_list(MACS)
+            .name_list(&["none", "zlib@openssh.com"])
+            .name_list(&["none", "zlib@openssh.com"])
+            .name_list(&[])
+            .name_list(&[])
+            .bool(false)
+            .u32(0)
+            .finish()
+    }
+
+    #[test]
+    fn negotiation() {
+        let algorithms = Algorithms::negotiate(&client_kexinit(
+            &[
+                "sntrup761x25519-sha512",
+                "curve25519-sha256",
+                "ext-info-c",
+                KEX_STRICT_CLIENT,
+            ],
+            &["aes256-ctr", AES128_GCM, CHACHA20_POLY1305],
+        ))
+        .unwrap();
+        assert_eq!(algorithms.cipher_client_to_server, AES128_GCM);
+        assert!(algorithms.strict && algorithms.ext_info && !algorithms.wrong_guess);
+
+        assert!(
+            Algorithms::negotiate(&client_kexinit(&["diffie-hellman-group14-sha256"], CIPHERS))
+                .is_err()
+        );
+        assert!(Algorithms::negotiate(&client_kexinit(KEX_ALGORITHMS, &["aes256-ctr"])).is_err());
+    }
+
+    // Client side of a curve25519-sha256 key exchange (RFC 8731), then
+    // a message echoed by the server
+    fn key_exchange(cipher: &str, strict: bool) {
+        let rng = rand::SystemRandom::new();
+        let pkcs8 = signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let host_key = keys::HostKey::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let host_key_blob = host_key.blob();
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (mut reader, writer) = handshake(stream, host_key).unwrap();
+            let payload = reader.receive(&writer).unwrap();
+            writer.send(&payload).unwrap();
+        });
+
+        let stream = net::TcpStream::connect(addr).unwrap();
+        let mut output = stream.try_clone().unwrap();
+        let mut input = io::BufReader::new(stream);
+
+        let client_version = b"SSH-2.0-OpenSSH_9.9";
+        output.write_all(b"SSH-2.0-OpenSSH_9.9\r\n").unwrap();
+        let server_version = read_version(&mut input).unwrap();
+        assert_eq!(server_version, VERSION.as_bytes());
+
+        let kex = if strict {
+            vec!["curve25519-sha256", "ext-info-c", KEX_STRICT_CLIENT]
+        } else {
+            vec!["curve25519-sha256", "ext-info-c"]
+        };
+        let client_kexinit = client_kexinit(&kex, &[cipher]);
+        let private = agreement::EphemeralPrivateKey::generate(&agreement::X25519, &rng).unwrap();
+        let client_public = private.compute_public_key().unwrap();
+        let init = wire::Writer::new(MSG_KEX_ECDH_INIT)
+            .string(client_public.as_ref())
+            .finish();
+        for (sequence, payload) in (0..).zip([&client_kexinit, &init]) {
+            let packet = Sealing::None.seal(sequence, payload, &rng).unwrap();
+            output.write_all(&packet).unwrap();
+        }
+
+        let server_kexinit = Opening::None.open(&mut input, 0).unwrap();
+        assert_eq!(server_kexinit[0], MSG_KEXINIT);
+        let reply = Opening::None.open(&mut input, 1).unwrap();
+        assert_eq!(reply[0], MSG_KEX_ECDH_REPLY);
+        let mut reply = wire::Reader::new(&reply[1..]);
+        let (blob, server_public, signature_blob) = (
+            reply.string().unwrap(),
+            reply.string().unwrap(),
+            reply.string().unwrap(),
+        );
+        assert_eq!(blob, host_key_blob);
+
+        let secret = agreement::agree_ephemeral(
+            private,
+            &agreement::UnparsedPublicKey::new(&agreement::X25519, server_public),
+            |secret| wire::Writer::raw().mpint(secret).finish(),
+        )
+        .unwrap();
+        let hash = digest::digest(
+            &digest::SHA256,
+            &wire::Writer::raw()
+                .string(client_version)
+                .string(&server_version)
+                .string(&client_kexinit)
+                .string(&server_kexinit)
+                .string(blob)
+                .string(client_public.as_ref())
+                .string(server_public)
+                .bytes(&secret)
+                .finish(),
+        );
+        let hash = hash.as_ref();
+
+        let mut blob = wire::Reader::new(blob);
+        assert_eq!(blob.string().unwrap(), keys::HOST_KEY_ALGORITHM.as_bytes());
+        let mut signature_blob = wire::Reader::new(signature_blob);
+        assert_eq!(
+            signature_blob.string().unwrap(),
+            keys::HOST_KEY_ALGORITHM.as_bytes()
+        );
+        signature::UnparsedPublicKey::new(&signature::ED25519, blob.string().unwrap())
+            .verify(hash, signature_blob.string().unwrap())
+            .unwrap();
+
+        assert_eq!(Opening::None.open(&mut input, 2).unwrap(), [MSG_NEWKEYS]);
+        let packet = Sealing::None.seal(2, &[MSG_NEWKEYS], &rng).unwrap();
+        output.write_all(&packet).unwrap();
+
+        let lengths = key_lengths(cipher);
+        let keys = |iv_letter, key_letter| {
+            (
+                derive(&secret, hash, key_letter, hash, lengths.0),
+                derive(&secret, hash, iv_letter, hash, lengths.1),
+            )
+        };
+        let (key, iv) = keys(b'A', b'C');
+        let mut sealing = Sealing::new(cipher, &key, &iv).unwrap();
+        let (key, iv) = keys(b'B', b'D');
+        let mut opening = Opening::new(cipher, &key, &iv).unwrap();
+
+        // strict key exchanges reset the sequence numbers
+        let first = if strict { 0 } else { 3 };
+        let ext_info = opening.open(&mut input, first).unwrap();
+        assert_eq!(ext_info[0], MSG_EXT_INFO);
+
+        let payload = wire::Writer::new(80)
+            .string(b"keepalive@openssh.com")
+            .finish();
+        let packet = sealing.seal(first, &payload, &rng).unwrap();
+        output.write_all(&packet).unwrap();
+        assert_eq!(opening.open(&mut input, first + 1).unwrap(), payload);
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn key_exchanges() {
+        for cipher in CIPHERS {
+            key_exchange(cipher, true);
+            key_exchange(cipher, false);
+        }
+    }
+}
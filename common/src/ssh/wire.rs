@@ -0,0 +1,163 @@
+use std::io;
+
+// Data types of the SSH protocol (RFC 4251 section 5)
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed message")
+}
+
+pub struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub const fn new(buf: &'a [u8]) -> Self {
+        Self(buf)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], io::Error> {
+        if self.0.len() < len {
+            return Err(malformed());
+        }
+        let (res, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(res)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, io::Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, io::Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, io::Error> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, io::Error> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(buf))
+    }
+
+    pub fn string(&mut self) -> Result<&'a [u8], io::Error> {
+        let len = usize::try_from(self.u32()?).map_err(|_| malformed())?;
+        self.bytes(len)
+    }
+
+    pub fn utf8(&mut self) -> Result<String, io::Error> {
+        Ok(String::from_utf8_lossy(self.string()?).to_string())
+    }
+
+    pub fn name_list(&mut self) -> Result<Vec<String>, io::Error> {
+        let names = self.utf8()?;
+        Ok(names
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(ToString::to_string)
+            .collect())
+    }
+}
+
+pub struct Writer(Vec<u8>);
+
+impl Writer {
+    // Starts a message of the given type
+    pub fn new(message: u8) -> Self {
+        Self(vec![message])
+    }
+
+    pub const fn raw() -> Self {
+        Self(vec![])
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(u8::from(value))
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn string(&mut self, value: &[u8]) -> &mut Self {
+        // messages never come close to 4 GiB
+        self.u32(u32::try_from(value.len()).unwrap_or(u32::MAX));
+        self.bytes(value)
+    }
+
+    // Unsigned big-endian integer, without superfluous leading zero
+    pub fn mpint(&mut self, value: &[u8]) -> &mut Self {
+        let start = value.iter().position(|b| *b != 0).unwrap_or(value.len());
+        let value = &value[start..];
+        if value.first().is_some_and(|b| b & 0x80 != 0) {
+            self.u32(u32::try_from(value.len() + 1).unwrap_or(u32::MAX));
+            self.u8(0);
+            self.bytes(value)
+        } else {
+            self.string(value)
+        }
+    }
+
+    pub fn name_list(&mut self, names: &[&str]) -> &mut Self {
+        self.string(names.join(",").as_bytes())
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Examples of RFC 4251 section 5
+    #[test]
+    fn encoding() {
+        assert_eq!(
+            Writer::raw().string(b"testing").finish(),
+            b"\x00\x00\x00\x07testing"
+        );
+        assert_eq!(Writer::raw().mpint(&[]).finish(), [0, 0, 0, 0]);
+        assert_eq!(Writer::raw().mpint(&[0, 0]).finish(), [0, 0, 0, 0]);
+        assert_eq!(
+            Writer::raw()
+                .mpint(&[0x09, 0xa3, 0x78, 0xf9, 0xb2, 0xe3, 0x32, 0xa7])
+                .finish(),
+            [0, 0, 0, 8, 0x09, 0xa3, 0x78, 0xf9, 0xb2, 0xe3, 0x32, 0xa7]
+        );
+        assert_eq!(Writer::raw().mpint(&[0x80]).finish(), [0, 0, 0, 2, 0, 0x80]);
+        assert_eq!(Writer::raw().name_list(&[]).finish(), [0, 0, 0, 0]);
+        assert_eq!(
+            Writer::raw().name_list(&["zlib", "none"]).finish(),
+            b"\x00\x00\x00\x09zlib,none"
+        );
+    }
+
+    #[test]
+    fn decoding() {
+        let mut reader =
+            Reader::new(b"\x01\x00\x00\x00\x2a\x00\x00\x00\x09zlib,none\x00\x00\x00\x04ab");
+        assert!(reader.bool().unwrap());
+        assert_eq!(reader.u32().unwrap(), 42);
+        assert_eq!(reader.name_list().unwrap(), ["zlib", "none"]);
+        // truncated string
+        assert!(reader.string().is_err());
+    }
+}
@@ -112,3 +112,232 @@ where
     stream.read_exact(&mut buf)?;
     Ok(String::from_utf8_lossy(&buf).to_string())
 }
+
+#[cfg(any(feature = "service-clipboard", feature = "service-dns"))]
+type BytesLen = u64;
+
+#[cfg(any(feature = "service-clipboard", feature = "service-dns"))]
+pub fn serialize_bytes<W>(stream: &mut W, b: &[u8]) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    let len = b.len() as BytesLen;
+    stream.write_all(&len.to_le_bytes())?;
+
+    stream.write_all(b)?;
+
+    Ok(())
+}
+
+#[cfg(any(feature = "service-clipboard", feature = "service-dns"))]
+pub fn deserialize_bytes<R>(stream: &mut R) -> Result<Vec<u8>, io::Error>
+where
+    R: io::Read,
+{
+    let mut len = [0u8; 8];
+    stream.read_exact(&mut len)?;
+    let len = BytesLen::from_le_bytes(len);
+
+    let len = usize::try_from(len)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// Matches a string against a glob pattern where '*' matches any
+// sequence of characters and '?' any single character
+#[cfg(any(
+    all(
+        feature = "backend",
+        any(
+            feature = "service-archive",
+            feature = "service-dns",
+            feature = "service-forward",
+            feature = "service-ftp",
+            feature = "service-socks5"
+        )
+    ),
+    all(feature = "frontend", feature = "service-ssh")
+))]
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+
+    let (mut p, mut i) = (0, 0);
+    let mut backtrack = None;
+
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, i));
+            p += 1;
+        } else if let Some((bp, bi)) = backtrack {
+            p = bp + 1;
+            i = bi + 1;
+            backtrack = Some((bp, bi + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Comma separated programs allowed to be started, embedded at build
+// time, used when SOXY_COMMAND_ALLOW is not set
+#[cfg(all(
+    feature = "backend",
+    any(feature = "service-command", feature = "service-process")
+))]
+const COMMAND_ALLOW_EMBEDDED: Option<&str> = option_env!("SOXY_COMMAND_ALLOW");
+
+#[cfg(all(
+    feature = "backend",
+    any(feature = "service-command", feature = "service-process")
+))]
+pub fn command_allowlist() -> Option<String> {
+    std::env::var("SOXY_COMMAND_ALLOW")
+        .ok()
+        .or_else(|| COMMAND_ALLOW_EMBEDDED.map(ToString::to_string))
+        .filter(|allow| !allow.trim().is_empty())
+}
+
+// All programs are allowed unless an allowlist is set, in which case
+// the path must be absolute and one of its entries
+#[cfg(all(
+    feature = "backend",
+    any(feature = "service-command", feature = "service-process")
+))]
+pub fn is_command_allowed(path: &str) -> bool {
+    let Some(allow) = command_allowlist() else {
+        return true;
+    };
+    std::path::Path::new(path).is_absolute()
+        && allow.split(',').any(|allowed| allowed.trim() == path)
+}
+
+#[cfg(any(
+    all(
+        feature = "frontend",
+        any(feature = "service-http-proxy", feature = "service-ssh")
+    ),
+    all(
+        feature = "backend",
+        any(
+            feature = "service-dns",
+            feature = "service-forward",
+            feature = "service-socks5"
+        )
+    )
+))]
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[cfg(any(
+    all(
+        feature = "frontend",
+        any(feature = "service-http-proxy", feature = "service-ssh")
+    ),
+    all(
+        feature = "backend",
+        any(
+            feature = "service-dns",
+            feature = "service-forward",
+            feature = "service-socks5"
+        )
+    )
+))]
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(char::from(
+                    BASE64_ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize],
+                ));
+            } else {
+                res.push('=');
+            }
+        }
+    }
+
+    res
+}
+
+// Padding is optional, None if a character is not in the alphabet
+#[cfg(all(feature = "frontend", feature = "service-ssh"))]
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let mut res = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = BASE64_ALPHABET.iter().position(|b| *b == c)?;
+        acc = (acc << 6) | u32::try_from(value).ok()?;
+        bits += 6;
+        if 8 <= bits {
+            bits -= 8;
+            res.push(u8::try_from((acc >> bits) & 0xff).ok()?);
+        }
+    }
+    Some(res)
+}
+
+// Year, month, day, hour, minute and second (UTC) of a Unix time, from
+// http://howardhinnant.github.io/date_algorithms.html
+#[cfg(any(
+    all(
+        feature = "frontend",
+        any(
+            feature = "service-clipboard",
+            feature = "service-command",
+            feature = "service-ftp"
+        )
+    ),
+    all(feature = "backend", feature = "service-ftp")
+))]
+pub fn civil_from_unix(secs: i64) -> [i64; 6] {
+    let days = secs.div_euclid(86400);
+    let secs = secs.rem_euclid(86400);
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    [year, month, day, secs / 3600, (secs % 3600) / 60, secs % 60]
+}
+
+// Inverse of civil_from_unix
+#[cfg(any(
+    all(feature = "backend", feature = "service-ftp"),
+    all(feature = "frontend", feature = "service-ssh")
+))]
+pub fn unix_from_civil([year, month, day, hour, minute, second]: [i64; 6]) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if 2 < month { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    days * 86400 + hour * 3600 + minute * 60 + second
+}
@@ -1,4 +1,4 @@
-use common::service;
+use common::{frontend, service};
 use std::{
     env, fmt, fs,
     io::{self, Read, Write},
@@ -90,6 +90,31 @@ pub struct Service {
     pub ip: Option<String>,
     #[serde(default)]
     pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "toml::Table::is_empty")]
+    pub settings: toml::Table,
+}
+
+fn flatten_setting(settings: &mut frontend::FrontendSettings, key: String, value: &toml::Value) {
+    match value {
+        toml::Value::String(s) => settings.insert(key, s.clone()),
+        toml::Value::Array(values) => values
+            .iter()
+            .for_each(|value| flatten_setting(settings, key.clone(), value)),
+        toml::Value::Table(table) => table
+            .iter()
+            .for_each(|(k, value)| flatten_setting(settings, format!("{key}.{k}"), value)),
+        value => settings.insert(key, value.to_string()),
+    }
+}
+
+impl Service {
+    pub fn settings(&self) -> frontend::FrontendSettings {
+        let mut settings = frontend::FrontendSettings::default();
+        self.settings
+            .iter()
+            .for_each(|(key, value)| flatten_setting(&mut settings, key.clone(), value));
+        settings
+    }
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -113,6 +138,7 @@ fn default_services() -> Vec<Service> {
             enabled: true,
             ip: None,
             port: None,
+            settings: toml::Table::new(),
         })
         .collect()
 }
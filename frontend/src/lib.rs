@@ -84,7 +84,18 @@ fn bootstrap() -> Result<&'static config::Config, Error> {
     Ok(CONFIG.get_or_init(|| config))
 }
 
-#[allow(clippy::missing_panics_doc)]
+fn service_sockaddr(
+    config: &config::Config,
+    service_conf: &config::Service,
+    default_port: u16,
+) -> Result<net::SocketAddr, Error> {
+    let ip = net::IpAddr::from_str(&service_conf.ip.clone().unwrap_or(config.ip.clone()))
+        .map_err(|e| Error::Binding(e.to_string()))?;
+    let port = service_conf.port.unwrap_or(default_port);
+    Ok(net::SocketAddr::new(ip, port))
+}
+
+#[allow(clippy::missing_panics_doc, clippy::too_many_lines)]
 fn start_res(
     config: &config::Config,
     frontend_channel: channel::Channel,
@@ -105,15 +116,15 @@ fn start_res(
                     match service.frontend().and_then(frontend::Frontend::tcp) {
                         None => Ok::<_, Error>(servers),
                         Some(frontend::FrontendTcp { default_port, .. }) => {
-                            let ip = net::IpAddr::from_str(
-                                &service_conf.ip.clone().unwrap_or(config.ip.clone()),
+                            let sockaddr = service_sockaddr(config, service_conf, *default_port)?;
+
+                            let server = frontend::FrontendTcpServer::bind(
+                                service,
+                                sockaddr,
+                                None,
+                                service_conf.settings(),
                             )
                             .map_err(|e| Error::Binding(e.to_string()))?;
-                            let port = service_conf.port.unwrap_or(*default_port);
-                            let sockaddr = net::SocketAddr::new(ip, port);
-
-                            let server = frontend::FrontendTcpServer::bind(service, sockaddr, None)
-                                .map_err(|e| Error::Binding(e.to_string()))?;
 
                             servers.push(server);
 
@@ -125,6 +136,35 @@ fn start_res(
         },
     )?;
 
+    let udp_servers = config
+        .services
+        .iter()
+        .filter(|s| s.enabled)
+        .filter_map(|service_conf| {
+            service::lookup(service_conf.name.as_str())
+                .filter(|service| !service.internal())
+                .and_then(|service| {
+                    service
+                        .frontend()
+                        .and_then(frontend::Frontend::udp)
+                        .map(|frontend_udp| (service_conf, service, frontend_udp))
+                })
+        })
+        .try_fold(
+            vec![],
+            |mut servers, (service_conf, service, frontend::FrontendUdp { default_port, .. })| {
+                let sockaddr = service_sockaddr(config, service_conf, *default_port)?;
+
+                let server =
+                    frontend::FrontendUdpServer::bind(service, sockaddr, service_conf.settings())
+                        .map_err(|e| Error::Binding(e.to_string()))?;
+
+                servers.push(server);
+
+                Ok::<_, Error>(servers)
+            },
+        )?;
+
     #[cfg(not(feature = "service-forward"))]
     {
         if config.forward.as_ref().is_some_and(|v| !v.is_empty()) {
@@ -149,6 +189,7 @@ fn start_res(
                     &common::forward::SERVICE,
                     sockaddr,
                     Some(forward_conf.destination.clone()),
+                    frontend::FrontendSettings::default(),
                 )
                 .map_err(|e| Error::Binding(e.to_string()))?;
 
@@ -179,6 +220,19 @@ fn start_res(
                         .unwrap();
                 }
 
+                for server in &udp_servers {
+                    thread::Builder::new()
+                        .name(format!("{} udp", server.service().name()))
+                        .spawn_scoped(scope, || {
+                            if let Err(e) = server.start(&frontend_channel) {
+                                common::error!("{} error: {e}", server.service().name());
+                            } else {
+                                common::debug!("{} terminated", server.service().name());
+                            }
+                        })
+                        .unwrap();
+                }
+
                 if let Err(e) = frontend_channel.run(service::Kind::Frontend, &backend_to_frontend)
                 {
                     common::error!("frontend channel stopped: {e}");
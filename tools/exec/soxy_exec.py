@@ -0,0 +1,63 @@
+#!/usr/bin/env python3
+"""Runs a program through the exec mode of the soxy command service,
+forwarding the standard input, separating the standard output and
+error, and exiting with the exit code of the remote program."""
+
+import argparse
+import socket
+import sys
+import threading
+
+
+def forward_stdin(sock):
+    try:
+        while data := sys.stdin.buffer.read1(4096):
+            sock.sendall(data)
+        sock.shutdown(socket.SHUT_WR)
+    except OSError:
+        pass
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--host", default="127.0.0.1")
+    parser.add_argument("--port", type=int, default=3031)
+    parser.add_argument("--cwd")
+    parser.add_argument("--env", action="append", default=[], metavar="NAME=VALUE")
+    parser.add_argument("--timeout", type=int, metavar="SECONDS")
+    parser.add_argument("--no-stdin", action="store_true")
+    parser.add_argument("program")
+    parser.add_argument("args", nargs=argparse.REMAINDER)
+    options = parser.parse_args()
+
+    request = [f"program {options.program}"]
+    request += [f"arg {arg}" for arg in options.args]
+    request += [f"env {env}" for env in options.env]
+    if options.cwd is not None:
+        request.append(f"cwd {options.cwd}")
+    if options.timeout is not None:
+        request.append(f"timeout {options.timeout}")
+
+    sock = socket.create_connection((options.host, options.port))
+    sock.sendall(("\n".join(request) + "\n\n").encode())
+    if options.no_stdin:
+        sock.shutdown(socket.SHUT_WR)
+    else:
+        threading.Thread(target=forward_stdin, args=(sock,), daemon=True).start()
+
+    answer = sock.makefile("rb")
+    outputs = {b"stdout": sys.stdout.buffer, b"stderr": sys.stderr.buffer}
+    while line := answer.readline():
+        kind, _, value = line.rstrip(b"\n").partition(b" ")
+        if kind in outputs:
+            outputs[kind].write(answer.read(int(value)))
+            outputs[kind].flush()
+        elif kind == b"exit":
+            sys.exit(int(value))
+        else:
+            sys.exit(f"soxy_exec: {value.decode(errors='replace')}")
+    sys.exit("soxy_exec: connection closed")
+
+
+if __name__ == "__main__":
+    main()